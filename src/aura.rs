@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use chrono::Duration;
+
+use {Entry, AuraType};
+
+/// An aura the tracker thinks is up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActiveAura {
+    pub stacks: u64,
+    /// When it was applied (or first seen, if the apply was missed)
+    pub applied: Duration,
+    /// The last apply/refresh/stack change
+    pub refreshed: Duration,
+    pub buff: bool,
+}
+
+/// An aura that has gone, kept by trackers made `with_history`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PastAura<'a> {
+    pub target: &'a str,
+    pub source: &'a str,
+    pub spell: u32,
+    /// as it was when it went, so `stacks` is the last count seen
+    pub aura: ActiveAura,
+    pub removed: Duration,
+}
+
+/// Keeps track of the auras on each unit, by (target, source, spell),
+/// from the events passed to `update`. Queries answer for the time of
+/// the last event seen, apart from `auras_at`, which needs a tracker
+/// made `with_history` (otherwise callers have to replay the log up
+/// to the time they want).
+///
+/// COMBATANT_INFO replaces everything known about that player with
+/// its aura list, and deaths clear everything on the unit.
+#[derive(Debug, Clone)]
+pub struct AuraTracker<'a> {
+    // target id -> source id -> spell -> aura
+    auras: HashMap<&'a str, HashMap<&'a str, HashMap<u32, ActiveAura>>>,
+    now: Duration,
+    history: Option<Vec<PastAura<'a>>>,
+}
+
+impl<'a> Default for AuraTracker<'a> {
+    fn default() -> Self {
+        AuraTracker { auras: HashMap::new(), now: Duration::zero(), history: None }
+    }
+}
+
+impl<'a> AuraTracker<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// A tracker that also remembers every aura that has gone, for
+    /// `auras_at`
+    pub fn with_history() -> Self {
+        AuraTracker { history: Some(vec![]), ..Default::default() }
+    }
+
+    fn removed(&mut self, target: &'a str, source: &'a str, spell: u32, aura: ActiveAura) {
+        let now = self.now;
+        if let Some(ref mut history) = self.history {
+            history.push(PastAura { target, source, spell, aura, removed: now });
+        }
+    }
+
+    fn remove_target(&mut self, target: &'a str) {
+        if let Some(t) = self.auras.remove(target) {
+            for (src, s) in t {
+                for (spell, aura) in s {
+                    self.removed(target, src, spell, aura);
+                }
+            }
+        }
+    }
+
+    pub fn update(&mut self, log: &Entry<'a>) {
+        let now = log.timestamp();
+        self.now = now;
+        match *log {
+            Entry::Aura { ty, ref base, id, buff, amount, .. } => {
+                let source = self.auras.entry(base.dst.id).or_default()
+                    .entry(base.src.id).or_default();
+                if ty == AuraType::Remove {
+                    if let Some(aura) = source.remove(&id) {
+                        self.removed(base.dst.id, base.src.id, id, aura);
+                    }
+                    return;
+                }
+                let aura = source.entry(id).or_insert(ActiveAura { stacks: 1, applied: now, refreshed: now, buff });
+                aura.refreshed = now;
+                match ty {
+                    AuraType::Apply => {
+                        aura.applied = now;
+                        aura.stacks = 1;
+                    },
+                    AuraType::Stack | AuraType::Unstack => aura.stacks = amount.unwrap_or(aura.stacks),
+                    _ => (),
+                }
+            },
+            Entry::Info { id, ref auras, .. } => {
+                self.remove_target(id);
+                let target = self.auras.entry(id).or_default();
+                for &(src, aura) in auras {
+                    // the list doesn't say, but it's almost all buffs
+                    target.entry(src).or_default().insert(aura, ActiveAura { stacks: 1, applied: now, refreshed: now, buff: true });
+                }
+            },
+            Entry::Died { ref base } => {
+                self.remove_target(base.dst.id);
+            },
+            _ => (),
+        }
+    }
+
+    /// The time of the last event seen
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    pub fn get(&self, target: &str, source: &str, spell: u32) -> Option<&ActiveAura> {
+        self.auras.get(target).and_then(|t| t.get(source)).and_then(|s| s.get(&spell))
+    }
+
+    pub fn has(&self, target: &str, source: &str, spell: u32) -> bool {
+        self.get(target, source, spell).is_some()
+    }
+
+    /// Every aura on `target`, as (source id, spell, aura)
+    pub fn auras_on(&self, target: &str) -> Vec<(&'a str, u32, ActiveAura)> {
+        self.auras.get(target)
+            .map(|t| t.iter().flat_map(|(&src, s)| s.iter().map(move |(&spell, &aura)| (src, spell, aura))).collect())
+            .unwrap_or_default()
+    }
+
+    /// Every aura on `target` at `time`, as (source id, spell, aura),
+    /// for a tracker made `with_history`; others only know the auras
+    /// that are still up.
+    pub fn auras_at(&self, target: &str, time: Duration) -> Vec<(&'a str, u32, ActiveAura)> {
+        let mut ret = self.auras_on(target).into_iter()
+            .filter(|&(_, _, aura)| aura.applied <= time)
+            .collect::<Vec<_>>();
+        if let Some(ref history) = self.history {
+            ret.extend(history.iter()
+                       .filter(|p| p.target == target && p.aura.applied <= time && time < p.removed)
+                       .map(|p| (p.source, p.spell, p.aura)));
+        }
+        ret
+    }
+
+    /// Every aura being tracked, as (target id, source id, spell, aura)
+    pub fn all(&self) -> Vec<(&'a str, &'a str, u32, ActiveAura)> {
+        self.auras.iter().flat_map(|(&dst, t)| t.iter().flat_map(move |(&src, s)| {
+            s.iter().map(move |(&spell, &aura)| (dst, src, spell, aura))
+        })).collect()
+    }
+
+    /// How many of `spells` `source` has on `target`, eg the number
+    /// of a druid's HoTs on someone for mastery
+    pub fn count(&self, target: &str, source: &str, spells: &[u32]) -> usize {
+        self.auras.get(target).and_then(|t| t.get(source))
+            .map(|s| s.keys().filter(|spell| spells.contains(spell)).count())
+            .unwrap_or(0)
+    }
+
+    /// Total stacks of `spells` from `source` on `target`
+    pub fn stacks(&self, target: &str, source: &str, spells: &[u32]) -> u64 {
+        self.auras.get(target).and_then(|t| t.get(source))
+            .map(|s| s.iter().filter(|&(spell, _)| spells.contains(spell)).map(|(_, aura)| aura.stacks).sum())
+            .unwrap_or(0)
+    }
+
+    /// Forget everything, eg between encounters
+    pub fn clear(&mut self) {
+        let targets = self.auras.keys().cloned().collect::<Vec<_>>();
+        for target in targets {
+            self.remove_target(target);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use {Interner, iter};
+
+    static LOG: &str = r#"4/7 21:13:58.000  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:14:00.000  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:14:02.000  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,33763,"Lifebloom",0x8,BUFF
+4/7 21:14:10.000  SPELL_AURA_REMOVED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:14:20.000  UNIT_DIED,0000000000000000,nil,0x80000000,0x80000000,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0
+4/7 21:14:30.000  ENCOUNTER_END,2032,"Goroth",16,20,0
+"#;
+
+    fn spells_at(tracker: &AuraTracker, secs: i64) -> Vec<u32> {
+        let mut ret = tracker.auras_at("Player-3674-0AAAAAAA", Duration::seconds(secs))
+            .into_iter().map(|(_, spell, _)| spell).collect::<Vec<_>>();
+        ret.sort();
+        ret
+    }
+
+    #[test]
+    fn auras_at_past_times() {
+        let intern = Interner::default();
+        let mut tracker = AuraTracker::with_history();
+        for log in iter(&intern, LOG.as_bytes()) {
+            tracker.update(&log);
+        }
+        assert!(tracker.auras_on("Player-3674-0AAAAAAA").is_empty());
+        assert!(spells_at(&tracker, 1).is_empty());
+        assert_eq!(spells_at(&tracker, 3), vec![774]);
+        assert_eq!(spells_at(&tracker, 5), vec![774, 33763]);
+        assert_eq!(spells_at(&tracker, 15), vec![33763]);
+        assert!(spells_at(&tracker, 25).is_empty());
+    }
+
+    static STACKS: &str = r#"4/7 21:13:58.000  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,33763,"Lifebloom",0x8,BUFF
+4/7 21:13:59.000  COMBATANT_INFO,Player-3674-06D6B01E,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,11234,2468,2468,2468,1918,105,(18569,102351,0,197061,0,197073,155675),(0,0,0,0),[],[],[Player-3674-06D6B01E,774,Player-3674-0AAAAAAA,21562]
+4/7 21:14:00.000  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,242315,"Something",0x1,BUFF
+4/7 21:14:01.000  SPELL_AURA_APPLIED_DOSE,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,242315,"Something",0x1,BUFF,2
+4/7 21:14:02.000  SPELL_AURA_APPLIED_DOSE,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,242315,"Something",0x1,BUFF,3
+4/7 21:14:03.000  SPELL_AURA_REMOVED_DOSE,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,242315,"Something",0x1,BUFF,1
+4/7 21:14:04.000  UNIT_DIED,0000000000000000,nil,0x80000000,0x80000000,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0
+"#;
+
+    const DRUID: &str = "Player-3674-06D6B01E";
+
+    #[test]
+    fn info_stacks_and_deaths() {
+        let intern = Interner::default();
+        let mut logs = iter(&intern, STACKS.as_bytes());
+        let mut tracker = AuraTracker::with_history();
+        let mut step = |n: usize| {
+            for log in logs.by_ref().take(n) {
+                tracker.update(&log);
+            }
+            tracker.clone()
+        };
+
+        let t = step(1);
+        assert!(t.has(DRUID, DRUID, 33763));
+        // COMBATANT_INFO replaces the lifebloom with its own list
+        let t = step(1);
+        assert!(!t.has(DRUID, DRUID, 33763));
+        assert!(t.has(DRUID, DRUID, 774));
+        assert!(t.has(DRUID, "Player-3674-0AAAAAAA", 21562));
+        assert_eq!(t.auras_on(DRUID).len(), 2);
+        assert_eq!(t.get(DRUID, DRUID, 774).unwrap().applied, Duration::seconds(1));
+        assert_eq!(t.count(DRUID, DRUID, &[774, 33763, 242315]), 1);
+
+        let t = step(1);
+        assert_eq!(t.stacks(DRUID, DRUID, &[242315]), 1);
+        assert_eq!(t.count(DRUID, DRUID, &[774, 33763, 242315]), 2);
+        let t = step(2);
+        assert_eq!(t.stacks(DRUID, DRUID, &[242315]), 3);
+        assert_eq!(t.stacks(DRUID, DRUID, &[774, 242315]), 4);
+        let aura = *t.get(DRUID, DRUID, 242315).unwrap();
+        assert_eq!((aura.applied, aura.refreshed), (Duration::seconds(2), Duration::seconds(4)));
+        let t = step(1);
+        assert_eq!(t.stacks(DRUID, DRUID, &[242315]), 1);
+
+        let t = step(1);
+        assert!(t.auras_on(DRUID).is_empty());
+        assert_eq!(t.count(DRUID, DRUID, &[774, 242315]), 0);
+        // everything that went is in the history, with the time it went
+        let mut past = t.auras_at(DRUID, Duration::milliseconds(5500)).into_iter()
+            .map(|(src, spell, aura)| (src, spell, aura.stacks)).collect::<Vec<_>>();
+        past.sort();
+        assert_eq!(past, vec![("Player-3674-06D6B01E", 774, 1), ("Player-3674-06D6B01E", 242315, 1),
+                              ("Player-3674-0AAAAAAA", 21562, 1)]);
+        assert_eq!(spells_on(&t, DRUID, 500), vec![33763]);
+        assert_eq!(spells_on(&t, DRUID, 1500), vec![774, 21562]);
+        assert!(spells_on(&t, DRUID, 6000).is_empty());
+    }
+
+    #[test]
+    fn clear_keeps_history() {
+        let intern = Interner::default();
+        let mut tracker = AuraTracker::with_history();
+        for log in iter(&intern, STACKS.as_bytes()).take(3) {
+            tracker.update(&log);
+        }
+        tracker.clear();
+        assert!(tracker.all().is_empty());
+        assert_eq!(spells_on(&tracker, DRUID, 1500), vec![774, 21562]);
+    }
+
+    fn spells_on(tracker: &AuraTracker, target: &str, millis: i64) -> Vec<u32> {
+        let mut ret = tracker.auras_at(target, Duration::milliseconds(millis))
+            .into_iter().map(|(_, spell, _)| spell).collect::<Vec<_>>();
+        ret.sort();
+        ret
+    }
+}
@@ -0,0 +1,342 @@
+extern crate wow_combat_log;
+extern crate clap;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use clap::{Arg, App};
+use wow_combat_log::{Entry, Interner, Object};
+use wow_combat_log::unit::{self, Guid};
+
+#[derive(Debug, Default)]
+struct Anonymizer {
+    // (kind, original) -> pseudonym, kind is one of guid/name/realm/pet
+    map: HashMap<(String, String), String>,
+    counts: HashMap<String, usize>,
+    // guardians etc seen belonging to a player, so their guids are renamed too
+    owned: HashSet<String>,
+    pets: bool,
+}
+
+impl Anonymizer {
+    fn load(path: &Path, pets: bool) -> io::Result<Self> {
+        if !path.exists() {
+            return Ok(Anonymizer { pets, ..Default::default() });
+        }
+        Anonymizer::read(BufReader::new(File::open(path)?), pets)
+    }
+
+    /// Read a mapping written by `write`: kind, original and pseudonym
+    /// separated by tabs, one per line
+    fn read<R: BufRead>(read: R, pets: bool) -> io::Result<Self> {
+        let mut ret = Anonymizer { pets, ..Default::default() };
+        for (i, line) in read.lines().enumerate() {
+            let line = line?;
+            let mut fields = line.splitn(3, '\t');
+            let (kind, orig, new) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(kind), Some(orig), Some(new)) if !kind.is_empty() && !new.is_empty() => (kind, orig, new),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                               format!("line {}: expected kind, name and pseudonym separated by tabs", i + 1))),
+            };
+            *ret.counts.entry(kind.to_owned()).or_insert(0) += 1;
+            ret.map.insert((kind.to_owned(), orig.to_owned()), new.to_owned());
+        }
+        Ok(ret)
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out)?;
+        out.flush()
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut entries = self.map.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|&((kind, _), new)| (kind.clone(), new.clone()));
+        for ((kind, orig), new) in entries {
+            writeln!(out, "{}\t{}\t{}", kind, orig, new)?;
+        }
+        Ok(())
+    }
+
+    fn lookup<F: FnOnce(usize) -> String>(&mut self, kind: &str, orig: &str, make: F) -> String {
+        let key = (kind.to_owned(), orig.to_owned());
+        if let Some(new) = self.map.get(&key) {
+            return new.clone();
+        }
+        let count = self.counts.entry(kind.to_owned()).or_insert(0);
+        *count += 1;
+        let new = make(*count);
+        self.map.insert(key, new.clone());
+        new
+    }
+
+    fn guid(&mut self, guid: &str) -> String {
+        match unit::parse_guid(guid) {
+            Guid::Player { .. } => self.lookup("guid", guid, |n| format!("Player-0-{:08X}", n)),
+            // pets' guids are made from their owner's, so they always
+            // go; the kind and npc id are kept
+            Guid::Creature { kind, npc, .. } if kind == "Pet" || self.owned.contains(guid) =>
+                self.lookup("guid", guid, |n| format!("{}-0-0-0-0-{}-{:010X}", kind, npc, n)),
+            _ => guid.to_owned(),
+        }
+    }
+
+    fn own(&mut self, guid: &str, owner: &str) {
+        let is_player = |g| matches!(unit::parse_guid(g), Guid::Player { .. });
+        if is_player(owner) && !is_player(guid) && guid != "0000000000000000" {
+            self.owned.insert(guid.to_owned());
+        }
+    }
+
+    fn name(&mut self, guid: &str, name: &str) -> String {
+        if guid.starts_with("Player-") {
+            let realm = name.split_once('-').map(|(_, realm)| self.lookup("realm", realm, |n| format!("Realm{}", n)));
+            self.lookup("name", name, |n| match realm {
+                Some(realm) => format!("Player{}-{}", n, realm),
+                None => format!("Player{}", n),
+            })
+        } else if self.pets && guid.starts_with("Pet-") {
+            self.lookup("pet", name, |n| format!("Pet{}", n))
+        } else {
+            name.to_owned()
+        }
+    }
+
+    fn object<'a>(&mut self, intern: &'a Interner, obj: Object<'a>) -> Object<'a> {
+        Object {
+            name: intern.intern(&self.name(obj.id, obj.name)),
+            id: intern.intern(&self.guid(obj.id)),
+        }
+    }
+
+    fn entry<'a>(&mut self, intern: &'a Interner, mut log: Entry<'a>) -> Entry<'a> {
+        match log {
+            Entry::Aura { ref mut base, .. } | Entry::Died { ref mut base } => {
+                base.src = self.object(intern, base.src);
+                base.dst = self.object(intern, base.dst);
+            },
+            Entry::Heal { ref mut base, ref mut adv, .. } | Entry::Damage { ref mut base, ref mut adv, .. } => {
+                base.src = self.object(intern, base.src);
+                base.dst = self.object(intern, base.dst);
+                self.own(adv.id, adv.owner);
+                adv.id = intern.intern(&self.guid(adv.id));
+                adv.owner = intern.intern(&self.guid(adv.owner));
+            },
+            Entry::Info { ref mut id, ref mut auras, .. } => {
+                *id = intern.intern(&self.guid(id));
+                for aura in auras.iter_mut() {
+                    aura.0 = intern.intern(&self.guid(aura.0));
+                }
+            },
+            _ => (),
+        }
+        log
+    }
+
+    fn run<R: BufRead, W: Write>(&mut self, read: &mut R, out: &mut W) {
+        let intern = Interner::default();
+        let mut line = String::new();
+        let mut start = None;
+        while read.read_line(&mut line).unwrap() != 0 {
+            let start = *start.get_or_insert_with(|| wow_combat_log::parse_ts(&line).0);
+            match wow_combat_log::parse_line(&intern, &line, start) {
+                Entry::Unknown(..) => out.write_all(self.line(&line).as_bytes()).unwrap(),
+                log => {
+                    let log = self.entry(&intern, log);
+                    wow_combat_log::write_line(out, &log, start, wow_combat_log::line_ending(&line)).unwrap();
+                },
+            }
+            line.clear();
+        }
+    }
+
+    /// For events the parser doesn't understand: replace any player
+    /// (or pet, or owned) guid field, and the quoted name that follows a
+    /// guid. Summons mark what they summon as owned by the summoner.
+    fn line(&mut self, line: &str) -> String {
+        let mut ret = String::with_capacity(line.len());
+        let mut field_start = 0;
+        let mut in_quote = false;
+        let mut prev_guid: Option<&str> = None;
+        let mut fields = vec![];
+        for (i, c) in line.char_indices() {
+            match c {
+                '"' => in_quote = !in_quote,
+                ',' if !in_quote => {
+                    fields.push(&line[field_start..i]);
+                    field_start = i + 1;
+                },
+                _ => (),
+            }
+        }
+        fields.push(&line[field_start..]);
+        let ty = fields[0].split_once("  ").map(|x| x.1).unwrap_or("");
+        if (ty == "SPELL_SUMMON" || ty == "SPELL_CREATE") && fields.len() > 5 {
+            self.own(fields[5], fields[1]);
+        }
+
+        for (i, field) in fields.into_iter().enumerate() {
+            if i != 0 {
+                ret.push(',');
+            }
+            if let Some(guid) = prev_guid.take() {
+                if field.len() >= 2 && field.starts_with('"') && field.ends_with('"') {
+                    ret.push('"');
+                    ret.push_str(&self.name(guid, &field[1..field.len() - 1]));
+                    ret.push('"');
+                    continue;
+                }
+            }
+            if field.starts_with("Player-") || field.starts_with("Pet-") {
+                prev_guid = Some(field);
+            }
+            ret.push_str(&self.guid(field));
+        }
+        ret
+    }
+}
+
+fn main() {
+    let matches = App::new("anonymize")
+        .arg(Arg::with_name("File").required(true).help("Log file (- for stdin)"))
+        .arg(Arg::with_name("Output").short("o").long("output").takes_value(true).help("Output file (defaults to stdout)"))
+        .arg(Arg::with_name("Map").long("map").takes_value(true).help("File to load and save the name mapping in, to keep it stable across logs"))
+        .arg(Arg::with_name("Pets").long("pets").help("Also rename pets"))
+        .get_matches();
+
+    let input = matches.value_of("File").unwrap();
+    let stdin = io::stdin();
+    let mut read: Box<dyn BufRead> = if input == "-" {
+        Box::new(stdin.lock())
+    } else {
+        Box::new(BufReader::new(File::open(input).unwrap()))
+    };
+    let stdout = io::stdout();
+    let mut out: Box<dyn Write> = match matches.value_of("Output") {
+        Some(path) => Box::new(BufWriter::new(File::create(path).unwrap())),
+        None => Box::new(BufWriter::new(stdout.lock())),
+    };
+    let map_path = matches.value_of("Map").map(Path::new);
+    let pets = matches.is_present("Pets");
+    let mut anon = match map_path {
+        Some(path) => Anonymizer::load(path, pets).unwrap_or_else(|e| {
+            eprintln!("Can't read the map in {}: {}", path.display(), e);
+            std::process::exit(1);
+        }),
+        None => Anonymizer { pets, ..Default::default() },
+    };
+
+    anon.run(&mut read, &mut out);
+    out.flush().unwrap();
+
+    if let Some(path) = map_path {
+        anon.save(path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static LOG: &str = r#"4/7 21:13:58.541  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:58.600  COMBATANT_INFO,Player-3674-06D6B01E,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,11234,2468,2468,2468,1918,105,(18569,102351,0,197061,0,197073,155675),(0,0,0,0),[(128306,1,(),(),())],[(147143,915,(),(3562,1497),())],[Player-3674-06D6B01E,774,Player-3674-0AAAAAAA,21562]
+4/7 21:13:59.003  SPELL_HEAL,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,8936,"Regrowth",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,200000,50000,0,1
+4/7 21:13:59.100  SPELL_SUMMON,Player-3674-0BBBBBBB,"Hunty-Realm",0x512,0x0,Creature-0-3019-1676-22336-103673-00006814F1,"Darkglare",0xa28,0x0,205180,"Summon Darkglare",0x20
+4/7 21:13:59.200  SPELL_DAMAGE,Creature-0-3019-1676-22336-103673-00006814F1,"Darkglare",0x2112,0x0,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,205231,"Eye Beam",0x20,Creature-0-3019-1676-22336-103673-00006814F1,Player-3674-0BBBBBBB,100,100,0,0,0,0,0,0,6213.56,-842.09,0,40000,-1,32,0,0,0,nil,nil,nil,nil
+4/7 21:13:59.300  SWING_DAMAGE,Pet-0-3019-1676-22336-165189-0102FF1E2A,"Wolfie",0x1112,0x0,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,Pet-0-3019-1676-22336-165189-0102FF1E2A,Player-3674-0BBBBBBB,100,100,0,0,0,0,0,0,6213.56,-842.09,0,3000,-1,1,0,0,0,nil,nil,nil,nil
+4/7 21:13:59.400  SPELL_CAST_SUCCESS,Player-3674-0BBBBBBB,"Hunty-Realm",0x512,0x0,Pet-0-3019-1676-22336-165189-0102FF1E2A,"Wolfie",0x1112,0x0,136,"Mend Pet",0x8
+4/7 21:15:00.999  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    fn anonymize(log: &str, pets: bool) -> String {
+        anonymize_with(&mut Anonymizer { pets, ..Default::default() }, log)
+    }
+
+    fn anonymize_with(anon: &mut Anonymizer, log: &str) -> String {
+        let mut out = vec![];
+        anon.run(&mut log.as_bytes(), &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn renames_players_and_pets() {
+        let out = anonymize(LOG, true);
+        for orig in &["Player-3674-06D6B01E", "Player-3674-0BBBBBBB", "Druidy", "Tanky", "Hunty", "-Realm\"",
+                      "Pet-0-3019-1676-22336-165189-0102FF1E2A", "Wolfie", "103673-00006814F1"] {
+            assert!(!out.contains(orig), "{} is still in\n{}", orig, out);
+        }
+        // everything else is left alone, line for line
+        assert_eq!(out.lines().count(), LOG.lines().count());
+        assert!(out.contains("Creature-0-3019-1676-22336-115844-00006814F0,\"Goroth\""));
+        assert!(out.contains(",Pet-0-0-0-0-165189-"));
+        assert!(out.contains(",Creature-0-0-0-0-103673-"));
+    }
+
+    // each (original, pseudonym) pair of guids and names in the parsed logs
+    fn pairs<'a>(orig: &[Entry<'a>], anon: &[Entry<'a>]) -> Vec<(&'a str, &'a str)> {
+        let mut ret = vec![];
+        for (a, b) in orig.iter().zip(anon) {
+            if let (Some(a), Some(b)) = (a.base(), b.base()) {
+                ret.extend(&[(a.src.id, b.src.id), (a.src.name, b.src.name), (a.dst.id, b.dst.id), (a.dst.name, b.dst.name)]);
+            }
+            if let (Some(a), Some(b)) = (a.adv(), b.adv()) {
+                ret.extend(&[(a.id, b.id), (a.owner, b.owner)]);
+            }
+            if let (Entry::Info { id: a, auras: aa, .. }, Entry::Info { id: b, auras: ba, .. }) = (a, b) {
+                ret.push((*a, *b));
+                ret.extend(aa.iter().zip(ba).map(|(a, b)| (a.0, b.0)));
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn same_names_give_same_pseudonyms() {
+        let out = anonymize(LOG, true);
+        let intern = Interner::default();
+        let orig = wow_combat_log::iter(&intern, LOG.as_bytes()).collect::<Vec<_>>();
+        let anon = wow_combat_log::iter(&intern, out.as_bytes()).collect::<Vec<_>>();
+        assert_eq!(orig.len(), anon.len());
+        let mut forward = HashMap::new();
+        let mut back = HashMap::new();
+        for (a, b) in pairs(&orig, &anon) {
+            assert_eq!(*forward.entry(a).or_insert(b), b, "{} was renamed two ways", a);
+            assert_eq!(*back.entry(b).or_insert(a), a, "{} stands for two names", b);
+        }
+        assert_eq!(forward["Player-3674-06D6B01E"], "Player-0-00000001");
+        assert_eq!(forward["Druidy-Realm"], "Player1-Realm1");
+        assert_eq!(forward["Tanky-Realm"], "Player2-Realm1");
+        // and the lines the parser doesn't know use the same ones
+        let hunter = format!("{},\"Player3-Realm1\"", forward["Player-3674-0BBBBBBB"]);
+        let pet = format!("{},\"{}\"", forward["Pet-0-3019-1676-22336-165189-0102FF1E2A"], forward["Wolfie"]);
+        let summon = out.lines().find(|l| l.contains("SPELL_SUMMON")).unwrap();
+        let cast = out.lines().find(|l| l.contains("SPELL_CAST_SUCCESS")).unwrap();
+        assert!(summon.contains(&hunter), "{}", summon);
+        assert!(cast.contains(&hunter) && cast.contains(&pet), "{}", cast);
+    }
+
+    #[test]
+    fn map_round_trips() {
+        let mut anon = Anonymizer::default();
+        let out = anonymize_with(&mut anon, LOG);
+        let mut map = vec![];
+        anon.write(&mut map).unwrap();
+        let mut loaded = Anonymizer::read(&map[..], false).unwrap();
+        assert_eq!(loaded.map, anon.map);
+        assert_eq!(anonymize_with(&mut loaded, LOG), out);
+        // new players carry on from the loaded numbering
+        anonymize_with(&mut loaded, &LOG.replace("Tanky", "Healy"));
+        assert_eq!(loaded.map[&("name".to_owned(), "Healy-Realm".to_owned())], "Player4-Realm1");
+    }
+
+    #[test]
+    fn bad_map_lines_are_errors() {
+        for map in &["name\tDruidy-Realm\tPlayer1-Realm1\n\n", "name\tDruidy-Realm\tPlayer1-Realm1\nguid\tPlayer-3674-06D6B01E\n"] {
+            let err = Anonymizer::read(map.as_bytes(), false).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().starts_with("line 2:"), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+extern crate wow_combat_log;
+extern crate chrono;
+extern crate clap;
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use chrono::Duration;
+use clap::{Arg, App};
+use wow_combat_log::{Entry, EventSource, AuraTracker, ActiveAura};
+
+// Time during which at least one of a group of auras was up
+#[derive(Debug, Clone, Copy)]
+struct Uptime {
+    active: u32,
+    since: Duration,
+    total: Duration,
+}
+
+#[derive(Debug)]
+struct Union<K: Hash + Eq> {
+    map: HashMap<K, Uptime>,
+}
+
+impl<K: Hash + Eq + Clone> Union<K> {
+    fn new() -> Self {
+        Union { map: HashMap::new() }
+    }
+    fn up(&mut self, key: K, now: Duration) {
+        let u = self.map.entry(key).or_insert(Uptime { active: 0, since: now, total: Duration::zero() });
+        if u.active == 0 {
+            u.since = now;
+        }
+        u.active += 1;
+    }
+    fn down(&mut self, key: K, now: Duration) {
+        if let Some(u) = self.map.get_mut(&key) {
+            if u.active == 0 {
+                return;
+            }
+            u.active -= 1;
+            if u.active == 0 {
+                u.total = u.total + (now - u.since);
+            }
+        }
+    }
+    // Start counting from `now`, for auras that were already up when an encounter started
+    fn reset(&mut self, now: Duration) {
+        self.map.retain(|_, u| u.active != 0);
+        for u in self.map.values_mut() {
+            u.since = now;
+            u.total = Duration::zero();
+        }
+    }
+    fn total(&self, key: &K, now: Duration) -> Duration {
+        self.map.get(key).map(|u| if u.active != 0 { u.total + (now - u.since) } else { u.total }).unwrap_or(Duration::zero())
+    }
+}
+
+struct Filter<'m> {
+    auras: Option<Vec<&'m str>>,
+    sources: Option<Vec<&'m str>>,
+    targets: Option<Vec<&'m str>>,
+    buffs: bool,
+    debuffs: bool,
+}
+
+fn run(source: &EventSource, filter: &Filter) {
+    let intern = wow_combat_log::Interner::default();
+    let mut tracker = AuraTracker::new();
+    let mut names = HashMap::new();
+    let mut spells = HashMap::new();
+    let mut by_target = Union::new();
+    let mut by_source = Union::new();
+    let mut encounter = None;
+
+    let wanted = |names: &HashMap<&str, &str>, spells: &HashMap<u32, &str>, (dst, src, spell, aura): (&str, &str, u32, ActiveAura)| {
+        let name = |id| *names.get(id).unwrap_or(&id);
+        (if aura.buff { filter.buffs } else { filter.debuffs }) &&
+            filter.auras.as_ref().map(|a| a.iter().any(|&a| a == spell.to_string() || Some(&a) == spells.get(&spell))).unwrap_or(true) &&
+            filter.sources.as_ref().map(|s| s.contains(&name(src))).unwrap_or(true) &&
+            filter.targets.as_ref().map(|t| t.contains(&name(dst))).unwrap_or(true)
+    };
+
+    for log in source.open(&intern, None, false).unwrap() {
+        let now = log.timestamp();
+        if let Some(base) = log.base() {
+            names.insert(base.src.id, base.src.name);
+            names.insert(base.dst.id, base.dst.name);
+        }
+        let target = match log {
+            Entry::Aura { ref base, id, aura, .. } => {
+                spells.insert(id, aura);
+                Some(base.dst.id)
+            },
+            Entry::Died { ref base } => Some(base.dst.id),
+            Entry::Info { id, .. } => Some(id),
+            _ => None,
+        };
+
+        // see what changed on the target
+        let before = target.map(|t| tracker.auras_on(t)).unwrap_or_default();
+        tracker.update(&log);
+        if let Some(target) = target {
+            let before = before.into_iter().map(|(src, spell, aura)| (target, src, spell, aura))
+                .filter(|&x| wanted(&names, &spells, x)).map(|(_, src, spell, _)| (src, spell)).collect::<HashSet<_>>();
+            let after = tracker.auras_on(target).into_iter().map(|(src, spell, aura)| (target, src, spell, aura))
+                .filter(|&x| wanted(&names, &spells, x)).map(|(_, src, spell, _)| (src, spell)).collect::<HashSet<_>>();
+            for &(src, spell) in before.difference(&after) {
+                by_target.down((target, spell), now);
+                by_source.down((src, spell), now);
+            }
+            for &(src, spell) in after.difference(&before) {
+                by_target.up((target, spell), now);
+                by_source.up((src, spell), now);
+            }
+        }
+
+        match log {
+            Entry::EncounterStart { name, difficulty, .. } => {
+                encounter = Some((name, difficulty, now));
+                by_target.reset(now);
+                by_source.reset(now);
+            },
+            Entry::EncounterEnd { kill, .. } => {
+                if let Some((name, difficulty, start)) = encounter.take() {
+                    let duration = now - start;
+                    println!("{} ({}), kill: {}, duration: {}", name, difficulty, kill, duration.num_seconds());
+                    report(&by_target, &by_source, &names, &spells, duration, now);
+                    println!();
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+fn report<'a>(by_target: &Union<(&'a str, u32)>, by_source: &Union<(&'a str, u32)>,
+              names: &HashMap<&'a str, &'a str>, spells: &HashMap<u32, &'a str>, duration: Duration, now: Duration) {
+    let frac = |d: Duration| 100. * d.num_milliseconds() as f64 / duration.num_milliseconds().max(1) as f64;
+    let mut ids = by_target.map.keys().map(|&(_, spell)| spell).collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    for spell in ids {
+        println!("  {} ({})", spells.get(&spell).unwrap_or(&"?"), spell);
+        for &(title, union) in &[("target", by_target), ("source", by_source)] {
+            let mut rows = union.map.keys().filter(|k| k.1 == spell)
+                .map(|k| (names.get(k.0).unwrap_or(&k.0).to_string(), union.total(k, now)))
+                .filter(|&(_, d)| d > Duration::zero())
+                .collect::<Vec<_>>();
+            rows.sort_by_key(|&(_, d)| Reverse(d));
+            println!("    by {}:", title);
+            for (name, d) in rows {
+                println!("      {:24} {:5.1}%", name, frac(d));
+            }
+        }
+    }
+}
+
+fn main() {
+    let app = App::new("aura uptime");
+    let app = if cfg!(feature = "wcl") {
+        app.arg(Arg::with_name("API key").long("wcl").takes_value(true).help("warcraftlogs API key"))
+    } else {
+        app
+    };
+    let matches = app
+        .arg(Arg::with_name("File/WCL ID").required(true).help("Log file (- for stdin), binary log or WCL log ID"))
+        .arg(Arg::with_name("Aura").long("aura").takes_value(true).multiple(true).number_of_values(1)
+             .help("Aura id or name to report on (default all)"))
+        .arg(Arg::with_name("Source").long("source").takes_value(true).multiple(true).number_of_values(1)
+             .help("Only count auras from this source name"))
+        .arg(Arg::with_name("Target").long("target").takes_value(true).multiple(true).number_of_values(1)
+             .help("Only count auras on this target name"))
+        .arg(Arg::with_name("Type").long("type").takes_value(true).possible_values(&["buff", "debuff", "both"])
+             .default_value("both").help("Count buffs, debuffs or both"))
+        .get_matches();
+    let ty = matches.value_of("Type").unwrap();
+    let values = |name| matches.values_of(name).map(|v| v.collect::<Vec<_>>());
+    let filter = Filter {
+        auras: values("Aura"), sources: values("Source"), targets: values("Target"),
+        buffs: ty != "debuff", debuffs: ty != "buff",
+    };
+    let source = EventSource::new(matches.value_of("File/WCL ID").unwrap(), matches.value_of("API key"));
+    run(&source, &filter);
+}
@@ -1,12 +1,23 @@
 extern crate wow_combat_log;
+extern crate clap;
 
-use std::fs::File;
-use std::io::BufReader;
+use clap::{Arg, App};
+use wow_combat_log::EventSource;
 
 fn main() {
-    let read = BufReader::new(File::open(std::env::args().nth(1).unwrap()).unwrap());
+    let app = App::new("basic test");
+    let app = if cfg!(feature = "wcl") {
+        app.arg(Arg::with_name("API key").long("wcl").takes_value(true).help("warcraftlogs API key"))
+    } else {
+        app
+    };
+    let matches = app
+        .arg(Arg::with_name("File/WCL ID").required(true).help("Log file (- for stdin) or WCL log ID"))
+        .arg(Arg::with_name("Actor").long("actor").takes_value(true).help("Only show events from this source name"))
+        .get_matches();
+    let source = EventSource::new(matches.value_of("File/WCL ID").unwrap(), matches.value_of("API key"));
     let intern = wow_combat_log::Interner::default();
-    for log in wow_combat_log::iter(&intern, read) {
+    for log in source.open(&intern, matches.value_of("Actor"), false).unwrap() {
         println!("{:?}", log);
     }
 }
@@ -0,0 +1,52 @@
+extern crate wow_combat_log;
+extern crate chrono;
+extern crate clap;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use chrono::NaiveDateTime;
+use clap::{Arg, App};
+use wow_combat_log::{Entry, EventSource, StoreWriter};
+
+fn main() {
+    let app = App::new("convert log");
+    let app = if cfg!(feature = "wcl") {
+        app.arg(Arg::with_name("API key").long("wcl").takes_value(true).help("warcraftlogs API key"))
+    } else {
+        app
+    };
+    let matches = app
+        .arg(Arg::with_name("File/WCL ID").required(true).help("Log file (- for stdin), binary log or WCL log ID"))
+        .arg(Arg::with_name("Output").required(true).help("File to write"))
+        .arg(Arg::with_name("Text").long("text").help("Write a text combat log instead of a binary one"))
+        .get_matches();
+    let source = EventSource::new(matches.value_of("File/WCL ID").unwrap(), matches.value_of("API key"));
+    let intern = wow_combat_log::Interner::default();
+    let iter = source.open(&intern, None, false).unwrap();
+    // wcl reports don't have a start time, so they start at the epoch
+    let start = iter.start_time().unwrap_or_else(|| NaiveDateTime::from_timestamp(0, 0));
+    let eol = iter.line_ending();
+    let mut out = BufWriter::new(File::create(matches.value_of("Output").unwrap()).unwrap());
+
+    if matches.is_present("Text") {
+        let mut skipped = 0;
+        for log in iter {
+            // the parser doesn't keep enough of these to write them back
+            if let Entry::Unknown(..) = log {
+                skipped += 1;
+                continue;
+            }
+            wow_combat_log::write_line(&mut out, &log, start, eol).unwrap();
+        }
+        if skipped != 0 {
+            eprintln!("skipped {} events of unknown types", skipped);
+        }
+    } else {
+        let mut writer = StoreWriter::new(out, start).unwrap();
+        for log in iter {
+            writer.write(&log).unwrap();
+        }
+        out = writer.into_inner();
+    }
+    out.flush().unwrap();
+}
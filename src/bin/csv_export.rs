@@ -0,0 +1,369 @@
+extern crate wow_combat_log;
+extern crate chrono;
+extern crate clap;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use chrono::Duration;
+use clap::{Arg, App};
+use wow_combat_log::{Entry, EventSource, AuraType, DamageType, HealType, BaseInfo, AdvancedInfo};
+
+static COLUMNS: &[(&str, &str)] = &[
+    ("time", "seconds since the start of the encounter (empty outside encounters)"),
+    ("log_time", "seconds since the start of the log"),
+    ("encounter", "name of the current encounter"),
+    ("pull", "number of the current pull, from 1"),
+    ("event", "log event type"),
+    ("source", "source name"),
+    ("source_id", "source guid"),
+    ("source_flags", "source unit flags"),
+    ("source_flags2", "source raid flags"),
+    ("target", "target name"),
+    ("target_id", "target guid"),
+    ("target_flags", "target unit flags"),
+    ("target_flags2", "target raid flags"),
+    ("spell_id", "spell id"),
+    ("spell", "spell name"),
+    ("school", "spell school mask"),
+    ("periodic", "1 for periodic heals and damage"),
+    ("amount", "heal or damage amount, including overheal"),
+    ("overheal", "overhealing"),
+    ("overkill", "overkill (empty if the hit didn't kill)"),
+    ("resisted", "damage resisted"),
+    ("blocked", "damage blocked"),
+    ("absorbed", "amount absorbed"),
+    ("crit", "1 for crits"),
+    ("hp", "target hp after the event"),
+    ("maxhp", "target max hp"),
+    ("adv_id", "guid of the unit the advanced fields are about"),
+    ("owner", "owner guid of that unit"),
+    ("ap", "attack power"),
+    ("sp", "spell power"),
+    ("power_type", "power type"),
+    ("power", "current power"),
+    ("max_power", "max power"),
+    ("map_index", "map index"),
+    ("x", "x position"),
+    ("y", "y position"),
+    ("ilvl", "item level"),
+    ("buff", "1 for buffs, 0 for debuffs"),
+    ("aura_state", "applied, refreshed, removed, stacked or unstacked"),
+    ("stacks", "stack count after a stack/unstack"),
+    ("removed", "1 if the aura was removed"),
+    ];
+
+static DEFAULT_COLUMNS: &str = "time,encounter,event,source,target,spell_id,spell,amount,overheal,absorbed,crit";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind { Heal, Damage, Aura }
+
+fn event_kind(x: &str) -> Option<Kind> {
+    match x.trim() {
+        "heal" => Some(Kind::Heal),
+        "damage" => Some(Kind::Damage),
+        "aura" => Some(Kind::Aura),
+        _ => None,
+    }
+}
+
+fn valid_events(x: String) -> Result<(), String> {
+    match x.split(',').find(|k| event_kind(k).is_none()) {
+        Some(k) => Err(format!("unknown event kind {} (expected heal, damage or aura)", k)),
+        None => Ok(()),
+    }
+}
+
+fn valid_columns(x: String) -> Result<(), String> {
+    match x.split(',').map(|x| x.trim()).find(|col| !COLUMNS.iter().any(|&(name, _)| name == *col)) {
+        Some(col) => Err(format!("unknown column {} (see --list-columns)", col)),
+        None => Ok(()),
+    }
+}
+
+fn kind(log: &Entry) -> Option<Kind> {
+    match *log {
+        Entry::Heal { .. } => Some(Kind::Heal),
+        Entry::Damage { .. } => Some(Kind::Damage),
+        Entry::Aura { .. } => Some(Kind::Aura),
+        _ => None,
+    }
+}
+
+// What to export
+#[derive(Debug, Default)]
+struct Filter<'m> {
+    kinds: Vec<Kind>,
+    sources: Option<Vec<&'m str>>,
+    targets: Option<Vec<&'m str>>,
+    spells: Option<Vec<&'m str>>,
+    encounters: Option<Vec<&'m str>>,
+}
+
+// Where we are in the log
+#[derive(Debug, Default)]
+struct State<'a> {
+    encounter: Option<(&'a str, u32, Duration)>,
+    pull: u32,
+}
+
+fn csv_field(x: &str) -> String {
+    if x.contains(',') || x.contains('"') || x.contains('\n') {
+        format!("\"{}\"", x.replace('"', "\"\""))
+    } else {
+        x.to_owned()
+    }
+}
+
+fn secs(d: Duration) -> String {
+    format!("{:.3}", d.num_milliseconds() as f64 / 1000.)
+}
+
+fn bool_field(x: bool) -> String {
+    if x { "1" } else { "0" }.to_owned()
+}
+
+fn adv_field(adv: &AdvancedInfo, col: &str) -> String {
+    match col {
+        "adv_id" => adv.id.to_owned(),
+        "owner" => adv.owner.to_owned(),
+        "ap" => adv.ap.to_string(),
+        "sp" => adv.sp.to_string(),
+        "power_type" => adv.power_type.to_string(),
+        "power" => adv.power.to_string(),
+        "max_power" => adv.max_power.to_string(),
+        "map_index" => adv.map_index.to_string(),
+        "x" => format!("{:.2}", adv.x as f64 / 100.),
+        "y" => format!("{:.2}", adv.y as f64 / 100.),
+        "ilvl" => adv.ilvl.to_string(),
+        _ => String::new(),
+    }
+}
+
+fn base_field(base: &BaseInfo, col: &str) -> String {
+    match col {
+        "source" => base.src.name.to_owned(),
+        "source_id" => base.src.id.to_owned(),
+        "source_flags" => format!("0x{:x}", base.src_flags1),
+        "source_flags2" => format!("0x{:x}", base.src_flags2),
+        "target" => base.dst.name.to_owned(),
+        "target_id" => base.dst.id.to_owned(),
+        "target_flags" => format!("0x{:x}", base.dst_flags1),
+        "target_flags2" => format!("0x{:x}", base.dst_flags2),
+        _ => String::new(),
+    }
+}
+
+fn field(state: &State, log: &Entry, col: &str) -> String {
+    use wow_combat_log::Entry::*;
+    let ts = log.timestamp();
+    match col {
+        "time" => return state.encounter.map(|(_, _, start)| secs(ts - start)).unwrap_or_default(),
+        "log_time" => return secs(ts),
+        "encounter" => return state.encounter.map(|(name, _, _)| name.to_owned()).unwrap_or_default(),
+        "pull" => return state.encounter.map(|_| state.pull.to_string()).unwrap_or_default(),
+        "event" => return log.event_name().to_owned(),
+        _ => (),
+    }
+    if let Some(base) = log.base() {
+        if col.starts_with("source") || col.starts_with("target") {
+            return base_field(base, col);
+        }
+    }
+    match *log {
+        Heal { ty, id, spell, flags, hp, maxhp, heal, overheal, absorbed, crit, ref adv, .. } => match col {
+            "spell_id" => id.to_string(),
+            "spell" => spell.to_owned(),
+            "school" => flags.to_string(),
+            "periodic" => bool_field(ty == HealType::Periodic),
+            "amount" => heal.to_string(),
+            "overheal" => overheal.to_string(),
+            "absorbed" => absorbed.to_string(),
+            "crit" => bool_field(crit),
+            "hp" => hp.to_string(),
+            "maxhp" => maxhp.to_string(),
+            _ => adv_field(adv, col),
+        },
+        Damage { ty, id, spell, school, hp, maxhp, amount, overkill, resisted, blocked, absorbed, crit, ref adv, .. } => match col {
+            "spell_id" => id.to_string(),
+            "spell" => spell.to_owned(),
+            "school" => school.to_string(),
+            "periodic" => bool_field(ty == DamageType::Periodic),
+            "amount" => amount.to_string(),
+            "overkill" => if overkill >= 0 { overkill.to_string() } else { String::new() },
+            "resisted" => resisted.to_string(),
+            "blocked" => blocked.to_string(),
+            "absorbed" => absorbed.to_string(),
+            "crit" => bool_field(crit),
+            "hp" => hp.to_string(),
+            "maxhp" => maxhp.to_string(),
+            _ => adv_field(adv, col),
+        },
+        Aura { ty, id, aura, flags, buff, amount, .. } => match col {
+            "spell_id" => id.to_string(),
+            "spell" => aura.to_owned(),
+            "school" => flags.to_string(),
+            "buff" => bool_field(buff),
+            "aura_state" => match ty {
+                AuraType::Apply => "applied",
+                AuraType::Refresh => "refreshed",
+                AuraType::Remove => "removed",
+                AuraType::Stack => "stacked",
+                AuraType::Unstack => "unstacked",
+            }.to_owned(),
+            "stacks" => match ty {
+                AuraType::Stack | AuraType::Unstack => amount.map(|x| x.to_string()).unwrap_or_default(),
+                _ => String::new(),
+            },
+            "removed" => bool_field(ty == AuraType::Remove),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+/// Write the header and a row for each event `filter` lets through
+fn export<'a, I, W>(iter: I, out: &mut W, columns: &[&str], filter: &Filter) -> io::Result<()>
+    where I: Iterator<Item=Entry<'a>>, W: Write {
+    writeln!(out, "{}", columns.join(","))?;
+    let mut state = State::default();
+    for log in iter {
+        match log {
+            Entry::EncounterStart { name, id, ts, .. } => {
+                state.encounter = Some((name, id, ts));
+                state.pull += 1;
+            },
+            Entry::EncounterEnd { .. } => state.encounter = None,
+            _ => (),
+        }
+        if !kind(&log).map(|k| filter.kinds.contains(&k)).unwrap_or(false) {
+            continue;
+        }
+        if let Some(ref encounters) = filter.encounters {
+            match state.encounter {
+                Some((name, id, _)) if encounters.iter().any(|&e| e == name || e == id.to_string()) => (),
+                _ => continue,
+            }
+        }
+        let base = log.base().unwrap();
+        if filter.sources.as_ref().map(|s| !s.contains(&base.src.name)).unwrap_or(false) ||
+            filter.targets.as_ref().map(|t| !t.contains(&base.dst.name)).unwrap_or(false) {
+            continue;
+        }
+        if let Some(ref spells) = filter.spells {
+            let (id, name) = (field(&state, &log, "spell_id"), field(&state, &log, "spell"));
+            if !spells.iter().any(|&s| s == id || s == name) {
+                continue;
+            }
+        }
+
+        let row = columns.iter().map(|col| csv_field(&field(&state, &log, col))).collect::<Vec<_>>();
+        writeln!(out, "{}", row.join(","))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let app = App::new("csv export");
+    let app = if cfg!(feature = "wcl") {
+        app.arg(Arg::with_name("API key").long("wcl").takes_value(true).help("warcraftlogs API key"))
+    } else {
+        app
+    };
+    let matches = app
+        .arg(Arg::with_name("File/WCL ID").required(true).help("Log file (- for stdin), binary log or WCL log ID"))
+        .arg(Arg::with_name("Output").long("output").short("o").takes_value(true).help("File to write (default stdout)"))
+        .arg(Arg::with_name("Events").long("events").takes_value(true).default_value("heal,damage").validator(valid_events)
+             .help("Kinds of event to export: heal, damage, aura"))
+        .arg(Arg::with_name("Source").long("source").takes_value(true).multiple(true).number_of_values(1)
+             .help("Only export events from this source name"))
+        .arg(Arg::with_name("Target").long("target").takes_value(true).multiple(true).number_of_values(1)
+             .help("Only export events on this target name"))
+        .arg(Arg::with_name("Spell").long("spell").takes_value(true).multiple(true).number_of_values(1)
+             .help("Only export events for this spell id or name"))
+        .arg(Arg::with_name("Encounter").long("encounter").takes_value(true).multiple(true).number_of_values(1)
+             .help("Only export events during this encounter (name or id)"))
+        .arg(Arg::with_name("Columns").long("columns").takes_value(true).default_value(DEFAULT_COLUMNS).validator(valid_columns)
+             .help("Comma separated columns to write (see --list-columns)"))
+        .arg(Arg::with_name("List columns").long("list-columns").help("List the available columns"))
+        .get_matches();
+
+    if matches.is_present("List columns") {
+        for &(name, help) in COLUMNS {
+            println!("{:16}{}", name, help);
+        }
+        return;
+    }
+
+    let columns = matches.value_of("Columns").unwrap().split(',').map(|x| x.trim()).collect::<Vec<_>>();
+    let values = |name| matches.values_of(name).map(|v| v.collect::<Vec<_>>());
+    let filter = Filter {
+        kinds: matches.value_of("Events").unwrap().split(',').map(|x| event_kind(x).unwrap()).collect(),
+        sources: values("Source"), targets: values("Target"), spells: values("Spell"), encounters: values("Encounter"),
+    };
+
+    let out: Box<dyn Write> = match matches.value_of("Output") {
+        Some(path) => Box::new(File::create(path).unwrap()),
+        None => Box::new(io::stdout()),
+    };
+    let mut out = BufWriter::new(out);
+    let source = EventSource::new(matches.value_of("File/WCL ID").unwrap(), matches.value_of("API key"));
+    let intern = wow_combat_log::Interner::default();
+    export(source.open(&intern, None, false).unwrap(), &mut out, &columns, &filter).unwrap();
+    out.flush().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static LOG: &str = r#"4/7 21:13:50.000  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,33763,"Lifebloom",0x8,BUFF
+4/7 21:13:58.541  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:59.003  SPELL_HEAL,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,8936,"Regrowth",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,200000,50000,0,1
+4/7 21:13:59.200  SPELL_AURA_REMOVED_DOSE,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,242315,"Something",0x1,BUFF,2
+4/7 21:13:59.300  SPELL_AURA_REMOVED,0000000000000000,nil,0x80000000,0x80000000,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,1234,"Debuff, Nasty",0x20,DEBUFF
+4/7 21:14:20.500  SPELL_PERIODIC_DAMAGE,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,8921,"Moonfire",0x40,Creature-0-3019-1676-22336-115844-00006814F0,0000000000000000,89960000,100000000,0,0,0,0,0,0,6213.56,-842.09,0,12000,0,64,0,0,0,nil,nil,nil,nil
+4/7 21:15:00.999  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    fn export_log(columns: &str, filter: &Filter) -> String {
+        let intern = wow_combat_log::Interner::default();
+        let mut out = vec![];
+        let columns = columns.split(',').collect::<Vec<_>>();
+        export(wow_combat_log::iter(&intern, LOG.as_bytes()), &mut out, &columns, filter).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn one_row_per_event_kind() {
+        let filter = Filter { kinds: vec![Kind::Heal, Kind::Damage, Kind::Aura], ..Default::default() };
+        let columns = "time,log_time,pull,event,source,target,spell_id,spell,periodic,amount,overheal,overkill,crit,x,buff,aura_state,stacks,removed";
+        assert_eq!(export_log(columns, &filter), "\
+time,log_time,pull,event,source,target,spell_id,spell,periodic,amount,overheal,overkill,crit,x,buff,aura_state,stacks,removed
+,0.000,,SPELL_AURA_APPLIED,Druidy-Realm,Tanky-Realm,33763,Lifebloom,,,,,,,1,applied,,0
+0.462,9.003,1,SPELL_HEAL,Druidy-Realm,Tanky-Realm,8936,Regrowth,0,200000,50000,,1,-3437.90,,,,
+0.659,9.200,1,SPELL_AURA_REMOVED_DOSE,Druidy-Realm,Druidy-Realm,242315,Something,,,,,,,1,unstacked,2,0
+0.759,9.300,1,SPELL_AURA_REMOVED,nil,Druidy-Realm,1234,\"Debuff, Nasty\",,,,,,,0,removed,,1
+21.959,30.500,1,SPELL_PERIODIC_DAMAGE,Druidy-Realm,Goroth,8921,Moonfire,1,12000,,0,0,6213.56,,,,
+");
+        assert_eq!(export_log(DEFAULT_COLUMNS, &Filter::default()), format!("{}\n", DEFAULT_COLUMNS));
+    }
+
+    #[test]
+    fn filters() {
+        let filter = Filter {
+            kinds: vec![Kind::Heal, Kind::Damage, Kind::Aura], encounters: Some(vec!["2032"]), sources: Some(vec!["Druidy-Realm"]),
+            ..Default::default()
+        };
+        assert_eq!(export_log("event", &filter), "event\nSPELL_HEAL\nSPELL_AURA_REMOVED_DOSE\nSPELL_PERIODIC_DAMAGE\n");
+        let filter = Filter { kinds: vec![Kind::Aura], spells: Some(vec!["Lifebloom", "1234"]), ..Default::default() };
+        assert_eq!(export_log("spell_id", &filter), "spell_id\n33763\n1234\n");
+    }
+
+    #[test]
+    fn validates_arguments() {
+        assert!(valid_events("heal, aura".to_owned()).is_ok());
+        assert_eq!(valid_events("heal,cast".to_owned()), Err("unknown event kind cast (expected heal, damage or aura)".to_owned()));
+        assert!(valid_columns(DEFAULT_COLUMNS.to_owned()).is_ok());
+        assert_eq!(valid_columns("time,bogus".to_owned()), Err("unknown column bogus (see --list-columns)".to_owned()));
+    }
+}
@@ -0,0 +1,11 @@
+extern crate wow_combat_log;
+
+use wow_combat_log::Interner;
+use wow_combat_log::healer::HealerOptions;
+use wow_combat_log::disc_priest::DiscPriestComputation;
+
+fn main() {
+    let intern = Interner::default();
+    // unfiltered, for the damage of the priest's pets
+    HealerOptions::from_args("discipline atonement").run_analysis(&intern, true, DiscPriestComputation::new);
+}
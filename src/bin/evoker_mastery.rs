@@ -0,0 +1,10 @@
+extern crate wow_combat_log;
+
+use wow_combat_log::Interner;
+use wow_combat_log::healer::HealerOptions;
+use wow_combat_log::evoker::LifeBinder;
+
+fn main() {
+    let intern = Interner::default();
+    HealerOptions::from_args("evoker mastery").run::<LifeBinder>(&intern);
+}
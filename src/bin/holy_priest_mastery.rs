@@ -0,0 +1,10 @@
+extern crate wow_combat_log;
+
+use wow_combat_log::Interner;
+use wow_combat_log::healer::HealerOptions;
+use wow_combat_log::holy_priest::HolyPriestComputation;
+
+fn main() {
+    let intern = Interner::default();
+    HealerOptions::from_args("holy priest mastery").run_analysis(&intern, false, HolyPriestComputation::new);
+}
@@ -0,0 +1,40 @@
+extern crate wow_combat_log;
+extern crate clap;
+
+use std::path::Path;
+use clap::{Arg, App};
+use wow_combat_log::{EncounterIndex, Entry};
+
+fn main() {
+    let matches = App::new("index log")
+        .arg(Arg::with_name("File").required(true).help("Log file"))
+        .arg(Arg::with_name("Encounter").long("encounter").takes_value(true)
+             .help("Print the events of this encounter (numbered as in the listing) instead"))
+        .get_matches();
+    let log = Path::new(matches.value_of("File").unwrap());
+    let index = EncounterIndex::for_log(log).unwrap();
+
+    if let Some(n) = matches.value_of("Encounter") {
+        let entry = match n.parse::<usize>().ok().and_then(|n| index.encounters.get(n)) {
+            Some(entry) => entry,
+            None => {
+                eprintln!("No encounter {} in the log (there are {})", n, index.encounters.len());
+                std::process::exit(1);
+            },
+        };
+        let intern = wow_combat_log::Interner::default();
+        for log in index.iter_at(&intern, log, entry).unwrap() {
+            println!("{:?}", log);
+            if let Entry::EncounterEnd { .. } = log {
+                break;
+            }
+        }
+        return;
+    }
+
+    for (i, e) in index.encounters.iter().enumerate() {
+        let duration = e.end.map(|end| (end - e.start).num_seconds().to_string()).unwrap_or("?".to_owned());
+        println!("{}: {} ({}), start: {}, duration: {}, kill: {}, offset: {}",
+                 i, e.name, e.difficulty, e.start.num_seconds(), duration, e.kill, e.offset);
+    }
+}
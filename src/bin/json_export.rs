@@ -0,0 +1,30 @@
+extern crate wow_combat_log;
+extern crate clap;
+
+use std::io::{self, BufWriter, Write};
+use clap::{Arg, App};
+use wow_combat_log::EventSource;
+
+fn main() {
+    let app = App::new("json export");
+    let app = if cfg!(feature = "wcl") {
+        app.arg(Arg::with_name("API key").long("wcl").takes_value(true).help("warcraftlogs API key"))
+    } else {
+        app
+    };
+    let matches = app
+        .arg(Arg::with_name("File/WCL ID").required(true).help("Log file (- for stdin), binary log or WCL log ID"))
+        .arg(Arg::with_name("Actor").long("actor").takes_value(true).help("Only export events cast by this name"))
+        .get_matches();
+    let source = EventSource::new(matches.value_of("File/WCL ID").unwrap(), matches.value_of("API key"));
+    let intern = wow_combat_log::Interner::default();
+    let iter = source.open(&intern, matches.value_of("Actor"), false).unwrap();
+    let start = iter.start_time();
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    for log in iter {
+        wow_combat_log::write_json_line(&mut out, &log, start).unwrap();
+    }
+    out.flush().unwrap();
+}
@@ -1,27 +1,28 @@
 extern crate wow_combat_log;
 extern crate chrono;
+extern crate clap;
 
-use std::fs::File;
-use std::io::BufReader;
 use std::collections::HashMap;
 use std::fmt;
 use chrono::Duration;
-
-static REJUV_AURAS: &'static [u32] = &[
-    774, // Rejuv
-    155777, // Rejuv (Germ)
-    ];
+use clap::{Arg, App};
+use std::path::Path;
+use wow_combat_log::{EventSource, SpellDb};
+use wow_combat_log::resto_druid::DruidSpells;
 
 #[derive(Debug, Clone, Default)]
 struct RestoComputation<'a> {
     map: HashMap<&'a str, [usize; 2]>,
     histo: [(u64, u64, u64); 32],
     player: &'a str, 
+    rejuv: Vec<u32>,
+    // of `rejuv`, the ones that go in the second column
+    germination: Vec<u32>,
 }
 
 impl<'a> RestoComputation<'a> {
-    fn new(player: &'a str) -> Self {
-        RestoComputation {player: player, .. Default::default() }
+    fn new(player: &'a str, spells: DruidSpells) -> Self {
+        RestoComputation {player, rejuv: spells.rejuv_auras, germination: spells.germination, .. Default::default() }
     }
 
     fn reset_stats(&mut self) {
@@ -41,17 +42,17 @@ impl<'a> RestoComputation<'a> {
         }
         let entry = self.map.entry(log.base().unwrap().dst.id).or_insert([0, 0]);
         match *log {
-            Aura { ty, id, .. } if REJUV_AURAS.contains(&id) && ty != Remove => {
-                let i = if id == REJUV_AURAS[0] { 0 } else { 1 };
+            Aura { ty, id, .. } if self.rejuv.contains(&id) && ty != Remove => {
+                let i = if self.germination.contains(&id) { 1 } else { 0 };
                 entry[i] = 0;
             },
 
-            Heal { id, heal: total_heal, overheal, .. } if REJUV_AURAS.contains(&id) => {
+            Heal { id, heal: total_heal, overheal, .. } if self.rejuv.contains(&id) => {
                 if log.timestamp() < filter_start_time {
                     return;
                 }
 
-                let i = if id == REJUV_AURAS[0] { 0 } else { 1 };
+                let i = if self.germination.contains(&id) { 1 } else { 0 };
                 self.histo[entry[i]].0 += overheal;
                 self.histo[entry[i]].1 += total_heal;
                 self.histo[entry[i]].2 += 1;
@@ -89,16 +90,32 @@ impl<'a, 'b, 'c> std::ops::SubAssign<&'b RestoComputation<'c>> for RestoComputat
 }
 
 fn main() {
-    let read = BufReader::new(File::open(std::env::args().nth(1).unwrap()).unwrap());
-    let player = std::env::args().nth(2).unwrap();
+    let app = App::new("rejuv histogram overheal");
+    let app = if cfg!(feature = "wcl") {
+        app.arg(Arg::with_name("API key").long("wcl").takes_value(true).help("warcraftlogs API key"))
+    } else {
+        app
+    };
+    let matches = app
+        .arg(Arg::with_name("File/WCL ID").required(true).help("Log file (- for stdin) or WCL log ID"))
+        .arg(Arg::with_name("Player").required(true).help("Player name (as reported in log)"))
+        .arg(Arg::with_name("Start").help("Start time in seconds from start of log"))
+        .arg(Arg::with_name("End").help("End time in seconds from start of log"))
+        .arg(Arg::with_name("Spells").long("spells").takes_value(true)
+             .help("Spell database entries to add to or replace the built-in ones"))
+        .get_matches();
+    let player = matches.value_of("Player").unwrap();
     let intern = wow_combat_log::Interner::default();
-    let start = std::env::args().nth(3).map(|x| Duration::seconds(x.parse().unwrap())).unwrap_or(Duration::zero());
-    let end = std::env::args().nth(4).map(|x| Duration::seconds(x.parse().unwrap())).unwrap_or(Duration::max_value());
+    let start = matches.value_of("Start").map(|x| Duration::seconds(x.parse().unwrap())).unwrap_or(Duration::zero());
+    let end = matches.value_of("End").map(|x| Duration::seconds(x.parse().unwrap())).unwrap_or(Duration::max_value());
+    let source = EventSource::new(matches.value_of("File/WCL ID").unwrap(), matches.value_of("API key"));
+
+    let db = SpellDb::with_overrides(matches.value_of("Spells").map(Path::new)).unwrap();
 
-    let iter = wow_combat_log::iter(&intern, read);
+    let iter = source.open(&intern, Some(player), false).unwrap();
     let iter = iter.take_while(|x| x.timestamp() < end);
     let mut encounter_start = None;
-    let mut total = RestoComputation::new(&player);
+    let mut total = RestoComputation::new(player, DruidSpells::new(&db));
     let mut encounter = total.clone();
     let mut kills = total.clone();
     let mut bosses = total.clone();
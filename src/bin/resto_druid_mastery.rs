@@ -2,221 +2,335 @@ extern crate wow_combat_log;
 extern crate chrono;
 extern crate clap;
 
-use std::fs::File;
-use std::io::BufReader;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
 use chrono::Duration;
-use clap::{Arg, App};
-use wow_combat_log::Entry;
-
-static MASTERY_AURAS: &'static [u32] = &[
-    33763, // Lifebloom
-    774, // Rejuv
-    155777, // Rejuv (Germ)
-    8936, // Regrowth,
-    48438, // WG
-    207386, // Spring Blossoms
-    200389, // Cultivation
-    102352, // Cenarion Ward
-    22842, // Frenzied Regen (no really, it counts)
-    ];
-
-static MASTERY_NAMES: &'static [(u32, &'static str)] = &[
-    (33763, "LB"),
-    (774, "Rejuv"),
-    (155777, "Germ"),
-    (8936, "Regrowth"),
-    (48438, "WG"),
-    (207386, "SB"),
-    (200389, "Cult"),
-    (102352, "CW"),
-    (22842, "Frenzied"),
-    ];
+use clap::Arg;
+use wow_combat_log::report::Analysis;
+use wow_combat_log::resto_druid::{SPEC_RESTO_DRUID, DruidSpells, Harmony};
+use wow_combat_log::{Entry, HealType, AuraTracker, Roster, LevelStats, Stat, BuffTable, CritTable, Item};
+use wow_combat_log::healer::{MasteryModel, HealerComputation, HealerOptions};
 
-// not renewal(108238), not ysera's gift(145109/10), not trinkets
-//
-// Living seed is not itself affected by mastery, but the heal its
-// strength is based on _is_. The ideal computation would be to use
-// the mastery stacks (and rating) from when the heal was created, but
-// to use the overheal/etc values that we only know when it goes off.
-static OTHER_HEALS: &'static [u32] = &[
-    157982, // Tranq
+const SPELL_LIVING_SEED: u32 = 48503;
+const LIVING_SEED_DURATION_MS: i64 = 15000;
+const AURA_2PC: u32 = 232378;
+// heals that don't happen more often with haste
+static HASTE_FIXED: &[u32] = &[
     18562, // Swiftmend
     33778, // Lifebloom bloom
-    5185, // HT
-    81269, // Efflo
-    189800, // Nature's Essence (WG insta heal)
-    189853, // Dreamwalker
-    48503, // Living seed
     ];
 
-static REJUV_AURAS: &'static [u32] = &[
-    774, // Rejuv
-    155777, // Rejuv (Germ)
+static WEIGHT_STATS: &[(Stat, &str)] = &[
+    (Stat::Intellect, "int"),
+    (Stat::Crit, "crit"),
+    (Stat::Haste, "haste"),
+    (Stat::Mastery, "mastery"),
+    (Stat::Versatility, "vers"),
     ];
 
-static LIVING_SEED_HEALS: &'static [u32] = &[5185, 8936, 18562];
-const AURA_2PC: u32 = 232378;
 const SPELL_REGROWTH: u32 = 8936;
 const SPELL_TRANQ: u32 = 157982;
-const MASTERY_2PC: u32 = 4000;
+const SPELL_LEECH: u32 = 143924;
 
-pub fn find_init_mastery<'a, I: Iterator<Item=Entry<'a>>>(iter: I, player: &str) -> Option<(&'a str, u32)> {
-    let mut map = HashMap::new();
-    let mut player_id = None;
-    for log in iter {
-        if player_id.is_none() {
-            if let Some(base) = log.base() {
-                let id;
-                if base.src.name == player {
-                    id = base.src.id;
-                } else if base.dst.name == player {
-                    id = base.dst.id;
-                } else {
-                    continue;
-                }
-                if let Some(v) = map.get(id) {
-                    return Some((id, *v));
-                }
-                player_id = Some(id);
-            }
-        }
-        match log {
-            wow_combat_log::Entry::Info { id, mastery, .. } => {
-                if let Some(pid) = player_id {
-                    if pid == id {
-                        return Some((pid, mastery));
-                    } else {
-                        continue
-                    }
-                }
-                map.entry(id).or_insert(mastery);
-            },
-            _ => (),
-        }
-    }
-    //None
-    Some((player_id.unwrap(), 8773))
+// What mastery did to a heal
+#[derive(Debug, Clone)]
+struct MasteryState {
+    stacks: usize,
+    // as a fraction
+    mastery: f64,
+    // mastery without the 2pc, if it was up
+    without_2pc: Option<f64>,
+    // the player's HoTs on the target
+    hots: Vec<u32>,
+}
+
+// A living seed, with the mastery of the crit that planted it
+#[derive(Debug, Clone)]
+struct Seed {
+    planted: Duration,
+    state: MasteryState,
 }
 
+// Healing and damage done since the last leech heal, which is what it
+// came from
+#[derive(Debug, Clone, Default)]
+struct LeechSource {
+    healing: u64,
+    damage: u64,
+    // the no-mastery part of it, and that weighted by stacks, as for
+    // total_unmastery_healing and mastery_healing
+    unmast: u64,
+    stacks_unmast: u64,
+    // as for stat_values
+    stat_values: [f64; 5],
+}
 
+/// The data tables an analysis uses
+#[derive(Default, Debug)]
+pub struct Tables {
+    buffs: Rc<BuffTable>,
+    crit: CritTable,
+    spells: DruidSpells,
+    harmony: Harmony,
+    // player name -> race, from --race
+    races: HashMap<String, String>,
+}
+
+/// Harmony, with the rest of what the analyzer counts: living seeds,
+/// leech, the 2pc and stat weights
 #[derive(Default, Debug, Clone)]
-pub struct RestoComputation<'a> {
-    map: HashMap<&'a str, (HashSet<u32>, Duration)>,
-    total_healing: u64,
-    total_unmastery_healing: u64,
+pub struct Resto<'a> {
     total_uncrit_healing: u64,
-    mastery_healing: u64,
     living_seed_healing: u64,
     regrowth_healing: u64,
     tranq_healing: u64,
     rejuv_healing: u64,
+    leech_healing: u64,
+    // healing per point of each of WEIGHT_STATS
+    stat_values: [f64; 5],
     healing_2pc: u64,
     healing_2pc_added: u64,
-    under_2pc: bool,
-    player_id: &'a str,
-    cur_mastery: u32,
+    // ratings without the buffs in `tables.buffs`
+    ratings: HashMap<Stat, u32>,
+    // from --race, once the player's name has been seen
+    race: Option<String>,
+    // from the last COMBATANT_INFO, for the crit multiplier
+    gear: Vec<Item>,
+    info_auras: Vec<u32>,
+    // a crit heal's size relative to a normal one
+    crit_mult: f64,
+    // at the player's level
+    stats: Rc<LevelStats>,
+    tables: Rc<Tables>,
+    // target -> their living seed
+    seeds: HashMap<&'a str, Seed>,
+    leech_source: LeechSource,
+    // what mastery did to the heal being counted, from heal_mastery
+    state: Option<MasteryState>,
     total_healing_per: [u64; 14],
     total_healing_per_unmast: [u64; 14],
     hot_mastery_healing_added: HashMap<u32, u64>,
 }
 
-impl<'a> RestoComputation<'a> {
-    pub fn new(player_id: &'a str, starting_mastery: u32) -> Self {
-        RestoComputation {
-            player_id: player_id, cur_mastery: starting_mastery,
-            ..Default::default()
+pub type RestoComputation<'a> = HealerComputation<'a, Resto<'a>>;
+
+impl<'a> Resto<'a> {
+    pub fn new(tables: &Rc<Tables>, stats: Rc<LevelStats>) -> Self {
+        Resto { tables: tables.clone(), stats, ..Default::default() }
+    }
+
+    // The player's auras that are in `tables.buffs`, as (id, stacks)
+    fn player_buffs(&self, auras: &AuraTracker<'a>, player_id: &str) -> Vec<(u32, u64)> {
+        auras.auras_on(player_id).into_iter()
+            .filter(|&(_, spell, _)| self.tables.buffs.contains(spell))
+            .map(|(_, spell, aura)| (spell, aura.stacks))
+            .collect()
+    }
+
+    fn update_crit(&mut self) {
+        let crit = &self.tables.crit;
+        let race = self.race.as_ref().map(|r| &r[..]).or_else(|| crit.race_from_auras(&self.info_auras));
+        self.crit_mult = crit.multiplier(race, &self.gear);
+    }
+
+    // (%, % per point of rating) of `stat` with the buffs in `auras`
+    fn stat_percent(&self, stat: Stat, auras: &[(u32, u64)]) -> (f64, f64) {
+        let (buffs, stats) = (&self.tables.buffs, &self.stats);
+        let rating = buffs.rating(stat, self.ratings.get(&stat).cloned().unwrap_or(0), auras.iter().cloned());
+        let (_, percent) = buffs.totals(stat, auras.iter().cloned());
+        (stats.buffed_percent(stat, rating, &percent), stats.marginal(stat, rating, &percent))
+    }
+
+    // mastery % (as a fraction) with the buffs in `auras`
+    fn mastery_with(&self, auras: &[(u32, u64)]) -> f64 {
+        self.stats.mastery[&SPEC_RESTO_DRUID] * self.stat_percent(Stat::Mastery, auras).0 / 100.
+    }
+
+    // How much more a heal would have done per point of each of
+    // WEIGHT_STATS, with the player's buffs `buffs`
+    fn stat_values(&self, buffs: &[(u32, u64)], id: u32, ty: HealType, heal: u64, uncrit_heal: u64, stacks_unmast: u64) -> [f64; 5] {
+        let int = self.tables.buffs.rating(Stat::Intellect, self.ratings.get(&Stat::Intellect).cloned().unwrap_or(0), buffs.iter().cloned());
+        let (crit, dcrit) = self.stat_percent(Stat::Crit, buffs);
+        let (haste, dhaste) = self.stat_percent(Stat::Haste, buffs);
+        let (_, dmastery) = self.stat_percent(Stat::Mastery, buffs);
+        let (vers, dvers) = self.stat_percent(Stat::Versatility, buffs);
+        let heal = heal as f64;
+        let crit_value = if id == SPELL_LIVING_SEED {
+            // seeds come from crits, so there are more of them with more crit
+            if crit > 0. { heal / crit * dcrit } else { 0. }
+        } else {
+            uncrit_heal as f64 * (self.crit_mult - 1.) * dcrit / 100.
+        };
+        // hots tick faster, and everything else gets cast more
+        let hasted = ty == HealType::Periodic || !HASTE_FIXED.contains(&id);
+        [if int != 0 { heal / int as f64 } else { 0. },
+         crit_value,
+         if hasted { heal * dhaste / (100. + haste) } else { 0. },
+         stacks_unmast as f64 * self.stats.mastery[&SPEC_RESTO_DRUID] * dmastery / 100.,
+         heal * dvers / (100. + vers)]
+    }
+
+    // The fraction of the healing in `source` that a leech heal of
+    // `total_heal` (`heal` of it not overhealing) gave back. The leech
+    // rating says how much of the heal the healing should have given;
+    // without it the heal is split by the healing and damage done.
+    fn leech_share(&self, buffs: &[(u32, u64)], source: &LeechSource, total_heal: u64, heal: u64) -> f64 {
+        if source.healing == 0 || total_heal == 0 {
+            return 0.;
         }
+        let (leech, _) = self.stat_percent(Stat::Leech, buffs);
+        let from_healing = if leech > 0. {
+            (source.healing as f64 * leech / 100. / total_heal as f64).min(1.)
+        } else {
+            source.healing as f64 / (source.healing + source.damage) as f64
+        };
+        heal as f64 * from_healing / source.healing as f64
     }
 
-    pub fn reset_stats(&mut self) {
-        let prev = std::mem::replace(self, Default::default());
-        *self = RestoComputation {
-            player_id: prev.player_id,
-            cur_mastery: prev.cur_mastery,
-            map: prev.map,
-            under_2pc: prev.under_2pc,
-            ..Default::default()
+    /// Stat weights, relative to intellect
+    pub fn weights(&self) -> Vec<(&'static str, f64)> {
+        WEIGHT_STATS.iter().zip(self.stat_values.iter())
+            .map(|(&(_, name), &v)| (name, if self.stat_values[0] != 0. { v / self.stat_values[0] } else { 0. }))
+            .collect()
+    }
+
+    fn mastery_state(&self, auras: &AuraTracker<'a>, player_id: &str, buffs: &[(u32, u64)], target: &str) -> MasteryState {
+        let under_2pc = buffs.iter().any(|&(id, _)| id == AURA_2PC);
+        MasteryState {
+            stacks: self.tables.harmony.stacks(auras, target, player_id),
+            mastery: self.mastery_with(buffs),
+            without_2pc: if under_2pc {
+                Some(self.mastery_with(&buffs.iter().cloned().filter(|&(id, _)| id != AURA_2PC).collect::<Vec<_>>()))
+            } else {
+                None
+            },
+            hots: self.tables.spells.mastery_auras.iter().cloned().filter(|&aura| auras.has(target, player_id, aura)).collect(),
         }
     }
+}
 
-    pub fn parse_entry(&mut self, log: &wow_combat_log::Entry<'a>, filter_start_time: Duration) {
-        use wow_combat_log::Entry::*;
-        use wow_combat_log::AuraType::*;
+impl<'a> MasteryModel<'a> for Resto<'a> {
+    fn spec() -> u32 { SPEC_RESTO_DRUID }
+    fn name() -> &'static str { "restoration druid" }
+    fn needs() -> &'static str { "target" }
 
-        if let Info { id, mastery, ref auras, .. } = *log {
-            let entry = self.map.entry(id).or_insert((HashSet::new(), log.timestamp()));
-            let player_id = self.player_id;
+    fn update(&mut self, log: &Entry<'a>, auras: &AuraTracker<'a>, player_id: &'a str) {
+        if let Entry::Info { id, int, crits, hastes, mastery, versr, steal, gear, auras: ref info_auras, .. } = *log {
             if player_id == id {
-                self.cur_mastery = mastery;
-                if auras.contains(&(self.player_id, AURA_2PC)) {
-                    self.cur_mastery -= MASTERY_2PC;
-                    self.under_2pc = true;
+                self.gear = wow_combat_log::parse_gear(gear);
+                self.info_auras = info_auras.iter().map(|&(_, aura)| aura).collect();
+                self.update_crit();
+
+                // the stats include the buffs the player had at the time
+                let buffs = self.player_buffs(auras, player_id);
+                for &(stat, rating) in &[(Stat::Intellect, int), (Stat::Crit, crits), (Stat::Haste, hastes),
+                                         (Stat::Mastery, mastery), (Stat::Versatility, versr), (Stat::Leech, steal)] {
+                    self.ratings.insert(stat, self.tables.buffs.unbuffed(stat, rating, buffs.iter().cloned()));
                 }
             }
-            entry.0 = auras.iter()
-                .filter(|&&(src, aura)| src == player_id && MASTERY_AURAS.contains(&aura))
-                .map(|&(_, aura)| aura).collect();
-            entry.1 = log.timestamp();
         }
-
-        if log.base().is_none() {
-            return;
-        }
-        let base = log.base().unwrap();
-        if base.src.id != self.player_id {
-            return;
+        if let Some(base) = log.base() {
+            if base.src.id == player_id && self.race.is_none() {
+                if let Some(race) = self.tables.races.get(base.src.name) {
+                    self.race = Some(race.clone());
+                    self.update_crit();
+                }
+            }
         }
-        let entry = self.map.entry(log.base().unwrap().dst.id).or_insert((HashSet::new(), log.timestamp()));
-        let diff = log.timestamp() - entry.1;
+    }
 
-        // If we haven't seen anything from them for 10 seconds,
-        // assume they left the zone and may have lost all their buffs
-        if diff > Duration::seconds(10) {
-            entry.0.clear();
-        } else {
-            entry.1 = log.timestamp();
+    fn effect(&self, log: &Entry<'a>, auras: &AuraTracker<'a>, player_id: &'a str) -> Option<f64> {
+        self.tables.harmony.effect(log, auras, player_id)
+    }
+
+    fn heal_mastery(&mut self, log: &Entry<'a>, auras: &AuraTracker<'a>, player_id: &'a str, _mastery: f64) -> Option<(f64, f64)> {
+        let (target, id) = match *log {
+            Entry::Heal { ref base, id, .. } => (base.dst.id, id),
+            _ => return None,
+        };
+        let buffs = self.player_buffs(auras, player_id);
+        let mut state = self.mastery_state(auras, player_id, &buffs, target);
+        if id == SPELL_LIVING_SEED {
+            // a seed is worth what the heal that planted it was, mastery included
+            if let Some(seed) = self.seeds.remove(target) {
+                if (log.timestamp() - seed.planted).num_milliseconds() <= LIVING_SEED_DURATION_MS {
+                    state = seed.state;
+                }
+            }
         }
+        let ret = (state.stacks as f64, state.mastery);
+        self.state = Some(state);
+        Some(ret)
+    }
+
+    fn player_event(&mut self, log: &Entry<'a>, auras: &AuraTracker<'a>, player_id: &'a str, counted: bool,
+                    split: Option<(u64, f64, f64)>) -> Option<(u64, f64)> {
+        use wow_combat_log::Entry::*;
+
         match *log {
-            Aura { ty, id, .. } if MASTERY_AURAS.contains(&id) => {
-                match ty {
-                    Apply | Refresh => {
-                        entry.0.insert(id);
-                    },
-                    Remove => {
-                        entry.0.remove(&id);
-                    },
-                    _ => (),
+            Heal { id: SPELL_LEECH, heal: total_heal, overheal, .. } => {
+                let source = std::mem::take(&mut self.leech_source);
+                if !counted {
+                    return None;
                 }
-                entry.1 = log.timestamp();
+                let heal = total_heal - overheal;
+                self.total_uncrit_healing += heal;
+                self.total_healing_per[0] += heal;
+                self.leech_healing += heal;
+                // leech is a share of everything since the last one, so
+                // the part from healing gets the same share of its
+                // mastery (damage has none)
+                let share = self.leech_share(&self.player_buffs(auras, player_id), &source, total_heal, heal);
+                let unmast = (source.unmast as f64 * share) as u64;
+                self.total_healing_per_unmast[0] += unmast;
+                for (v, s) in self.stat_values.iter_mut().zip(source.stat_values.iter()) {
+                    *v += s * share;
+                }
+                Some((unmast, ((source.stacks_unmast as f64 * share) as u64) as f64))
             },
-            Aura { ty, id: AURA_2PC, .. } => {
-                self.under_2pc = ty != Remove;
+            Damage { amount, .. } => {
+                self.leech_source.damage += amount;
+                None
             },
-            Heal { id, heal: total_heal, overheal, crit, ty, .. } => {
-                if log.timestamp() < filter_start_time {
-                    return;
+            Heal { ref base, id, heal: total_heal, overheal, crit, ty, .. } => {
+                let state = self.state.take();
+                let buffs = self.player_buffs(auras, player_id);
+                if crit && ty == HealType::Heal && self.tables.spells.living_seed_heals.contains(&id) {
+                    let state = state.clone().unwrap_or_else(|| self.mastery_state(auras, player_id, &buffs, base.dst.id));
+                    self.seeds.insert(base.dst.id, Seed { planted: log.timestamp(), state });
+                }
+                if !counted {
+                    return None;
                 }
 
                 let heal = total_heal - overheal;
-                let stacks = entry.0.len();
-                let mastery = self.cur_mastery + if self.under_2pc { MASTERY_2PC } else { 0 };
-                let mastery = (mastery as f64 /666.6+4.8)/100.;
-                let unmast = ((heal as f64) / (1. + stacks as f64 * mastery)) as u64;
-                let uncrit_heal = if crit { total_heal / 2 } else { total_heal }; // TODO /2 ignores drape and tauren
+                let uncrit_heal = if crit { (total_heal as f64 / self.crit_mult) as u64 } else { total_heal };
                 let uncrit_heal = std::cmp::min(uncrit_heal, heal);
-                self.total_healing += heal;
-                if REJUV_AURAS.contains(&id) {
+                self.leech_source.healing += heal;
+                if self.tables.spells.rejuv_auras.contains(&id) {
                     self.rejuv_healing += heal;
                 }
-                if MASTERY_AURAS.contains(&id) || OTHER_HEALS.contains(&id) {
+                // only mastery heals get anything from mastery, but all
+                // of them scale with the other stats
+                let mastery = match (split, state) {
+                    (Some((unmast, _, _)), Some(state)) => Some((unmast, state)),
+                    _ => None,
+                };
+                let stacks_unmast = mastery.as_ref().map(|&(unmast, ref state)| (state.stacks as u64) * unmast).unwrap_or(0);
+                let values = self.stat_values(&buffs, id, ty, heal, uncrit_heal, stacks_unmast);
+                for (i, v) in values.iter().enumerate() {
+                    self.stat_values[i] += v;
+                    self.leech_source.stat_values[i] += v;
+                }
+                if let Some((unmast, MasteryState { stacks, mastery, without_2pc, hots })) = mastery {
                     self.total_healing_per[stacks] += heal;
                     self.total_healing_per_unmast[stacks] += unmast;
-                    self.mastery_healing += (stacks as u64) * unmast;
-                    self.total_unmastery_healing += unmast;
+                    self.leech_source.unmast += unmast;
+                    self.leech_source.stacks_unmast += (stacks as u64) * unmast;
 
-                    for &aura in &entry.0 {
+                    for &aura in &hots {
                         // Only measure the contribution to other heals
                         if aura != id {
                             let added = (unmast as f64 * mastery) as u64;
@@ -224,16 +338,16 @@ impl<'a> RestoComputation<'a> {
                         }
                     }
 
-                    if self.under_2pc {
-                        let added = (stacks as f64 * unmast as f64 * MASTERY_2PC as f64 /666.6 / 100.) as u64;
+                    if let Some(without) = without_2pc {
+                        let added = (stacks as f64 * unmast as f64 * (mastery - without)) as u64;
                         self.healing_2pc += heal;
                         self.healing_2pc_added += added;
                     }
                 }
 
                 self.total_uncrit_healing += uncrit_heal;
-                if ty == wow_combat_log::HealType::Heal {
-                    if LIVING_SEED_HEALS.contains(&id) {
+                if ty == HealType::Heal {
+                    if self.tables.spells.living_seed_heals.contains(&id) {
                         self.living_seed_healing += uncrit_heal;
                     }
                     if id == SPELL_REGROWTH {
@@ -243,44 +357,38 @@ impl<'a> RestoComputation<'a> {
                 if id == SPELL_TRANQ {
                     self.tranq_healing += heal;
                 }
+                None
             },
-            _ => ()
+            _ => None,
         }
     }
-}
 
-impl<'a> fmt::Display for RestoComputation<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "scale_mastery_frac: {:.6}; scale_living_seed: {:.6}; scale_regrowth: {:.6}; scale_tranq: {:.6}; scale_rejuv: {:.6};\n  scale_2pc: {:.6}; scale_2pc_added: {:.6};\n",
-                    self.mastery_healing as f64 / self.total_unmastery_healing as f64,
-                    self.living_seed_healing as f64 / self.total_uncrit_healing as f64,
-                    self.regrowth_healing as f64 / self.total_uncrit_healing as f64,
-                    self.tranq_healing as f64 / self.total_healing as f64,
-                    self.rejuv_healing as f64 / self.total_healing as f64,
-                    self.healing_2pc as f64/self.total_healing as f64,
-                    self.healing_2pc_added as f64/self.total_healing as f64,
-                    )?;
-        writeln!(f, "Mastery stack healing on other heals: ")?;
-        for &(aura, name) in MASTERY_NAMES {
-            let added = self.hot_mastery_healing_added.get(&aura).map(|x| *x).unwrap_or(0);
-            if added != 0 {
-                write!(f, "{}: {:.6},  ", name, added as f64 / self.total_healing as f64)?;
-            }
+    fn reset_stats(&mut self) {
+        let prev = std::mem::take(self);
+        *self = Resto {
+            ratings: prev.ratings,
+            race: prev.race,
+            gear: prev.gear,
+            info_auras: prev.info_auras,
+            crit_mult: prev.crit_mult,
+            stats: prev.stats,
+            tables: prev.tables,
+            seeds: prev.seeds,
+            leech_source: prev.leech_source,
+            ..Default::default()
         }
-        Ok(())
     }
-}
 
-impl<'a, 'b, 'c> std::ops::SubAssign<&'b RestoComputation<'c>> for RestoComputation<'a> {
-    fn sub_assign(&mut self, rhs: &'b RestoComputation<'c>) {
-        self.total_healing -= rhs.total_healing;
-        self.total_unmastery_healing -= rhs.total_unmastery_healing;
+    fn subtract(&mut self, rhs: &Self) {
         self.total_uncrit_healing -= rhs.total_uncrit_healing;
-        self.mastery_healing -= rhs.mastery_healing;
         self.living_seed_healing -= rhs.living_seed_healing;
         self.regrowth_healing -= rhs.regrowth_healing;
         self.tranq_healing -= rhs.tranq_healing;
         self.rejuv_healing -= rhs.rejuv_healing;
+        self.leech_healing -= rhs.leech_healing;
+        for (i, &j) in self.stat_values.iter_mut().zip(rhs.stat_values.iter()) {
+            *i -= j;
+        }
         self.healing_2pc -= rhs.healing_2pc;
         self.healing_2pc_added -= rhs.healing_2pc_added;
         for (i, &j) in self.total_healing_per.iter_mut().zip(rhs.total_healing_per.iter()) {
@@ -290,102 +398,302 @@ impl<'a, 'b, 'c> std::ops::SubAssign<&'b RestoComputation<'c>> for RestoComputat
             *i -= j;
         }
         for (aura, &heal) in rhs.hot_mastery_healing_added.iter() {
-            *self.hot_mastery_healing_added.get_mut(aura).unwrap() -= heal;
+            *self.hot_mastery_healing_added.entry(*aura).or_default() -= heal;
+        }
+    }
+
+    fn report(comp: &RestoComputation<'a>, f: &mut fmt::Formatter) -> fmt::Result {
+        let (model, total_healing) = (comp.model(), comp.total_healing());
+        let scales = scales(comp);
+        write!(f, "scale_mastery_frac: {:.6}; scale_living_seed: {:.6}; scale_regrowth: {:.6}; scale_tranq: {:.6}; scale_rejuv: {:.6};\n  scale_2pc: {:.6}; scale_2pc_added: {:.6}; scale_leech: {:.6};\n",
+                    scales[0], scales[1], scales[2], scales[3], scales[4], scales[5], scales[6], scales[7])?;
+        writeln!(f, "Mastery stack healing on other heals: ")?;
+        for &(aura, ref name) in &model.tables.spells.mastery_names {
+            let added = model.hot_mastery_healing_added.get(&aura).copied().unwrap_or(0);
+            if added != 0 {
+                write!(f, "{}: {:.6},  ", name, added as f64 / total_healing as f64)?;
+            }
+        }
+        write!(f, "\nStat weights (int = 1): ")?;
+        for (name, weight) in model.weights().into_iter().skip(1) {
+            write!(f, "{}: {:.3}; ", name, weight)?;
+        }
+        Ok(())
+    }
+}
+
+static SCALE_NAMES: &[&str] = &[
+    "mastery_frac", "living_seed", "regrowth", "tranq", "rejuv", "2pc", "2pc_added", "leech",
+    ];
+
+// the scale_ values, in SCALE_NAMES order
+fn scales(comp: &RestoComputation) -> [f64; 8] {
+    let (model, total_healing) = (comp.model(), comp.total_healing() as f64);
+    [comp.mastery_frac(),
+     model.living_seed_healing as f64 / model.total_uncrit_healing as f64,
+     model.regrowth_healing as f64 / model.total_uncrit_healing as f64,
+     model.tranq_healing as f64 / total_healing,
+     model.rejuv_healing as f64 / total_healing,
+     model.healing_2pc as f64 / total_healing,
+     model.healing_2pc_added as f64 / total_healing,
+     model.leech_healing as f64 / total_healing]
+}
+
+// One druid's numbers for `run_all`, added up like `report` does
+struct Druid<'a> {
+    id: &'a str,
+    total: RestoComputation<'a>,
+    bosses: RestoComputation<'a>,
+    kills: RestoComputation<'a>,
+    encounter: RestoComputation<'a>,
+}
+
+impl<'a> Druid<'a> {
+    fn new(id: &'a str, info: &Entry<'a>, opts: &HealerOptions, tables: &Rc<Tables>) -> Self {
+        let stats = Rc::new(opts.level_stats(info, SPEC_RESTO_DRUID, Resto::name(), false));
+        let model = Resto::new(tables, stats.clone());
+        let comp = RestoComputation::with_model(model, id, info, stats, tables.buffs.clone(), &opts.spells);
+        Druid { id, total: comp.clone(), bosses: comp.clone(), kills: comp.clone(), encounter: comp }
+    }
+
+    fn parse_entry(&mut self, log: &Entry<'a>, start: Duration) {
+        match *log {
+            Entry::EncounterStart { .. } => {
+                self.bosses.subtract(&self.encounter);
+                self.kills.subtract(&self.encounter);
+                self.encounter.reset_stats();
+            },
+            Entry::EncounterEnd { kill, .. } => {
+                if !kill {
+                    self.kills.subtract(&self.encounter);
+                }
+                self.encounter.reset_stats();
+            },
+            _ => (),
+        }
+        for comp in &mut [&mut self.total, &mut self.bosses, &mut self.kills, &mut self.encounter] {
+            comp.parse_entry(log, start);
+        }
+    }
+
+    fn finish(&mut self) {
+        self.bosses.subtract(&self.encounter);
+        self.kills.subtract(&self.encounter);
+    }
+}
+
+// One line per druid, for comparing them
+fn print_table<'a, F: for<'b> Fn(&'b Druid<'a>) -> &'b RestoComputation<'a>>(druids: &[Druid<'a>], roster: &Roster, which: F) {
+    print!("{:24}", "player");
+    for name in SCALE_NAMES {
+        print!(" {:>12}", name);
+    }
+    for &(_, name) in &WEIGHT_STATS[1..] {
+        print!(" {:>8}", name);
+    }
+    println!();
+    for d in druids {
+        let comp = which(d);
+        if comp.total_healing() == 0 {
+            continue;
         }
+        print!("{:24}", roster.get(d.id).map(|p| p.name).unwrap_or(d.id));
+        for x in &scales(comp) {
+            print!(" {:12.6}", x);
+        }
+        for (_, weight) in comp.model().weights().into_iter().skip(1) {
+            print!(" {:8.3}", weight);
+        }
+        println!();
     }
 }
 
-fn run<'a, I: Iterator<Item=Entry<'a>>, F: Fn(Option<&str>) -> I>(player: &str, start: Duration, end: Duration, get_iter: F) {
-    
-    let (pid, cur_mastery) = find_init_mastery(get_iter(None), player).unwrap();
-    let iter = get_iter(Some(player));
-    let iter = iter.take_while(|x| x.timestamp() < end);
+// Every resto druid in the log, in one pass. Like `find_init_info` in
+// src/report.rs, a druid's healing from before their COMBATANT_INFO
+// counts, so the events since the last encounter ended are kept to be
+// replayed for anyone new (COMBATANT_INFO comes at the start of an
+// encounter, so that includes their pre-hots).
+fn run_all(opts: &HealerOptions, tables: &Rc<Tables>) {
+    let intern = wow_combat_log::Interner::default();
+    let iter = opts.open(&intern, None);
+    let iter = iter.take_while(|x| x.timestamp() < opts.end);
+    let mut roster = Roster::new();
+    let mut druids: Vec<Druid> = vec![];
+    let mut pending = vec![];
     let mut encounter_start = None;
-    let mut total = RestoComputation::new(pid, cur_mastery);
-    let mut encounter = total.clone();
-    let mut kills = total.clone();
-    let mut bosses = total.clone();
 
     for log in iter {
         use wow_combat_log::Entry::*;
+        roster.update(&log);
         match log {
-            EncounterStart {..} => {
-                encounter_start = Some(log.timestamp());
-                bosses -= &encounter;
-                kills -= &encounter;
-                encounter.reset_stats();
+            Info { id, .. } if log.spec_id() == Some(SPEC_RESTO_DRUID) && !druids.iter().any(|d| d.id == id) => {
+                let mut druid = Druid::new(id, &log, opts, tables);
+                for log in &pending {
+                    druid.parse_entry(log, opts.start);
+                }
+                druids.push(druid);
             },
+            EncounterStart {..} => encounter_start = Some(log.timestamp()),
             EncounterEnd {name, kill, difficulty, ..} => {
                 if let Some(s) = encounter_start {
                     println!("duration: {}, start: {}, {} ({}), kill: {}", (log.timestamp() - s).num_seconds(), s.num_seconds(), name, difficulty, kill);
-                    println!("{}", encounter);
-                    println!("");
+                    print_table(&druids, &roster, |d| &d.encounter);
+                    println!();
                     encounter_start = None;
                 }
-                if !kill {
-                    kills -= &encounter;
-                }
-                encounter.reset_stats();
             },
             _ => ()
         }
-        encounter.parse_entry(&log, start);
-        total.parse_entry(&log, start);
-        kills.parse_entry(&log, start);
-        bosses.parse_entry(&log, start);
+        for d in &mut druids {
+            d.parse_entry(&log, opts.start);
+        }
+        match log {
+            EncounterEnd {..} | ChallengeEnd {..} => pending.clear(),
+            _ => pending.push(log),
+        }
+    }
+    for d in &mut druids {
+        d.finish();
     }
-    bosses -= &encounter;
-    kills -= &encounter;
 
     println!("-------");
-    println!("");
+    println!();
     println!("Log total:");
-    println!("{}", total);
-    println!("");
+    print_table(&druids, &roster, |d| &d.total);
+    println!();
     println!("Boss total:");
-    println!("{}", bosses);
-    println!("");
+    print_table(&druids, &roster, |d| &d.bosses);
+    println!();
     println!("Kill total:");
-    println!("{}", kills);
-}
-
-#[cfg(feature = "wcl")]
-pub fn wcl_iter<'a>(intern: &'a wow_combat_log::Interner, log: &str, api_key: &str,
-                    skip: bool, name: Option<&str>) -> wow_combat_log::wcl::Iter<'a> {
-    wow_combat_log::wcl::iter(intern, log, api_key, skip, name)
-}
-
-#[cfg(not(feature = "wcl"))]
-pub fn wcl_iter<'a>(_: &'a wow_combat_log::Interner, _: &str, _: &str,
-                    _: bool, _: Option<&str>) -> wow_combat_log::Iter<'a, BufReader<File>> {
-    unreachable!()
+    print_table(&druids, &roster, |d| &d.kills);
 }
 
 fn main() {
-    let app = App::new("resto druid mastery");
-    let app = if cfg!(feature = "wcl") {
-        app.arg(Arg::with_name("API key").long("wcl").takes_value(true).help("warcraftlogs API key"))
-    } else {
-        app
-    };
-    let matches = app
-        .arg(Arg::with_name("File/WCL ID").required(true).help("Log file or WCL log ID"))
-        .arg(Arg::with_name("Player").required(true).help("Player name (as reported in log)"))
-        .arg(Arg::with_name("Start").help("Start time in seconds from start of log"))
-        .arg(Arg::with_name("End").help("End time in seconds from start of log"))
+    let matches = HealerOptions::app("resto druid mastery")
+        .arg(Arg::with_name("All").long("all").conflicts_with("Player")
+             .help("Compare every restoration druid in the log"))
+        .arg(Arg::with_name("Crit").long("crit").takes_value(true)
+             .help("Crit multiplier table to use instead of the built-in one"))
+        .arg(Arg::with_name("Race").long("race").takes_value(true).multiple(true).number_of_values(1)
+             .help("A player's race, as Name-Realm=Race, for racial crit bonuses"))
         .get_matches();
-    let player = matches.value_of("Player").unwrap();
-    let intern = wow_combat_log::Interner::default();
-    let start = matches.value_of("Start").map(|x| Duration::seconds(x.parse().unwrap())).unwrap_or(Duration::zero());
-    let end = matches.value_of("End").map(|x| Duration::seconds(x.parse().unwrap())).unwrap_or(Duration::max_value());
-    let input = matches.value_of("File/WCL ID").unwrap();
-    if let Some(api) = matches.value_of("API key") {
-        run(player, start, end, |player|
-            wcl_iter(&intern, input, api, player.is_none(), player)
-        );
+    let opts = HealerOptions::from_matches(&matches);
+    let crit = match matches.value_of("Crit") {
+        Some(path) => CritTable::load(Path::new(path)).unwrap(),
+        None => CritTable::builtin(),
+    };
+    let races = matches.values_of("Race").map(|v| v.map(|x| {
+        let mut split = x.splitn(2, '=');
+        (split.next().unwrap().to_owned(), split.next().expect("--race takes Name-Realm=Race").to_owned())
+    }).collect()).unwrap_or_default();
+    let tables = Rc::new(Tables {
+        buffs: Rc::new(opts.buffs.clone()), crit, spells: DruidSpells::new(&opts.spells), harmony: Harmony::new(&opts.spells), races,
+    });
+    if matches.is_present("All") {
+        run_all(&opts, &tables);
     } else {
-        run(player, start, end, |_|
-            wow_combat_log::iter(&intern, BufReader::new(File::open(input).unwrap()))
-        );
+        let intern = wow_combat_log::Interner::default();
+        opts.run_with(&intern, |_, stats| Resto::new(&tables, stats.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wow_combat_log::{Interner, iter, SpellDb, StatTable};
+
+    // at level 110, with 11234 mastery rating: 0.6 * (11234 / 400 + 8) =
+    // 21.65% per HoT. The regrowth crits with one HoT up and plants a
+    // seed, which goes off after a second HoT is up.
+    static LOG: &str = r#"4/7 21:13:58.000  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:58.100  COMBATANT_INFO,Player-3674-06D6B01E,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,11234,2468,2468,2468,1918,105,(18569,102351,0,197061,0,197073,155675),(0,0,0,0),[],[(147143,915,(),(),())],[]
+4/7 21:13:59.000  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:14:00.000  SPELL_HEAL,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,8936,"Regrowth",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,200000,50000,0,1
+4/7 21:14:01.000  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,33763,"Lifebloom",0x8,BUFF
+4/7 21:14:02.000  SPELL_HEAL,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,48503,"Living Seed",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,60000,0,0,nil
+4/7 21:14:03.000  SPELL_PERIODIC_HEAL,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,60000,0,0,nil
+4/7 21:14:04.000  SPELL_DAMAGE,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,8921,"Moonfire",0x40,Creature-0-3019-1676-22336-115844-00006814F0,0000000000000000,89960000,100000000,0,0,0,0,0,0,6213.56,-842.09,0,12000,0,64,0,0,0,nil,nil,nil,nil
+4/7 21:14:05.000  SPELL_HEAL,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,143924,"Leech",0x1,Player-3674-06D6B01E,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,10000,0,0,nil
+4/7 21:14:30.000  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    const DRUID: &str = "Player-3674-06D6B01E";
+
+    fn analyze<'a>(intern: &'a Interner, log: &str) -> RestoComputation<'a> {
+        let db = SpellDb::builtin();
+        let tables = Rc::new(Tables {
+            buffs: Rc::new(BuffTable::builtin()), crit: CritTable::builtin(), spells: DruidSpells::new(&db),
+            harmony: Harmony::new(&db), races: HashMap::new(),
+        });
+        let logs = iter(intern, log.as_bytes()).collect::<Vec<_>>();
+        let stats = Rc::new(StatTable::builtin().level(110).unwrap().clone());
+        let model = Resto::new(&tables, stats.clone());
+        let mut comp = RestoComputation::with_model(model, DRUID, &logs[1], stats, tables.buffs.clone(), &db);
+        for log in &logs {
+            comp.parse_entry(log, Duration::zero());
+        }
+        comp
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn living_seed() {
+        let intern = Interner::default();
+        let comp = analyze(&intern, LOG);
+        let scales = scales(&comp);
+        // 150000 + 60000 + 60000 + 10000 leech
+        assert_eq!(comp.total_healing(), 280000);
+        // without crits: 100000 regrowth + 60000 + 60000 + 10000
+        assert!(close(scales[1], 100000. / 230000.), "{:?}", scales);
+        assert!(close(scales[2], 100000. / 230000.), "{:?}", scales);
+        assert!(close(scales[4], 60000. / 280000.), "{:?}", scales);
+        // the seed gets the one HoT of the regrowth that planted it, not
+        // the two up when it goes off, so there's (150000 + 60000) / 1.2165
+        // no-mastery healing with one stack and 60000 / 1.4330 with two
+        // (leech gets a share of both, which doesn't change the average)
+        let m = 0.6 * (11234. / 400. + 8.) / 100.;
+        let (one, two) = (210000. / (1. + m), 60000. / (1. + 2. * m));
+        let frac = (one + 2. * two) / (one + two);
+        assert!((scales[0] - frac).abs() < 1e-4, "{} {}", scales[0], frac);
+        assert_eq!((scales[3], scales[5], scales[6]), (0., 0., 0.));
+    }
+
+    #[test]
+    fn leech() {
+        let intern = Interner::default();
+        let m = 0.6 * (11234. / 400. + 8.) / 100.;
+        let unmast = 210000. / (1. + m) + 60000. / (1. + 2. * m);
+
+        let comp = analyze(&intern, LOG);
+        assert!(close(scales(&comp)[7], 10000. / 280000.));
+        assert_eq!(comp.model().leech_healing, 10000);
+        // with no leech rating, it's split between the 270000 healing
+        // and 12000 damage done before it
+        let leeched = comp.model().total_healing_per_unmast[0] as f64;
+        assert!((leeched - unmast * 10000. / 282000.).abs() < 2., "{}", leeched);
+
+        // 460 rating is 2%, so 5400 of it came from the healing
+        let log = LOG.replace("5762,5762,5762,0,0,4927", "5762,5762,5762,0,460,4927");
+        let comp = analyze(&intern, &log);
+        let leeched = comp.model().total_healing_per_unmast[0] as f64;
+        assert!((leeched - unmast * 5400. / 270000.).abs() < 2., "{}", leeched);
+        assert!(close(scales(&comp)[7], 10000. / 280000.));
+    }
+
+    #[test]
+    fn stat_weights() {
+        let intern = Interner::default();
+        let comp = analyze(&intern, LOG);
+        let weights = comp.model().weights();
+        assert_eq!(weights.iter().map(|x| x.0).collect::<Vec<_>>(), vec!["int", "crit", "haste", "mastery", "vers"]);
+        // worked out by hand from the ratings in COMBATANT_INFO: crit
+        // is the regrowth's crit bonus and more seeds, and mastery only
+        // counts the stacks
+        for (&(name, weight), &expected) in weights.iter().zip(&[1., 1.4586, 0.7913, 0.4782, 0.6719]) {
+            assert!((weight - expected).abs() < 1e-3, "{}: {}", name, weight);
+        }
     }
 }
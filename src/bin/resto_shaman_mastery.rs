@@ -0,0 +1,10 @@
+extern crate wow_combat_log;
+
+use wow_combat_log::Interner;
+use wow_combat_log::healer::HealerOptions;
+use wow_combat_log::resto_shaman::DeepHealing;
+
+fn main() {
+    let intern = Interner::default();
+    HealerOptions::from_args("resto shaman mastery").run::<DeepHealing>(&intern);
+}
@@ -0,0 +1,360 @@
+extern crate wow_combat_log;
+extern crate chrono;
+extern crate clap;
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use chrono::{Duration, NaiveDateTime};
+use clap::{Arg, App};
+use wow_combat_log::{Entry, Interner};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    // every ENCOUNTER_START..ENCOUNTER_END on its own
+    Pull,
+    // consecutive pulls of the same boss, up to and including the kill
+    Encounter,
+    // everything, split whenever the zone changes to a different instance
+    Instance,
+    // everything, in fixed length pieces
+    Window(Duration),
+}
+
+fn difficulty_name(difficulty: u16) -> String {
+    match difficulty {
+        1 => "normal5".to_owned(),
+        2 => "heroic5".to_owned(),
+        8 => "mythicplus".to_owned(),
+        14 => "normal".to_owned(),
+        15 => "heroic".to_owned(),
+        16 => "mythic".to_owned(),
+        17 => "lfr".to_owned(),
+        23 => "mythic5".to_owned(),
+        x => format!("difficulty{}", x),
+    }
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars().filter_map(|c| match c {
+        c if c.is_alphanumeric() => Some(c),
+        ' ' | '-' | '_' => Some('_'),
+        _ => None,
+    }).collect()
+}
+
+fn split_ts(line: &str) -> (&str, &str) {
+    let mut parts = line.splitn(2, "  ");
+    (parts.next().unwrap(), parts.next().unwrap_or(""))
+}
+
+fn event_type(line: &str) -> &str {
+    split_ts(line).1.split(',').next().unwrap().trim()
+}
+
+// ZONE_CHANGE,1676,"Tomb of Sargeras",16 -> (1676, "Tomb of Sargeras")
+fn parse_zone(line: &str) -> (u32, String) {
+    let mut parts = split_ts(line).1.splitn(3, ',').skip(1);
+    let id = parts.next().unwrap().parse().unwrap();
+    let rest = parts.next().unwrap_or("");
+    let name = rest.rsplitn(2, ',').last().unwrap().trim_matches('"');
+    (id, name.to_owned())
+}
+
+#[derive(Debug)]
+struct Piece {
+    out: BufWriter<File>,
+    tmp_path: PathBuf,
+    start: NaiveDateTime,
+    // (encounter id, difficulty), name
+    boss: Option<((u32, u16), String)>,
+    // zone id and name
+    instance: Option<(u32, String)>,
+    pulls: u32,
+    kill: bool,
+}
+
+#[derive(Debug)]
+struct Splitter {
+    dir: PathBuf,
+    mode: Mode,
+    count: usize,
+    // COMBAT_LOG_VERSION, without the timestamp
+    header: Option<String>,
+    // latest COMBATANT_INFO per player, without timestamps
+    infos: HashMap<String, String>,
+    piece: Option<Piece>,
+}
+
+impl Splitter {
+    /// A splitter writing into `dir`, numbering its pieces on from any
+    /// already there so an earlier run's aren't overwritten
+    fn new(dir: PathBuf, mode: Mode) -> io::Result<Splitter> {
+        fs::create_dir_all(&dir)?;
+        let mut count = 0;
+        for entry in fs::read_dir(&dir)? {
+            let name = entry?.file_name();
+            let n = name.to_str().and_then(|x| x.split(['-', '.']).next()).and_then(|x| x.parse().ok());
+            count = count.max(n.unwrap_or(0));
+        }
+        Ok(Splitter { dir, mode, count, header: None, infos: HashMap::new(), piece: None })
+    }
+
+    fn split<R: BufRead>(&mut self, read: &mut R) {
+        let intern = Interner::default();
+        let mut line = String::new();
+        while read.read_line(&mut line).unwrap() != 0 {
+            self.line(&intern, &line);
+            line.clear();
+        }
+        self.close();
+    }
+
+    /// Start a new piece at `line`, with the header and the latest
+    /// COMBATANT_INFO for each player copied in front of it, retimed to
+    /// `line` so the piece starts at the right time. (Pulls log their
+    /// own COMBATANT_INFOs after the ENCOUNTER_START, but only for the
+    /// players that are in range.)
+    fn open(&mut self, line: &str) {
+        self.close();
+        self.count += 1;
+        let tmp_path = self.dir.join(format!("{:03}.part", self.count));
+        let mut out = BufWriter::new(File::create(&tmp_path).unwrap());
+        let ts = split_ts(line).0;
+        let mut infos = self.infos.iter().collect::<Vec<_>>();
+        infos.sort();
+        for rest in self.header.iter().chain(infos.into_iter().map(|x| x.1)) {
+            write!(out, "{}  {}", ts, rest).unwrap();
+        }
+        self.piece = Some(Piece {
+            out,
+            tmp_path,
+            start: wow_combat_log::parse_ts(line).0,
+            boss: None,
+            instance: None,
+            pulls: 0,
+            kill: false,
+        });
+    }
+
+    fn close(&mut self) {
+        let mut piece = match self.piece.take() {
+            Some(piece) => piece,
+            None => return,
+        };
+        piece.out.flush().unwrap();
+        let mut name = format!("{:03}-{}", self.count, piece.start.format("%m%d-%H%M%S"));
+        match self.mode {
+            Mode::Pull | Mode::Encounter => {
+                if let Some(((_, difficulty), ref boss)) = piece.boss {
+                    name.push_str(&format!("-{}-{}", sanitize(boss), difficulty_name(difficulty)));
+                }
+                if self.mode == Mode::Encounter {
+                    name.push_str(&format!("-{}pulls", piece.pulls));
+                }
+                name.push_str(if piece.kill { "-kill" } else { "-wipe" });
+            },
+            Mode::Instance => if let Some((id, ref zone)) = piece.instance {
+                name.push_str(&format!("-{}-{}", id, sanitize(zone)));
+            },
+            Mode::Window(_) => (),
+        }
+        name.push_str(".txt");
+        fs::rename(&piece.tmp_path, self.dir.join(name)).unwrap();
+    }
+
+    fn write(&mut self, line: &str) {
+        if let Some(ref mut piece) = self.piece {
+            piece.out.write_all(line.as_bytes()).unwrap();
+        }
+    }
+
+    fn line(&mut self, intern: &Interner, line: &str) {
+        let ty = event_type(line);
+        let log = if ty.starts_with("ENCOUNTER_") {
+            let ts = wow_combat_log::parse_ts(line).0;
+            Some(wow_combat_log::parse_line(intern, line, ts))
+        } else {
+            None
+        };
+
+        match self.mode {
+            Mode::Pull | Mode::Encounter => {
+                if let Some(Entry::EncounterStart { id, difficulty, name, .. }) = log {
+                    let same_boss = self.mode == Mode::Encounter && self.piece.as_ref()
+                        .and_then(|p| p.boss.as_ref()).map(|b| b.0 == (id, difficulty)).unwrap_or(false);
+                    if !same_boss {
+                        self.open(line);
+                        self.piece.as_mut().unwrap().boss = Some(((id, difficulty), name.to_owned()));
+                    }
+                    self.piece.as_mut().unwrap().pulls += 1;
+                }
+                self.write(line);
+                if let Some(Entry::EncounterEnd { kill, .. }) = log {
+                    if let Some(ref mut piece) = self.piece {
+                        piece.kill = kill;
+                    }
+                    if self.mode == Mode::Pull || kill {
+                        self.close();
+                    }
+                }
+            },
+            Mode::Instance => {
+                // zoning back into the same instance (eg after a
+                // disconnect) keeps going in the same piece
+                if ty == "ZONE_CHANGE" {
+                    let zone = parse_zone(line);
+                    // the first zone seen names the piece it's already in
+                    let changed = match self.piece {
+                        Some(ref piece) => piece.instance.as_ref().map(|i| i.0 != zone.0).unwrap_or(false),
+                        None => true,
+                    };
+                    if changed {
+                        self.open(line);
+                    }
+                    self.piece.as_mut().unwrap().instance = Some(zone);
+                }
+                if self.piece.is_none() {
+                    self.open(line);
+                }
+                self.write(line);
+            },
+            Mode::Window(len) => {
+                let ts = wow_combat_log::parse_ts(line).0;
+                if self.piece.as_ref().map(|p| ts - p.start >= len).unwrap_or(true) {
+                    self.open(line);
+                }
+                self.write(line);
+            },
+        }
+
+        // after the line itself has been written, so it isn't duplicated
+        // into a piece it starts
+        match ty {
+            "COMBAT_LOG_VERSION" => self.header = Some(split_ts(line).1.to_owned()),
+            "COMBATANT_INFO" => {
+                let rest = split_ts(line).1;
+                let id = rest.split(',').nth(1).unwrap();
+                self.infos.insert(id.to_owned(), rest.to_owned());
+            },
+            _ => (),
+        }
+    }
+}
+
+fn positive(x: String) -> Result<(), String> {
+    match x.parse::<i64>() {
+        Ok(x) if x > 0 => Ok(()),
+        _ => Err("expected a number of seconds above 0".to_owned()),
+    }
+}
+
+fn main() {
+    let matches = App::new("split log")
+        .arg(Arg::with_name("File").required(true).help("Log file (- for stdin)"))
+        .arg(Arg::with_name("Output").required(true).help("Directory to write the pieces to"))
+        .arg(Arg::with_name("Mode").long("by").takes_value(true)
+             .possible_values(&["pull", "encounter", "instance", "window"]).default_value("pull")
+             .help("pull: one file per boss pull, encounter: one file per boss (all pulls up to the kill), \
+                    instance: one file per zone visited, window: fixed length pieces"))
+        .arg(Arg::with_name("Window").long("window").takes_value(true).default_value("3600").validator(positive)
+             .help("Piece length in seconds for --by window"))
+        .get_matches();
+
+    let mode = match matches.value_of("Mode").unwrap() {
+        "pull" => Mode::Pull,
+        "encounter" => Mode::Encounter,
+        "instance" => Mode::Instance,
+        "window" => Mode::Window(Duration::seconds(matches.value_of("Window").unwrap().parse().unwrap())),
+        _ => unreachable!(),
+    };
+    let input = matches.value_of("File").unwrap();
+    let stdin = io::stdin();
+    let mut read: Box<dyn BufRead> = if input == "-" {
+        Box::new(stdin.lock())
+    } else {
+        Box::new(BufReader::new(File::open(input).unwrap()))
+    };
+    let dir = PathBuf::from(matches.value_of("Output").unwrap());
+    Splitter::new(dir, mode).unwrap().split(&mut read);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    static LOG: &str = r#"4/7 21:13:50.000  COMBAT_LOG_VERSION,4,ADVANCED_LOG_ENABLED,1,BUILD_VERSION,7.2.0,PROJECT_ID,1
+4/7 21:13:51.000  ZONE_CHANGE,1676,"Tomb of Sargeras",16
+4/7 21:13:58.541  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:58.600  COMBATANT_INFO,Player-3674-06D6B01E,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,11234,2468,2468,2468,1918,105,(18569,102351,0,197061,0,197073,155675),(0,0,0,0),[(128306,1,(),(),())],[(147143,915,(),(3562,1497),())],[]
+4/7 21:13:59.100  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:15:00.999  ENCOUNTER_END,2032,"Goroth",16,20,0
+4/7 21:16:00.000  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:17:00.000  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:17:01.000  SPELL_AURA_REMOVED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:19:00.000  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("split_log-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // the pieces' names and contents, in order
+    fn split(dir: &PathBuf, mode: Mode) -> Vec<(String, String)> {
+        Splitter::new(dir.clone(), mode).unwrap().split(&mut LOG.as_bytes());
+        let mut ret = fs::read_dir(dir).unwrap().map(|e| {
+            let path = e.unwrap().path();
+            (path.file_name().unwrap().to_str().unwrap().to_owned(), fs::read_to_string(&path).unwrap())
+        }).collect::<Vec<_>>();
+        ret.sort();
+        ret
+    }
+
+    #[test]
+    fn carries_header_and_combatant_info_into_pulls() {
+        let dir = dir("pull");
+        let pieces = split(&dir, Mode::Pull);
+        fs::remove_dir_all(&dir).unwrap();
+        let names = pieces.iter().map(|x| &x.0[..]).collect::<Vec<_>>();
+        assert_eq!(names, vec!["001-0407-211358-Goroth-mythic-wipe.txt", "002-0407-211700-Goroth-mythic-kill.txt"]);
+        let lines = LOG.lines().collect::<Vec<_>>();
+        let retimed = |line: &str, to: &str| format!("{}  {}", split_ts(to).0, split_ts(line).1);
+        assert_eq!(pieces[0].1.lines().collect::<Vec<_>>(),
+                   vec![&retimed(lines[0], lines[2])[..], lines[2], lines[3], lines[4], lines[5]]);
+        // the second pull didn't log the druid's COMBATANT_INFO
+        assert_eq!(pieces[1].1.lines().collect::<Vec<_>>(),
+                   vec![&retimed(lines[0], lines[7])[..], &retimed(lines[3], lines[7])[..], lines[7], lines[8], lines[9]]);
+    }
+
+    #[test]
+    fn keeps_pulls_of_a_boss_together() {
+        let dir = dir("encounter");
+        let pieces = split(&dir, Mode::Encounter);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(pieces[0].0, "001-0407-211358-Goroth-mythic-2pulls-kill.txt");
+        assert_eq!(pieces[0].1.lines().count(), 9);
+    }
+
+    #[test]
+    fn numbers_on_from_earlier_pieces() {
+        let dir = dir("again");
+        split(&dir, Mode::Pull);
+        let pieces = split(&dir, Mode::Pull);
+        fs::remove_dir_all(&dir).unwrap();
+        let numbers = pieces.iter().map(|x| &x.0[..3]).collect::<Vec<_>>();
+        assert_eq!(numbers, vec!["001", "002", "003", "004"]);
+    }
+
+    #[test]
+    fn window_must_be_positive() {
+        assert!(positive("60".to_owned()).is_ok());
+        assert!(positive("0".to_owned()).is_err());
+        assert!(positive("-5".to_owned()).is_err());
+        assert!(positive("x".to_owned()).is_err());
+    }
+}
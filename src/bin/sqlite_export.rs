@@ -0,0 +1,322 @@
+extern crate wow_combat_log;
+extern crate chrono;
+extern crate clap;
+#[macro_use]
+extern crate rusqlite;
+
+use std::collections::{HashMap, HashSet};
+use chrono::{Duration, NaiveDateTime};
+use clap::{Arg, App};
+use rusqlite::{Connection, Transaction};
+use wow_combat_log::{Entry, EventSource, AuraType, DamageType, HealType};
+
+static SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS logs (
+    id INTEGER PRIMARY KEY,
+    source TEXT NOT NULL,
+    start TEXT
+);
+CREATE TABLE IF NOT EXISTS units (
+    id INTEGER PRIMARY KEY,
+    guid TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS spells (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS encounters (
+    id INTEGER PRIMARY KEY,
+    log INTEGER NOT NULL REFERENCES logs,
+    encounter_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    difficulty INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    instance INTEGER,
+    start REAL NOT NULL,
+    end REAL,
+    kill INTEGER
+);
+CREATE TABLE IF NOT EXISTS heals (
+    log INTEGER NOT NULL REFERENCES logs,
+    encounter INTEGER REFERENCES encounters,
+    ts REAL NOT NULL,
+    source INTEGER REFERENCES units,
+    target INTEGER REFERENCES units,
+    spell INTEGER NOT NULL REFERENCES spells,
+    periodic INTEGER NOT NULL,
+    amount INTEGER NOT NULL,
+    overheal INTEGER NOT NULL,
+    absorbed INTEGER NOT NULL,
+    crit INTEGER NOT NULL,
+    hp INTEGER NOT NULL,
+    maxhp INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS damage (
+    log INTEGER NOT NULL REFERENCES logs,
+    encounter INTEGER REFERENCES encounters,
+    ts REAL NOT NULL,
+    source INTEGER REFERENCES units,
+    target INTEGER REFERENCES units,
+    spell INTEGER NOT NULL REFERENCES spells,
+    type TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    overkill INTEGER,
+    school INTEGER NOT NULL,
+    resisted INTEGER NOT NULL,
+    blocked INTEGER NOT NULL,
+    absorbed INTEGER NOT NULL,
+    crit INTEGER NOT NULL,
+    glancing INTEGER NOT NULL,
+    crushing INTEGER NOT NULL,
+    hp INTEGER NOT NULL,
+    maxhp INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS auras (
+    log INTEGER NOT NULL REFERENCES logs,
+    encounter INTEGER REFERENCES encounters,
+    ts REAL NOT NULL,
+    source INTEGER REFERENCES units,
+    target INTEGER REFERENCES units,
+    spell INTEGER NOT NULL REFERENCES spells,
+    type TEXT NOT NULL,
+    buff INTEGER NOT NULL,
+    amount INTEGER
+);
+CREATE TABLE IF NOT EXISTS combatant_info (
+    id INTEGER PRIMARY KEY,
+    log INTEGER NOT NULL REFERENCES logs,
+    encounter INTEGER REFERENCES encounters,
+    ts REAL NOT NULL,
+    unit INTEGER REFERENCES units,
+    strength INTEGER NOT NULL, agility INTEGER NOT NULL, stamina INTEGER NOT NULL, intellect INTEGER NOT NULL,
+    dodge INTEGER NOT NULL, parry INTEGER NOT NULL, block INTEGER NOT NULL,
+    crit_melee INTEGER NOT NULL, crit_ranged INTEGER NOT NULL, crit_spell INTEGER NOT NULL,
+    speed INTEGER NOT NULL, leech INTEGER NOT NULL,
+    haste_melee INTEGER NOT NULL, haste_ranged INTEGER NOT NULL, haste_spell INTEGER NOT NULL,
+    avoidance INTEGER NOT NULL, mastery INTEGER NOT NULL,
+    vers_damage INTEGER NOT NULL, vers_healing INTEGER NOT NULL, vers_taken INTEGER NOT NULL,
+    armor INTEGER NOT NULL,
+    talents TEXT NOT NULL, artifact TEXT NOT NULL, gear TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS combatant_auras (
+    info INTEGER NOT NULL REFERENCES combatant_info,
+    source INTEGER REFERENCES units,
+    spell INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS heals_encounter ON heals (encounter);
+CREATE INDEX IF NOT EXISTS damage_encounter ON damage (encounter);
+CREATE INDEX IF NOT EXISTS auras_encounter ON auras (encounter);
+";
+
+fn secs(d: Duration) -> f64 {
+    d.num_milliseconds() as f64 / 1000.
+}
+
+// Loads one log, keeping the unit/spell ids already looked up
+struct Loader<'t> {
+    tx: &'t Transaction<'t>,
+    log: i64,
+    encounter: Option<i64>,
+    // guid -> (row id, whether it has a real name yet)
+    units: HashMap<String, (i64, bool)>,
+    spells: HashSet<u32>,
+}
+
+impl<'t> Loader<'t> {
+    fn unit(&mut self, guid: &str, name: &str) -> Option<i64> {
+        if guid == "0000000000000000" {
+            return None;
+        }
+        let named = name != "nil";
+        match self.units.get(guid) {
+            Some(&(id, true)) => return Some(id),
+            Some(&(id, false)) if !named => return Some(id),
+            _ => (),
+        }
+        // COMBATANT_INFO only has the guid, so fill in the name when it's first seen
+        self.tx.execute("INSERT INTO units (guid, name) VALUES (?, ?)
+                         ON CONFLICT (guid) DO UPDATE SET name = excluded.name WHERE excluded.name != 'nil'",
+                        params![guid, name]).unwrap();
+        let id = self.tx.query_row("SELECT id FROM units WHERE guid = ?", params![guid], |row| row.get(0)).unwrap();
+        self.units.insert(guid.to_owned(), (id, named));
+        Some(id)
+    }
+
+    fn spell(&mut self, id: u32, name: &str) -> u32 {
+        if self.spells.insert(id) {
+            self.tx.execute("INSERT OR REPLACE INTO spells (id, name) VALUES (?, ?)", params![id, name]).unwrap();
+        }
+        id
+    }
+
+    fn entry(&mut self, log: &Entry) {
+        use wow_combat_log::Entry::*;
+        let ts = secs(log.timestamp());
+        let (src, dst) = match log.base() {
+            Some(base) => (self.unit(base.src.id, base.src.name), self.unit(base.dst.id, base.dst.name)),
+            None => (None, None),
+        };
+        match *log {
+            Heal { ty, id, spell, hp, maxhp, heal, overheal, absorbed, crit, .. } => {
+                let spell = self.spell(id, spell);
+                self.tx.prepare_cached("INSERT INTO heals VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)").unwrap()
+                    .execute(params![self.log, self.encounter, ts, src, dst, spell, ty == HealType::Periodic,
+                                     heal as i64, overheal as i64, absorbed as i64, crit, hp as i64, maxhp as i64]).unwrap();
+            },
+            Damage { ty, id, spell, hp, maxhp, amount, overkill, school, resisted, blocked, absorbed, crit, glancing, crushing, .. } => {
+                let spell = self.spell(id, spell);
+                let ty = match ty {
+                    DamageType::Spell => "spell",
+                    DamageType::Periodic => "periodic",
+                    DamageType::Swing => "swing",
+                    DamageType::Range => "range",
+                };
+                let overkill = if overkill >= 0 { Some(overkill) } else { None };
+                self.tx.prepare_cached("INSERT INTO damage VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)").unwrap()
+                    .execute(params![self.log, self.encounter, ts, src, dst, spell, ty, amount as i64, overkill, school,
+                                     resisted as i64, blocked as i64, absorbed as i64, crit, glancing, crushing,
+                                     hp as i64, maxhp as i64]).unwrap();
+            },
+            Aura { ty, id, aura, buff, amount, .. } => {
+                let spell = self.spell(id, aura);
+                let ty = match ty {
+                    AuraType::Apply => "apply",
+                    AuraType::Refresh => "refresh",
+                    AuraType::Remove => "remove",
+                    AuraType::Stack => "stack",
+                    AuraType::Unstack => "unstack",
+                };
+                self.tx.prepare_cached("INSERT INTO auras VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)").unwrap()
+                    .execute(params![self.log, self.encounter, ts, src, dst, spell, ty, buff, amount.map(|x| x as i64)]).unwrap();
+            },
+            Info { id, strength, agi, sta, int, dodge, parry, block, critm, critr, crits, spd, steal,
+                   hastem, hastr, hastes, avd, mastery, versm, versr, verss, armor,
+                   talents, artifact, gear, ref auras, .. } => {
+                let unit = self.unit(id, "nil");
+                self.tx.prepare_cached("INSERT INTO combatant_info VALUES (NULL, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+                                        ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)").unwrap()
+                    .execute(params![self.log, self.encounter, ts, unit, strength, agi, sta, int, dodge, parry, block,
+                                     critm, critr, crits, spd, steal, hastem, hastr, hastes, avd, mastery,
+                                     versm, versr, verss, armor, talents, artifact, gear]).unwrap();
+                let info = self.tx.last_insert_rowid();
+                for &(src, aura) in auras {
+                    let src = self.unit(src, "nil");
+                    self.tx.prepare_cached("INSERT INTO combatant_auras VALUES (?, ?, ?)").unwrap()
+                        .execute(params![info, src, aura]).unwrap();
+                }
+            },
+            EncounterStart { name, id, difficulty, size, instance, .. } => {
+                self.tx.execute("INSERT INTO encounters (log, encounter_id, name, difficulty, size, instance, start)
+                                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+                                params![self.log, id, name, difficulty, size, instance, ts]).unwrap();
+                self.encounter = Some(self.tx.last_insert_rowid());
+            },
+            EncounterEnd { kill, .. } => {
+                if let Some(encounter) = self.encounter.take() {
+                    self.tx.execute("UPDATE encounters SET end = ?, kill = ? WHERE id = ?",
+                                    params![ts, kill, encounter]).unwrap();
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Add a log to the database, returning the number of events in it.
+/// Each log is one transaction, so a log that fails to load isn't half
+/// there.
+fn load<'a, I: Iterator<Item=Entry<'a>>>(conn: &mut Connection, input: &str, start: Option<NaiveDateTime>, iter: I) -> usize {
+    let tx = conn.transaction().unwrap();
+    tx.execute("INSERT INTO logs (source, start) VALUES (?, ?)",
+               params![input, start.map(|t| t.to_string())]).unwrap();
+    let mut loader = Loader { tx: &tx, log: tx.last_insert_rowid(), encounter: None,
+                              units: HashMap::new(), spells: HashSet::new() };
+    let mut n = 0;
+    for log in iter {
+        loader.entry(&log);
+        n += 1;
+    }
+    tx.commit().unwrap();
+    n
+}
+
+fn main() {
+    let app = App::new("sqlite export");
+    let app = if cfg!(feature = "wcl") {
+        app.arg(Arg::with_name("API key").long("wcl").takes_value(true).help("warcraftlogs API key"))
+    } else {
+        app
+    };
+    let matches = app
+        .arg(Arg::with_name("Database").required(true).help("SQLite database to create or add to"))
+        .arg(Arg::with_name("File/WCL ID").required(true).multiple(true)
+             .help("Log files (- for stdin), binary logs or WCL log IDs to load"))
+        .get_matches();
+
+    let mut conn = Connection::open(matches.value_of("Database").unwrap()).unwrap();
+    conn.execute_batch(SCHEMA).unwrap();
+    for input in matches.values_of("File/WCL ID").unwrap() {
+        let source = EventSource::new(input, matches.value_of("API key"));
+        let intern = wow_combat_log::Interner::default();
+        let iter = source.open(&intern, None, false).unwrap();
+        let start = iter.start_time();
+        let n = load(&mut conn, input, start, iter);
+        eprintln!("{}: {} events", input, n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static LOG: &str = r#"4/7 21:13:58.541  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:58.600  COMBATANT_INFO,Player-3674-06D6B01E,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,11234,2468,2468,2468,1918,105,(18569,102351,0,197061,0,197073,155675),(0,0,0,0),[(128306,1,(),(),())],[(147143,915,(),(3562,1497),())],[Player-3674-06D6B01E,774]
+4/7 21:13:59.003  SPELL_HEAL,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,8936,"Regrowth",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,200000,50000,0,1
+4/7 21:13:59.300  SPELL_AURA_REMOVED,0000000000000000,nil,0x80000000,0x80000000,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,1234,"Debuff, Nasty",0x20,DEBUFF
+4/7 21:14:20.500  SPELL_PERIODIC_DAMAGE,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,8921,"Moonfire",0x40,Creature-0-3019-1676-22336-115844-00006814F0,0000000000000000,89960000,100000000,0,0,0,0,0,0,6213.56,-842.09,0,12000,0,64,0,0,0,nil,nil,nil,nil
+4/7 21:14:58.541  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), params![], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn loads_logs() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        let intern = wow_combat_log::Interner::default();
+        for _ in 0..2 {
+            assert_eq!(load(&mut conn, "test", None, wow_combat_log::iter(&intern, LOG.as_bytes())), 6);
+        }
+        assert_eq!([count(&conn, "logs"), count(&conn, "encounters"), count(&conn, "heals"), count(&conn, "damage"),
+                    count(&conn, "auras"), count(&conn, "combatant_info"), count(&conn, "combatant_auras")],
+                   [2, 2, 2, 2, 2, 2, 2]);
+        // units and spells are shared between logs, and the name seen after COMBATANT_INFO is filled in
+        assert_eq!(count(&conn, "units"), 3);
+        assert_eq!(count(&conn, "spells"), 3);
+        let unit: String = conn.query_row("SELECT u.name FROM combatant_info c JOIN units u ON c.unit = u.id LIMIT 1",
+                                          params![], |row| row.get(0)).unwrap();
+        assert_eq!(unit, "Druidy-Realm");
+
+        let heal: (String, String, String, i64, i64, bool, f64, i64) = conn.query_row(
+            "SELECT src.name, dst.name, s.name, h.amount, h.overheal, h.crit, h.ts, e.log
+             FROM heals h JOIN units src ON h.source = src.id JOIN units dst ON h.target = dst.id
+             JOIN spells s ON h.spell = s.id JOIN encounters e ON h.encounter = e.id ORDER BY h.log DESC LIMIT 1",
+            params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?, row.get(7)?))).unwrap();
+        assert_eq!(heal, ("Druidy-Realm".to_owned(), "Tanky-Realm".to_owned(), "Regrowth".to_owned(), 200000, 50000, true, 0.462, 2));
+
+        let encounter: (String, f64, f64, bool) = conn.query_row(
+            "SELECT name, start, end, kill FROM encounters WHERE log = 1",
+            params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))).unwrap();
+        assert_eq!(encounter, ("Goroth".to_owned(), 0., 60., true));
+
+        let aura: (Option<i64>, String, bool) = conn.query_row("SELECT source, type, buff FROM auras LIMIT 1",
+                                                               params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap();
+        assert_eq!(aura, (None, "remove".to_owned(), false));
+        let damage: (String, i64, Option<i64>) = conn.query_row("SELECT type, amount, overkill FROM damage LIMIT 1",
+                                                                params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap();
+        assert_eq!(damage, ("periodic".to_owned(), 12000, Some(0)));
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use chrono::Duration;
+
+use {Entry, AuraTracker};
+use report::Analysis;
+use spells::SpellDb;
+
+pub const SPEC_DISC_PRIEST: u32 = 256;
+pub const AURA_ATONEMENT: u32 = 194384;
+
+/// How long after a damage event an atonement heal can still be from it;
+/// they're normally logged at the same time
+pub fn atonement_window() -> Duration {
+    Duration::milliseconds(100)
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+struct DamageSpell {
+    damage: u64,
+    atonement: u64,
+}
+
+/// Discipline priest Atonement, whose heals on each target with the
+/// Atonement buff come from the priest's damage, and their pets'. Each
+/// heal goes to the last damage event before it.
+#[derive(Default, Debug, Clone)]
+pub struct DiscPriestComputation<'a> {
+    auras: AuraTracker<'a>,
+    player_id: &'a str,
+    // the player's pets (Shadowfiend, Mindbender), whose damage is theirs
+    pets: HashSet<&'a str>,
+    // the heals Atonement does
+    heals: Vec<u32>,
+    // spell and time of the last damage event
+    last_damage: Option<(u32, Duration)>,
+    names: HashMap<u32, &'a str>,
+    total_healing: u64,
+    atonement_healing: u64,
+    // atonement heals with no damage just before them
+    unattributed_healing: u64,
+    // atonement heals on targets the buff wasn't seen on
+    untracked_healing: u64,
+    damage_events: u64,
+    // summed over damage events
+    atonements_active: u64,
+    by_spell: HashMap<u32, DamageSpell>,
+}
+
+impl<'a> DiscPriestComputation<'a> {
+    pub fn new(player_id: &'a str, db: &SpellDb) -> Self {
+        DiscPriestComputation {
+            player_id,
+            heals: db.tagged(SPEC_DISC_PRIEST, "atonement"),
+            ..Default::default()
+        }
+    }
+
+    /// How many targets have the player's Atonement
+    pub fn atonements(&self) -> usize {
+        self.auras.all().into_iter()
+            .filter(|&(_, src, spell, _)| src == self.player_id && spell == AURA_ATONEMENT)
+            .count()
+    }
+
+    pub fn parse_entry(&mut self, log: &Entry<'a>, filter_start_time: Duration) {
+        self.auras.update(log);
+        if let Some(adv) = log.adv() {
+            if adv.owner == self.player_id {
+                self.pets.insert(adv.id);
+            }
+        }
+        let counted = log.timestamp() >= filter_start_time;
+        match *log {
+            Entry::Damage { ref base, id, spell, amount, .. }
+                if base.src.id == self.player_id || self.pets.contains(base.src.id) => {
+                self.last_damage = Some((id, log.timestamp()));
+                self.names.insert(id, spell);
+                if counted {
+                    self.damage_events += 1;
+                    self.atonements_active += self.atonements() as u64;
+                    self.by_spell.entry(id).or_default().damage += amount;
+                }
+            },
+            Entry::Heal { ref base, id, heal, overheal, .. } if base.src.id == self.player_id && counted => {
+                let heal = heal - overheal;
+                self.total_healing += heal;
+                if !self.heals.contains(&id) {
+                    return;
+                }
+                self.atonement_healing += heal;
+                if !self.auras.has(base.dst.id, self.player_id, AURA_ATONEMENT) {
+                    self.untracked_healing += heal;
+                }
+                match self.last_damage {
+                    Some((spell, ts)) if log.timestamp() - ts <= atonement_window() =>
+                        self.by_spell.entry(spell).or_default().atonement += heal,
+                    _ => self.unattributed_healing += heal,
+                }
+            },
+            _ => (),
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        let prev = ::std::mem::take(self);
+        *self = DiscPriestComputation {
+            auras: prev.auras,
+            player_id: prev.player_id,
+            pets: prev.pets,
+            heals: prev.heals,
+            last_damage: prev.last_damage,
+            names: prev.names,
+            ..Default::default()
+        }
+    }
+}
+
+impl<'a> Analysis<'a> for DiscPriestComputation<'a> {
+    fn parse_entry(&mut self, log: &Entry<'a>, start: Duration) {
+        DiscPriestComputation::parse_entry(self, log, start)
+    }
+
+    fn reset_stats(&mut self) {
+        DiscPriestComputation::reset_stats(self)
+    }
+
+    fn subtract(&mut self, rhs: &Self) {
+        self.total_healing -= rhs.total_healing;
+        self.atonement_healing -= rhs.atonement_healing;
+        self.unattributed_healing -= rhs.unattributed_healing;
+        self.untracked_healing -= rhs.untracked_healing;
+        self.damage_events -= rhs.damage_events;
+        self.atonements_active -= rhs.atonements_active;
+        for (spell, x) in rhs.by_spell.iter() {
+            let y = self.by_spell.entry(*spell).or_default();
+            y.damage -= x.damage;
+            y.atonement -= x.atonement;
+        }
+    }
+}
+
+impl<'a> fmt::Display for DiscPriestComputation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // 0 rather than NaN for a pull with no healing or damage
+        let frac = |a: u64, b: u64| if b != 0 { a as f64 / b as f64 } else { 0. };
+        writeln!(f, "atonement healing: {:.6}; average atonements: {:.2}; unattributed: {:.6}; untracked target: {:.6};",
+               frac(self.atonement_healing, self.total_healing),
+               frac(self.atonements_active, self.damage_events),
+               frac(self.unattributed_healing, self.atonement_healing),
+               frac(self.untracked_healing, self.atonement_healing))?;
+        // the share of atonement healing, and healing per point of damage
+        write!(f, "Atonement healing from: ")?;
+        let mut by = self.by_spell.iter().filter(|&(_, x)| x.atonement != 0).collect::<Vec<_>>();
+        by.sort_by_key(|x| ::std::cmp::Reverse(x.1.atonement));
+        for (spell, x) in by {
+            write!(f, "{}: {:.6} ", self.names[spell], frac(x.atonement, self.atonement_healing))?;
+            if x.damage != 0 {
+                write!(f, "({:.2}/dmg),  ", frac(x.atonement, x.damage))?;
+            } else {
+                write!(f, "(no dmg),  ")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Interner, iter};
+
+    // atonement on two targets for a smite and the mindbender's melee,
+    // then one on a target without it too long after any damage
+    static LOG: &str = r#"4/7 21:13:57.000  SPELL_AURA_APPLIED,Player-3674-0EEEEEEE,"Disc-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,194384,"Atonement",0x2,BUFF
+4/7 21:13:57.500  SPELL_AURA_APPLIED,Player-3674-0EEEEEEE,"Disc-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x512,0x0,194384,"Atonement",0x2,BUFF
+4/7 21:13:58.000  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:14:00.000  SPELL_DAMAGE,Player-3674-0EEEEEEE,"Disc-Realm",0x511,0x0,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,585,"Smite",0x2,Creature-0-3019-1676-22336-115844-00006814F0,0000000000000000,89960000,100000000,0,0,0,0,0,0,6213.56,-842.09,0,50000,0,2,0,0,0,nil,nil,nil,nil
+4/7 21:14:00.000  SPELL_HEAL,Player-3674-0EEEEEEE,"Disc-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,81751,"Atonement",0x2,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,20000,0,0,nil
+4/7 21:14:00.050  SPELL_HEAL,Player-3674-0EEEEEEE,"Disc-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x512,0x0,81751,"Atonement",0x2,Player-3674-06D6B01E,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,15000,5000,0,nil
+4/7 21:14:01.000  SPELL_DAMAGE,Pet-0-3019-1676-22336-62982-0000123456,"Mindbender",0x1111,0x0,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,262861,"Mindbender",0x20,Pet-0-3019-1676-22336-62982-0000123456,Player-3674-0EEEEEEE,100000,100000,0,0,0,0,0,0,6213.56,-842.09,0,30000,0,32,0,0,0,nil,nil,nil,nil
+4/7 21:14:01.000  SPELL_HEAL,Player-3674-0EEEEEEE,"Disc-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,81751,"Atonement",0x2,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,12000,0,0,nil
+4/7 21:14:06.000  SPELL_HEAL,Player-3674-0EEEEEEE,"Disc-Realm",0x511,0x0,Player-3674-0BBBBBBB,"Healy-Realm",0x512,0x0,81751,"Atonement",0x2,Player-3674-0BBBBBBB,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,3000,0,0,nil
+4/7 21:14:07.000  SPELL_HEAL,Player-3674-0EEEEEEE,"Disc-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,2061,"Flash Heal",0x2,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,8000,0,0,nil
+4/7 21:14:30.000  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    #[test]
+    fn atonement() {
+        let intern = Interner::default();
+        let mut comp = DiscPriestComputation::new("Player-3674-0EEEEEEE", &SpellDb::builtin());
+        for log in iter(&intern, LOG.as_bytes()) {
+            comp.parse_entry(&log, Duration::zero());
+        }
+        assert_eq!(comp.atonements(), 2);
+        assert_eq!(format!("{}", comp), "\
+atonement healing: 0.849057; average atonements: 2.00; unattributed: 0.066667; untracked target: 0.066667;
+Atonement healing from: Smite: 0.666667 (0.60/dmg),  Mindbender: 0.266667 (0.40/dmg),  ");
+
+        let mut none = comp.clone();
+        none.subtract(&comp);
+        assert_eq!(format!("{}", none), "\
+atonement healing: 0.000000; average atonements: 0.00; unattributed: 0.000000; untracked target: 0.000000;
+Atonement healing from: ");
+    }
+}
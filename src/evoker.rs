@@ -0,0 +1,53 @@
+use {Entry, AuraTracker};
+use healer::{MasteryModel, HealerComputation, health_before};
+
+pub const SPEC_PRESERVATION: u32 = 1468;
+
+/// Preservation evoker mastery (Life-Binder), which heals more the
+/// higher the target's health was, the other way around from Deep
+/// Healing. Echo's copies of heals are the same spells, so they're
+/// counted with them.
+#[derive(Default, Debug, Clone)]
+pub struct LifeBinder;
+
+impl<'a> MasteryModel<'a> for LifeBinder {
+    fn spec() -> u32 { SPEC_PRESERVATION }
+    fn name() -> &'static str { "preservation evoker" }
+    fn needs() -> &'static str { "health" }
+
+    fn effect(&self, log: &Entry<'a>, _: &AuraTracker<'a>, _: &'a str) -> Option<f64> {
+        health_before(log)
+    }
+}
+
+pub type EvokerComputation<'a> = HealerComputation<'a, LifeBinder>;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Interner;
+    use healer::tests::analyze;
+
+    // a living flame on the tank at 90.6% health and a reversion tick at 25%
+    static LOG: &str = r#"4/7 21:13:58.000  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:58.100  COMBATANT_INFO,Player-3674-0FFFFFFF,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,1800,2468,2468,2468,1918,1468,(0,0,0,0,0,0,0),(0,0,0,0),[],[(147143,915,(),(),())],[]
+4/7 21:14:00.000  SPELL_HEAL,Player-3674-0FFFFFFF,"Evoky-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,361509,"Living Flame",0x8,Player-3674-0AAAAAAA,0000000000000000,3200000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,400000,100000,0,nil
+4/7 21:14:01.000  SPELL_PERIODIC_HEAL,Player-3674-0FFFFFFF,"Evoky-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,366155,"Reversion",0x8,Player-3674-0AAAAAAA,0000000000000000,1000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,200000,0,0,nil
+4/7 21:14:30.000  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    #[test]
+    fn life_binder() {
+        let intern = Interner::default();
+        let comp = analyze::<LifeBinder>(&intern, LOG, 70);
+        assert_eq!(comp.total_healing(), 500000);
+        // 1.8% per point, at 1800 / 180 + 8 points
+        let m = 0.018 * (1800. / 180. + 8.);
+        assert!((comp.mastery() - m).abs() < 1e-9);
+        let (flame, reversion) = (300000. / (1. + 0.90625 * m), 200000. / (1. + 0.25 * m));
+        let frac = (0.90625 * flame + 0.25 * reversion) / (flame + reversion);
+        assert!((comp.mastery_frac() - frac).abs() < 1e-5, "{} {}", comp.mastery_frac(), frac);
+        assert!(format!("{}", comp).contains("mastery healing: 1.000000; unknown health: 0.000000;"));
+    }
+}
@@ -0,0 +1,492 @@
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+use chrono::Duration;
+use clap::{Arg, App, ArgMatches};
+
+use {Entry, AuraTracker, EventSource, Events, Interner};
+use report::{Analysis, report, find_init_info, find_player};
+use stats::{StatTable, LevelStats, BuffTable, Stat};
+use spells::{SpellDb, Interaction};
+
+/// The spells a healer analysis looks at
+#[derive(Default, Debug, Clone)]
+pub struct SpellTable {
+    /// heals mastery makes bigger
+    pub mastery_heals: Vec<u32>,
+    /// heals that only happen because of mastery, so all of their
+    /// healing is from it
+    pub mastery_procs: Vec<u32>,
+}
+
+impl SpellTable {
+    /// `spec`'s spells with mastery or proc in the database
+    pub fn for_spec(db: &SpellDb, spec: u32) -> Self {
+        SpellTable {
+            mastery_heals: db.with(spec, Interaction::Mastery),
+            mastery_procs: db.with(spec, Interaction::Proc),
+        }
+    }
+}
+
+/// How a spec's mastery changes its heals. `HealerComputation` does the
+/// rest.
+pub trait MasteryModel<'a>: Clone + Default + fmt::Debug {
+    /// The spec id in COMBATANT_INFO
+    fn spec() -> u32;
+    /// The spec's name, for messages
+    fn name() -> &'static str;
+    fn spells(db: &SpellDb) -> SpellTable {
+        SpellTable::for_spec(db, Self::spec())
+    }
+    /// Set up, with whatever it needs from the database
+    fn new(_db: &SpellDb) -> Self {
+        Default::default()
+    }
+    /// What it needs to know to tell how much mastery a heal got, for
+    /// "unknown ..." in the report
+    fn needs() -> &'static str;
+    /// Whether it needs events that aren't the player's
+    fn all_events() -> bool { false }
+    /// Take in any event, for whatever state it keeps
+    fn update(&mut self, _log: &Entry<'a>, _auras: &AuraTracker<'a>, _player_id: &'a str) {}
+    /// The fraction of full mastery one of the player's heals in
+    /// `mastery_heals` got (more than 1 if it stacks), if it can be told
+    fn effect(&self, log: &Entry<'a>, auras: &AuraTracker<'a>, player_id: &'a str) -> Option<f64>;
+    /// `effect`, and the mastery % (as a fraction, at full effect) to
+    /// go with it, for every one of the player's heals in
+    /// `mastery_heals`. By default that's their `mastery` now; a model
+    /// can give another, as for heals that copy an earlier one.
+    fn heal_mastery(&mut self, log: &Entry<'a>, auras: &AuraTracker<'a>, player_id: &'a str, mastery: f64) -> Option<(f64, f64)> {
+        self.effect(log, auras, player_id).map(|effect| (effect, mastery))
+    }
+    /// Each of the player's heals and damage events, for numbers of the
+    /// model's own, which it only counts if `counted`. `split` is the
+    /// no-mastery healing, effect and mastery of a heal that had them
+    /// worked out. It can return no-mastery healing (and that weighted
+    /// by effect) that a heal gets from others, like leech.
+    fn player_event(&mut self, _log: &Entry<'a>, _auras: &AuraTracker<'a>, _player_id: &'a str, _counted: bool,
+                    _split: Option<(u64, f64, f64)>) -> Option<(u64, f64)> {
+        None
+    }
+    /// Zero the model's own numbers, for `HealerComputation::reset_stats`
+    fn reset_stats(&mut self) {}
+    /// Take away the model's own numbers in `rhs`
+    fn subtract(&mut self, _rhs: &Self) {}
+    /// Write `comp`'s report; by default the numbers every healer has
+    fn report(comp: &HealerComputation<'a, Self>, f: &mut fmt::Formatter) -> fmt::Result {
+        comp.write_mastery(f)
+    }
+}
+
+/// The target's health before a heal, as a fraction, from the health
+/// after it in the advanced logging fields
+pub fn health_before(log: &Entry) -> Option<f64> {
+    match *log {
+        Entry::Heal { ref base, hp, maxhp, heal, overheal, ref adv, .. } if adv.id == base.dst.id && maxhp != 0 => {
+            let before = hp.saturating_sub(heal - overheal);
+            Some((before as f64 / maxhp as f64).min(1.))
+        },
+        _ => None,
+    }
+}
+
+/// A healer's mastery over a log, with the spec's part done by `M`
+#[derive(Default, Debug, Clone)]
+pub struct HealerComputation<'a, M> {
+    model: M,
+    spells: Rc<SpellTable>,
+    auras: AuraTracker<'a>,
+    player_id: &'a str,
+    // without the buffs in `buffs`
+    mastery_rating: u32,
+    stats: Rc<LevelStats>,
+    buffs: Rc<BuffTable>,
+    total_healing: u64,
+    // of the heals mastery applies to
+    mastery_base_healing: u64,
+    total_unmastery_healing: u64,
+    // no-mastery healing weighted by mastery effect
+    effect_unmast: f64,
+    mastery_added: u64,
+    proc_healing: u64,
+    // mastery heals where the effect couldn't be told
+    unknown_healing: u64,
+}
+
+impl<'a, M: MasteryModel<'a>> HealerComputation<'a, M> {
+    pub fn new(player_id: &'a str, info: &Entry<'a>, stats: Rc<LevelStats>, buffs: Rc<BuffTable>, db: &SpellDb) -> Self {
+        HealerComputation::with_model(M::new(db), player_id, info, stats, buffs, db)
+    }
+
+    /// `new`, with a model that needs more than the spell database
+    pub fn with_model(model: M, player_id: &'a str, info: &Entry<'a>, stats: Rc<LevelStats>, buffs: Rc<BuffTable>, db: &SpellDb) -> Self {
+        let mut comp = HealerComputation {
+            model,
+            spells: Rc::new(M::spells(db)),
+            player_id, stats, buffs,
+            ..Default::default()
+        };
+        comp.parse_entry(info, Duration::max_value());
+        comp
+    }
+
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    /// The player's healing, without overhealing
+    pub fn total_healing(&self) -> u64 {
+        self.total_healing
+    }
+
+    /// The average fraction of full mastery heals got, weighted by
+    /// their no-mastery healing
+    pub fn mastery_frac(&self) -> f64 {
+        self.effect_unmast / self.total_unmastery_healing as f64
+    }
+
+    /// Mastery % (as a fraction) at full effect, with current buffs
+    pub fn mastery(&self) -> f64 {
+        let auras = self.auras.auras_on(self.player_id).into_iter()
+            .map(|(_, spell, aura)| (spell, aura.stacks)).collect::<Vec<_>>();
+        let rating = self.buffs.rating(Stat::Mastery, self.mastery_rating, auras.iter().cloned());
+        let (_, percent) = self.buffs.totals(Stat::Mastery, auras.iter().cloned());
+        self.stats.mastery[&M::spec()] * self.stats.buffed_percent(Stat::Mastery, rating, &percent) / 100.
+    }
+
+    pub fn parse_entry(&mut self, log: &Entry<'a>, filter_start_time: Duration) {
+        self.auras.update(log);
+        self.model.update(log, &self.auras, self.player_id);
+        if let Entry::Info { id, mastery, .. } = *log {
+            if id == self.player_id {
+                // it includes the buffs the player had at the time
+                let auras = self.auras.auras_on(id).into_iter().map(|(_, spell, aura)| (spell, aura.stacks)).collect::<Vec<_>>();
+                self.mastery_rating = self.buffs.unbuffed(Stat::Mastery, mastery, auras);
+            }
+        }
+
+        let counted = log.timestamp() >= filter_start_time;
+        let (base, id, total_heal, overheal) = match *log {
+            Entry::Heal { ref base, id, heal, overheal, .. } => (base, id, heal, overheal),
+            Entry::Damage { ref base, .. } if base.src.id == self.player_id => {
+                self.model.player_event(log, &self.auras, self.player_id, counted, None);
+                return;
+            },
+            _ => return,
+        };
+        if base.src.id != self.player_id {
+            return;
+        }
+        let heal = total_heal - overheal;
+        // worked out for heals that aren't counted too, for models
+        // that keep track of them
+        let effect = if self.spells.mastery_heals.contains(&id) {
+            let mastery = self.mastery();
+            self.model.heal_mastery(log, &self.auras, self.player_id, mastery)
+        } else {
+            None
+        };
+        let split = effect.map(|(effect, mastery)| ((heal as f64 / (1. + mastery * effect)) as u64, effect, mastery));
+        let credit = self.model.player_event(log, &self.auras, self.player_id, counted, split);
+        if !counted {
+            return;
+        }
+        self.total_healing += heal;
+        if let Some((unmast, effect_unmast)) = credit {
+            self.total_unmastery_healing += unmast;
+            self.effect_unmast += effect_unmast;
+        }
+        if self.spells.mastery_procs.contains(&id) {
+            self.proc_healing += heal;
+            return;
+        }
+        if !self.spells.mastery_heals.contains(&id) {
+            return;
+        }
+        self.mastery_base_healing += heal;
+        let (unmast, effect, _) = match split {
+            Some(x) => x,
+            None => {
+                self.unknown_healing += heal;
+                return;
+            },
+        };
+        self.total_unmastery_healing += unmast;
+        self.effect_unmast += unmast as f64 * effect;
+        self.mastery_added += heal - unmast;
+    }
+
+    pub fn reset_stats(&mut self) {
+        let prev = ::std::mem::take(self);
+        *self = HealerComputation {
+            model: prev.model,
+            spells: prev.spells,
+            auras: prev.auras,
+            player_id: prev.player_id,
+            mastery_rating: prev.mastery_rating,
+            stats: prev.stats,
+            buffs: prev.buffs,
+            ..Default::default()
+        };
+        self.model.reset_stats();
+    }
+}
+
+impl<'a, M: MasteryModel<'a>> Analysis<'a> for HealerComputation<'a, M> {
+    fn parse_entry(&mut self, log: &Entry<'a>, start: Duration) {
+        HealerComputation::parse_entry(self, log, start)
+    }
+
+    fn reset_stats(&mut self) {
+        HealerComputation::reset_stats(self)
+    }
+
+    fn subtract(&mut self, rhs: &Self) {
+        self.total_healing -= rhs.total_healing;
+        self.mastery_base_healing -= rhs.mastery_base_healing;
+        self.total_unmastery_healing -= rhs.total_unmastery_healing;
+        self.effect_unmast -= rhs.effect_unmast;
+        self.mastery_added -= rhs.mastery_added;
+        self.proc_healing -= rhs.proc_healing;
+        self.unknown_healing -= rhs.unknown_healing;
+        self.model.subtract(&rhs.model);
+    }
+}
+
+impl<'a, M: MasteryModel<'a>> fmt::Display for HealerComputation<'a, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        M::report(self, f)
+    }
+}
+
+impl<'a, M: MasteryModel<'a>> HealerComputation<'a, M> {
+    /// The report's numbers that every healer has
+    pub fn write_mastery(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total_healing as f64;
+        let scaled = !self.spells.mastery_heals.is_empty();
+        if scaled {
+            // the average fraction of full mastery heals got, which is
+            // what mastery's weight scales with
+            write!(f, "scale_mastery_frac: {:.6}; mastery_effect: {:.2}%; ",
+                   self.effect_unmast / self.total_unmastery_healing as f64,
+                   100. * self.mastery_added as f64 / self.total_unmastery_healing as f64)?;
+        }
+        write!(f, "scale_mastery_added: {:.6};\n  mastery healing: {:.6};",
+               (self.mastery_added + self.proc_healing) as f64 / total,
+               (self.mastery_base_healing + self.proc_healing) as f64 / total)?;
+        if !self.spells.mastery_procs.is_empty() {
+            write!(f, " mastery procs: {:.6};", self.proc_healing as f64 / total)?;
+        }
+        if scaled {
+            write!(f, " unknown {}: {:.6};", M::needs(), self.unknown_healing as f64 / total)?;
+        }
+        Ok(())
+    }
+}
+
+/// The command line of a healer mastery binary
+pub struct HealerOptions {
+    pub start: Duration,
+    pub end: Duration,
+    /// None with --all, in binaries that have it
+    pub player: Option<String>,
+    pub source: EventSource,
+    pub stats: StatTable,
+    pub buffs: BuffTable,
+    pub spells: SpellDb,
+    /// from --level, otherwise worked out from the player's gear
+    pub level: Option<u32>,
+}
+
+impl HealerOptions {
+    pub fn from_args(name: &str) -> Self {
+        HealerOptions::from_matches(&HealerOptions::app(name).get_matches())
+    }
+
+    /// The arguments every healer binary takes, for ones that add more
+    /// of their own. The player isn't needed with an `All` argument.
+    pub fn app<'a, 'b>(name: &str) -> App<'a, 'b> {
+        let app = App::new(name);
+        let app = if cfg!(feature = "wcl") {
+            app.arg(Arg::with_name("API key").long("wcl").takes_value(true).help("warcraftlogs API key"))
+        } else {
+            app
+        };
+        app
+            .arg(Arg::with_name("File/WCL ID").required(true).help("Log file (- for stdin) or WCL log ID"))
+            .arg(Arg::with_name("Player").required_unless("All").help("Player name (as reported in log)"))
+            .arg(Arg::with_name("Start").long("start").takes_value(true).help("Start time in seconds from start of log"))
+            .arg(Arg::with_name("End").long("end").takes_value(true).help("End time in seconds from start of log"))
+            .arg(Arg::with_name("Stats").long("stats").takes_value(true)
+                 .help("Stat conversion table to use instead of the built-in one"))
+            .arg(Arg::with_name("Buffs").long("buffs").takes_value(true)
+                 .help("Aura stat buff table to use instead of the built-in one"))
+            .arg(Arg::with_name("Spells").long("spells").takes_value(true)
+                 .help("Spell database entries to add to or replace the built-in ones"))
+            .arg(Arg::with_name("Level").long("level").takes_value(true)
+                 .help("Character level, for stat conversions (by default from their item level)"))
+    }
+
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        HealerOptions {
+            start: matches.value_of("Start").map(|x| Duration::seconds(x.parse().unwrap())).unwrap_or(Duration::zero()),
+            end: matches.value_of("End").map(|x| Duration::seconds(x.parse().unwrap())).unwrap_or(Duration::max_value()),
+            player: matches.value_of("Player").map(|x| x.to_string()),
+            source: EventSource::new(matches.value_of("File/WCL ID").unwrap(), matches.value_of("API key")),
+            stats: match matches.value_of("Stats") {
+                Some(path) => StatTable::load(Path::new(path)).unwrap(),
+                None => StatTable::builtin(),
+            },
+            buffs: match matches.value_of("Buffs") {
+                Some(path) => BuffTable::load(Path::new(path)).unwrap(),
+                None => BuffTable::builtin(),
+            },
+            spells: SpellDb::with_overrides(matches.value_of("Spells").map(Path::new)).unwrap(),
+            level: matches.value_of("Level").map(|x| x.parse().unwrap()),
+        }
+    }
+
+    fn player(&self) -> &str {
+        self.player.as_ref().expect("no player given")
+    }
+
+    /// Open the log, exiting if it can't be
+    pub fn open<'a>(&self, intern: &'a Interner, actor: Option<&str>) -> Events<'a> {
+        match self.source.open(intern, actor, false) {
+            Ok(events) => events,
+            Err(e) => {
+                eprintln!("Can't read the log: {}", e);
+                ::std::process::exit(1);
+            },
+        }
+    }
+
+    /// The stat conversions for the player in COMBATANT_INFO `info`,
+    /// at --level or the level their gear is for, exiting if they aren't
+    /// known (or there's no mastery coefficient for `spec`, unless
+    /// `any_spec`)
+    pub fn level_stats(&self, info: &Entry, spec: u32, name: &str, any_spec: bool) -> LevelStats {
+        let level = match self.level.or_else(|| self.stats.level_of(info)) {
+            Some(level) => level,
+            None => {
+                eprintln!("Can't tell the player's level from their gear, give it with --level");
+                ::std::process::exit(1);
+            },
+        };
+        match self.stats.level(level) {
+            Some(stats) if any_spec || stats.mastery_percent(spec, 0).is_some() => stats.clone(),
+            _ => {
+                eprintln!("No {} mastery conversion for level {} in the stat table", name, level);
+                ::std::process::exit(1);
+            },
+        }
+    }
+
+    /// Run the analysis for `M` and print the report, exiting if it
+    /// can't be done
+    pub fn run<'a, M: MasteryModel<'a>>(&self, intern: &'a Interner) {
+        let db = &self.spells;
+        self.run_with(intern, |_, _| M::new(db));
+    }
+
+    /// `run`, with the model made by `model` from the player's
+    /// COMBATANT_INFO and stat conversions
+    pub fn run_with<'a, M, F>(&self, intern: &'a Interner, model: F)
+        where M: MasteryModel<'a>, F: FnOnce(&Entry<'a>, &Rc<LevelStats>) -> M {
+        let player = self.player();
+        let actor = if M::all_events() { None } else { Some(player) };
+        let mut iter = self.open(intern, actor).take_while(|x| x.timestamp() < self.end);
+        let mut pending = vec![];
+        let (pid, info) = match find_init_info(&mut iter, player, &mut pending) {
+            Some(x) => x,
+            None => {
+                eprintln!("No COMBATANT_INFO for {} in the log, so their mastery rating isn't known", player);
+                eprintln!("(it's only logged at the start of boss encounters with advanced combat logging on)");
+                ::std::process::exit(1);
+            },
+        };
+        let stats = Rc::new(self.level_stats(&info, M::spec(), M::name(), M::spells(&self.spells).mastery_heals.is_empty()));
+        let model = model(&info, &stats);
+        let iter = pending.into_iter().chain(iter);
+        report(iter, self.start, HealerComputation::with_model(model, pid, &info, stats, Rc::new(self.buffs.clone()), &self.spells));
+    }
+
+    /// Run an analysis that only needs the player's guid, made by
+    /// `analysis`, and print the report, exiting if they aren't in the
+    /// log. It sees everyone's events if `all_events`.
+    pub fn run_analysis<'a, A, F>(&self, intern: &'a Interner, all_events: bool, analysis: F)
+        where A: Analysis<'a>, F: FnOnce(&'a str, &SpellDb) -> A {
+        let player = self.player();
+        let actor = if all_events { None } else { Some(player) };
+        let mut iter = self.open(intern, actor).take_while(|x| x.timestamp() < self.end);
+        let mut pending = vec![];
+        let pid = match find_player(&mut iter, player, &mut pending) {
+            Some(x) => x,
+            None => {
+                eprintln!("{} isn't in the log", player);
+                ::std::process::exit(1);
+            },
+        };
+        let iter = pending.into_iter().chain(iter);
+        report(iter, self.start, analysis(pid, &self.spells));
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::rc::Rc;
+    use {Entry, Interner, iter};
+    use stats::{StatTable, BuffTable};
+    use spells::SpellDb;
+    use super::{MasteryModel, HealerComputation};
+
+    /// `M` run over `log` at `level`, for the player in its first
+    /// COMBATANT_INFO
+    pub fn analyze<'a, M: MasteryModel<'a>>(intern: &'a Interner, log: &str, level: u32) -> HealerComputation<'a, M> {
+        let db = SpellDb::builtin();
+        let logs = iter(intern, log.as_bytes()).collect::<Vec<_>>();
+        let info = logs.iter().find(|x| matches!(**x, Entry::Info { .. })).unwrap();
+        let id = match *info {
+            Entry::Info { id, .. } => id,
+            _ => unreachable!(),
+        };
+        let stats = Rc::new(StatTable::builtin().level(level).unwrap().clone());
+        let mut comp = HealerComputation::new(id, info, stats, Rc::new(BuffTable::builtin()), &db);
+        for log in &logs {
+            comp.parse_entry(log, ::chrono::Duration::zero());
+        }
+        comp
+    }
+
+    // the same healing wave at 25% health with the 2pc's 4000 mastery
+    // rating, which COMBATANT_INFO includes, and after it's gone
+    static LOG: &str = r#"4/7 21:13:58.000  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:58.100  COMBATANT_INFO,Player-3674-0CCCCCCC,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,15234,2468,2468,2468,1918,264,(0,0,0,0,0,0,0),(0,0,0,0),[],[(147143,915,(),(),())],[Player-3674-0CCCCCCC,232378]
+4/7 21:14:00.000  SPELL_HEAL,Player-3674-0CCCCCCC,"Shammy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,77472,"Healing Wave",0x8,Player-3674-0AAAAAAA,0000000000000000,1600000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,800000,0,0,nil
+4/7 21:14:01.000  SPELL_AURA_REMOVED,Player-3674-0CCCCCCC,"Shammy-Realm",0x511,0x0,Player-3674-0CCCCCCC,"Shammy-Realm",0x511,0x0,232378,"Astral Warden",0x8,BUFF
+4/7 21:14:02.000  SPELL_HEAL,Player-3674-0CCCCCCC,"Shammy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,77472,"Healing Wave",0x8,Player-3674-0AAAAAAA,0000000000000000,1600000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,800000,0,0,nil
+4/7 21:14:30.000  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    #[test]
+    fn mastery_rating_without_buffs() {
+        use resto_shaman::DeepHealing;
+        let intern = Interner::default();
+        let comp = analyze::<DeepHealing>(&intern, LOG, 110);
+        let m = |rating: f64| 0.03 * (rating / 400. + 8.);
+        assert!((comp.mastery() - m(11234.)).abs() < 1e-9);
+        let (buffed, plain) = (800000. / (1. + 0.75 * m(15234.)), 800000. / (1. + 0.75 * m(11234.)));
+        assert!((comp.mastery_frac() - 0.75).abs() < 1e-9);
+        let report = format!("{}", comp);
+        assert!(report.contains(&format!("mastery_effect: {:.2}%;", 100. * (1600000. - buffed - plain) / (buffed + plain))), "{}", report);
+    }
+
+    #[test]
+    fn health_before_heals() {
+        let intern = Interner::default();
+        let logs = iter(&intern, LOG.as_bytes()).collect::<Vec<_>>();
+        assert_eq!(super::health_before(&logs[2]), Some(0.25));
+        assert_eq!(super::health_before(&logs[0]), None);
+    }
+}
@@ -0,0 +1,122 @@
+use chrono::Duration;
+
+use {Entry, AuraTracker, PositionTracker};
+use healer::{MasteryModel, HealerComputation};
+
+pub const SPEC_HOLY_PALADIN: u32 = 65;
+
+const AURA_BEACON: u32 = 53563;
+const AURA_RULE_OF_LAW: u32 = 214202;
+// the talent's passive, which COMBATANT_INFO lists with the auras
+const AURA_BEACON_OF_THE_LIGHTBRINGER: u32 = 197446;
+
+// full effect within this many yards, none past the far one
+const FULL_RANGE: f64 = 10.;
+const MAX_RANGE: f64 = 40.;
+
+/// How long a position is trusted for
+pub fn max_position_age() -> Duration {
+    Duration::seconds(5)
+}
+
+/// The fraction of full mastery a heal gets at `distance` yards
+pub fn effectiveness(distance: f64, range_mult: f64) -> f64 {
+    let (full, max) = (FULL_RANGE * range_mult, MAX_RANGE * range_mult);
+    ((max - distance) / (max - full)).clamp(0., 1.)
+}
+
+/// Holy paladin mastery (Lightbringer), which heals more the closer the
+/// target is. Positions come from advanced logging: the target's from
+/// the heal itself, and the paladin's from the last event that showed
+/// it, if that was recent enough.
+#[derive(Default, Debug, Clone)]
+pub struct Lightbringer<'a> {
+    positions: PositionTracker<'a>,
+    // whether distance to the beacon target counts too
+    beacon_lightbringer: bool,
+}
+
+impl<'a> Lightbringer<'a> {
+    // The distance mastery goes by, to the paladin or their beacon
+    fn distance(&self, auras: &AuraTracker<'a>, player_id: &str, target: &str, now: Duration) -> Option<f64> {
+        let target = self.positions.recent(target, now, max_position_age());
+        let paladin = self.positions.recent(player_id, now, max_position_age());
+        let mut distance = match (target, paladin) {
+            (Some(t), Some(p)) => t.distance(&p),
+            _ => None,
+        };
+        if self.beacon_lightbringer {
+            let beacons = auras.all().into_iter()
+                .filter(|&(_, src, spell, _)| src == player_id && spell == AURA_BEACON)
+                .filter_map(|(dst, _, _, _)| self.positions.recent(dst, now, max_position_age()));
+            for beacon in beacons {
+                if let Some(d) = target.and_then(|t| t.distance(&beacon)) {
+                    distance = Some(distance.map(|x| x.min(d)).unwrap_or(d));
+                }
+            }
+        }
+        distance
+    }
+}
+
+impl<'a> MasteryModel<'a> for Lightbringer<'a> {
+    fn spec() -> u32 { SPEC_HOLY_PALADIN }
+    fn name() -> &'static str { "holy paladin" }
+    fn needs() -> &'static str { "position" }
+    // the paladin's position mostly comes from events on them
+    fn all_events() -> bool { true }
+
+    fn update(&mut self, log: &Entry<'a>, _auras: &AuraTracker<'a>, player_id: &'a str) {
+        self.positions.update(log);
+        if let Entry::Info { id, ref auras, .. } = *log {
+            if id == player_id {
+                self.beacon_lightbringer = auras.iter().any(|&(_, aura)| aura == AURA_BEACON_OF_THE_LIGHTBRINGER);
+            }
+        }
+    }
+
+    fn effect(&self, log: &Entry<'a>, auras: &AuraTracker<'a>, player_id: &'a str) -> Option<f64> {
+        let target = match *log {
+            Entry::Heal { ref base, .. } => base.dst.id,
+            _ => return None,
+        };
+        let range_mult = if auras.has(player_id, player_id, AURA_RULE_OF_LAW) { 1.5 } else { 1. };
+        self.distance(auras, player_id, target, log.timestamp()).map(|d| effectiveness(d, range_mult))
+    }
+}
+
+pub type HolyPaladinComputation<'a> = HealerComputation<'a, Lightbringer<'a>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_off_with_distance() {
+        assert_eq!(effectiveness(0., 1.), 1.);
+        assert_eq!(effectiveness(10., 1.), 1.);
+        assert_eq!(effectiveness(25., 1.), 0.5);
+        assert_eq!(effectiveness(40., 1.), 0.);
+        assert_eq!(effectiveness(100., 1.), 0.);
+        // Rule of Law
+        assert_eq!(effectiveness(15., 1.5), 1.);
+        assert_eq!(effectiveness(37.5, 1.5), 0.5);
+        assert_eq!(effectiveness(60., 1.5), 0.);
+    }
+
+    #[test]
+    fn beacon_of_the_lightbringer() {
+        use {Interner, iter};
+        let info = |auras: &str| format!("4/7 21:13:58.600  COMBATANT_INFO,Player-1,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,11234,2468,2468,2468,1918,65,(18569,102351,0,197061,0,197073,155675),(0,0,0,0),[],[(147143,915,(),(),())],[{}]\n", auras);
+        let intern = Interner::default();
+        let has = |auras: &str| {
+            let line = info(auras);
+            let mut model = Lightbringer::default();
+            model.update(&iter(&intern, line.as_bytes()).next().unwrap(), &AuraTracker::new(), "Player-1");
+            model.beacon_lightbringer
+        };
+        assert!(has("Player-1,197446,Player-1,53563"));
+        assert!(!has("Player-1,53563"));
+        assert!(!has(""));
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::fmt;
+use chrono::Duration;
+
+use {Entry, AuraType, HealType};
+use report::Analysis;
+use spells::{SpellDb, Interaction};
+
+pub const SPEC_HOLY_PRIEST: u32 = 257;
+pub const SPELL_ECHO_OF_LIGHT: u32 = 77489;
+
+/// Holy priest mastery (Echo of Light), which heals the target again over
+/// a few seconds for part of each heal. Echoes on the same target roll
+/// together, so each tick is split between the heals that are still in
+/// it, in proportion to what's left of each.
+#[derive(Default, Debug, Clone)]
+pub struct HolyPriestComputation<'a> {
+    player_id: &'a str,
+    // heals that leave an echo
+    heals: Vec<u32>,
+    // target -> heal spell -> echo healing still to come from it
+    echoes: HashMap<&'a str, HashMap<u32, f64>>,
+    names: HashMap<u32, &'a str>,
+    // echo per point of heal, over everything seen (counted or not)
+    seen_base: u64,
+    seen_echo: u64,
+    total_healing: u64,
+    // of the heals that make echoes, overhealing included
+    echo_base_healing: u64,
+    echo_raw: u64,
+    echo_healing: u64,
+    // by the heal the echo came from; 0 if it was made before the log
+    echo_healing_by: HashMap<u32, f64>,
+}
+
+impl<'a> HolyPriestComputation<'a> {
+    pub fn new(player_id: &'a str, db: &SpellDb) -> Self {
+        HolyPriestComputation {
+            player_id,
+            heals: db.with(SPEC_HOLY_PRIEST, Interaction::Mastery),
+            ..Default::default()
+        }
+    }
+
+    pub fn parse_entry(&mut self, log: &Entry<'a>, filter_start_time: Duration) {
+        match *log {
+            Entry::Aura { ty: AuraType::Remove, ref base, id: SPELL_ECHO_OF_LIGHT, .. } if base.src.id == self.player_id => {
+                self.echoes.remove(base.dst.id);
+            },
+            Entry::Died { ref base } => {
+                self.echoes.remove(base.dst.id);
+            },
+            _ => (),
+        }
+
+        let (ty, base, id, spell, total_heal, overheal) = match *log {
+            Entry::Heal { ty, ref base, id, spell, heal, overheal, .. } => (ty, base, id, spell, heal, overheal),
+            _ => return,
+        };
+        if base.src.id != self.player_id {
+            return;
+        }
+        let counted = log.timestamp() >= filter_start_time;
+        let heal = total_heal - overheal;
+        if counted {
+            self.total_healing += heal;
+        }
+
+        if id == SPELL_ECHO_OF_LIGHT && ty == HealType::Periodic {
+            let echo = self.echoes.entry(base.dst.id).or_default();
+            let left = echo.values().fold(0., |a, &b| a + b);
+            self.seen_echo += total_heal;
+            if counted {
+                self.echo_raw += total_heal;
+                self.echo_healing += heal;
+            }
+            if left <= 0. {
+                if counted {
+                    *self.echo_healing_by.entry(0).or_insert(0.) += heal as f64;
+                }
+                return;
+            }
+            for (spell, amount) in echo.iter_mut() {
+                let share = *amount / left;
+                if counted {
+                    *self.echo_healing_by.entry(*spell).or_insert(0.) += heal as f64 * share;
+                }
+                *amount = (*amount - total_heal as f64 * share).max(0.);
+            }
+            return;
+        }
+
+        if ty == HealType::Heal && self.heals.contains(&id) {
+            // echoes are a share of the whole heal, overhealing included;
+            // the share itself comes out of the ticks, so it doesn't need
+            // the mastery rating
+            if counted {
+                self.echo_base_healing += total_heal;
+            }
+            self.seen_base += total_heal;
+            self.names.insert(id, spell);
+            let share = self.mastery_share();
+            *self.echoes.entry(base.dst.id).or_default().entry(id).or_insert(0.) += total_heal as f64 * share;
+        }
+    }
+
+    // The echo per point of heal seen so far, or a guess at the start
+    fn mastery_share(&self) -> f64 {
+        if self.seen_base == 0 || self.seen_echo == 0 {
+            0.1
+        } else {
+            self.seen_echo as f64 / self.seen_base as f64
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        let prev = ::std::mem::take(self);
+        *self = HolyPriestComputation {
+            player_id: prev.player_id,
+            heals: prev.heals,
+            echoes: prev.echoes,
+            names: prev.names,
+            seen_base: prev.seen_base,
+            seen_echo: prev.seen_echo,
+            ..Default::default()
+        }
+    }
+}
+
+impl<'a> Analysis<'a> for HolyPriestComputation<'a> {
+    fn parse_entry(&mut self, log: &Entry<'a>, start: Duration) {
+        HolyPriestComputation::parse_entry(self, log, start)
+    }
+
+    fn reset_stats(&mut self) {
+        HolyPriestComputation::reset_stats(self)
+    }
+
+    fn subtract(&mut self, rhs: &Self) {
+        self.total_healing -= rhs.total_healing;
+        self.echo_base_healing -= rhs.echo_base_healing;
+        self.echo_raw -= rhs.echo_raw;
+        self.echo_healing -= rhs.echo_healing;
+        for (spell, &heal) in rhs.echo_healing_by.iter() {
+            *self.echo_healing_by.entry(*spell).or_default() -= heal;
+        }
+    }
+}
+
+impl<'a> fmt::Display for HolyPriestComputation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // echo healing goes up in proportion to mastery, so its share of
+        // healing is how much healing goes up with it
+        let frac = |a: f64, b: u64| if b != 0 { a / b as f64 } else { 0. };
+        writeln!(f, "scale_mastery_added: {:.6}; mastery_effect: {:.2}%; echo overheal: {:.6};",
+               frac(self.echo_healing as f64, self.total_healing),
+               100. * frac(self.echo_raw as f64, self.echo_base_healing),
+               frac((self.echo_raw - self.echo_healing) as f64, self.echo_raw))?;
+        write!(f, "Echo of Light healing from: ")?;
+        let mut by = self.echo_healing_by.iter().filter(|&(_, &heal)| heal >= 1.).collect::<Vec<_>>();
+        by.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap().then(a.0.cmp(b.0)));
+        for (spell, heal) in by {
+            let name = if *spell == 0 { "unknown" } else { self.names[spell] };
+            write!(f, "{}: {:.6},  ", name, frac(*heal, self.total_healing))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Interner, iter};
+
+    // The first echo is the 10% guess of the flash heal; after its
+    // tick the heal's is 4% (6000 of the 150000 seen). The second tick
+    // is shared by the flash heal's 4000 left and the heal's 2000, and
+    // the tick on the druid has nothing it came from.
+    static LOG: &str = r#"4/7 21:13:58.000  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:14:00.000  SPELL_HEAL,Player-3674-0DDDDDDD,"Holy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,2061,"Flash Heal",0x2,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,100000,20000,0,nil
+4/7 21:14:01.000  SPELL_PERIODIC_HEAL,Player-3674-0DDDDDDD,"Holy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,77489,"Echo of Light",0x2,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,6000,1000,0,nil
+4/7 21:14:02.000  SPELL_HEAL,Player-3674-0DDDDDDD,"Holy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,2060,"Heal",0x2,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,50000,0,0,nil
+4/7 21:14:03.000  SPELL_PERIODIC_HEAL,Player-3674-0DDDDDDD,"Holy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,77489,"Echo of Light",0x2,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,7000,0,0,nil
+4/7 21:14:04.000  SPELL_PERIODIC_HEAL,Player-3674-0DDDDDDD,"Holy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x512,0x0,77489,"Echo of Light",0x2,Player-3674-06D6B01E,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,2000,0,0,nil
+4/7 21:14:30.000  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    #[test]
+    fn echo_of_light() {
+        let intern = Interner::default();
+        let mut comp = HolyPriestComputation::new("Player-3674-0DDDDDDD", &SpellDb::builtin());
+        for log in iter(&intern, LOG.as_bytes()) {
+            comp.parse_entry(&log, Duration::zero());
+        }
+        assert_eq!(format!("{}", comp), "\
+scale_mastery_added: 0.097222; mastery_effect: 10.00%; echo overheal: 0.066667;
+Echo of Light healing from: Flash Heal: 0.067130,  Heal: 0.016204,  unknown: 0.013889,  ");
+
+        let mut none = comp.clone();
+        none.subtract(&comp);
+        assert_eq!(format!("{}", none), "\
+scale_mastery_added: 0.000000; mastery_effect: 0.00%; echo overheal: 0.000000;
+Echo of Light healing from: ");
+    }
+}
@@ -0,0 +1,253 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{self, SystemTime, UNIX_EPOCH};
+use chrono::{Duration, NaiveDateTime};
+
+use intern::Interner;
+use {Entry, Iter, iter_from, parse_ts, parse_line};
+
+const MAGIC: &str = "wow-combat-log index 1";
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+/// Where one boss pull is in a log file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// byte offset of the ENCOUNTER_START line
+    pub offset: u64,
+    /// byte offset just past the ENCOUNTER_END line, if there was one
+    pub end_offset: Option<u64>,
+    /// relative to the start of the log, like `Entry::timestamp`
+    pub start: Duration,
+    pub end: Option<Duration>,
+    pub name: String,
+    pub id: u32,
+    pub difficulty: u16,
+    pub kill: bool,
+}
+
+/// The encounters in a log, so that one can be read without parsing
+/// everything before it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncounterIndex {
+    /// time of the first line, which timestamps are relative to
+    pub start: NaiveDateTime,
+    /// size of the log when indexed, to notice when it has grown
+    pub len: u64,
+    /// and its modification time, to notice when it has been rewritten
+    /// (None if it isn't known, for indexes built from a reader)
+    pub modified: Option<SystemTime>,
+    pub encounters: Vec<IndexEntry>,
+}
+
+fn invalid<E: Into<Box<dyn Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl EncounterIndex {
+    /// Scan a whole log for ENCOUNTER_START/END lines
+    pub fn build<R: BufRead>(mut read: R) -> io::Result<Self> {
+        let intern = Interner::default();
+        let mut line = String::new();
+        let mut offset = 0;
+        let mut start = None;
+        let mut encounters: Vec<IndexEntry> = vec![];
+        loop {
+            let len = read.read_line(&mut line)? as u64;
+            if len == 0 {
+                break;
+            }
+            let log_start = *start.get_or_insert_with(|| parse_ts(&line).0);
+            let is_encounter = line.split_once("  ").map(|x| x.1.starts_with("ENCOUNTER_")).unwrap_or(false);
+            if is_encounter {
+                match parse_line(&intern, &line, log_start) {
+                    Entry::EncounterStart { ts, name, id, difficulty, .. } => {
+                        encounters.push(IndexEntry {
+                            offset, end_offset: None,
+                            start: ts, end: None,
+                            name: name.to_owned(), id, difficulty, kill: false,
+                        });
+                    },
+                    Entry::EncounterEnd { ts, id, kill, .. } => {
+                        if let Some(last) = encounters.last_mut() {
+                            if last.id == id && last.end.is_none() {
+                                last.end = Some(ts);
+                                last.end_offset = Some(offset + len);
+                                last.kill = kill;
+                            }
+                        }
+                    },
+                    _ => (),
+                }
+            }
+            offset += len;
+            line.clear();
+        }
+        Ok(EncounterIndex {
+            start: start.unwrap_or_else(|| NaiveDateTime::from_timestamp(0, 0)),
+            len: offset,
+            modified: None,
+            encounters,
+        })
+    }
+
+    /// `foo.txt` -> `foo.txt.idx`
+    pub fn sidecar_path(log: &Path) -> PathBuf {
+        let mut name = log.file_name().unwrap().to_owned();
+        name.push(".idx");
+        log.with_file_name(name)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{}", MAGIC)?;
+        let modified = match self.modified.and_then(|x| x.duration_since(UNIX_EPOCH).ok()) {
+            Some(x) => format!("{}.{:09}", x.as_secs(), x.subsec_nanos()),
+            None => "-".to_owned(),
+        };
+        writeln!(out, "{}\t{}\t{}", self.start.format(DATE_FORMAT), self.len, modified)?;
+        for e in &self.encounters {
+            writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                     e.offset, e.end_offset.map(|x| x as i64).unwrap_or(-1),
+                     e.start.num_milliseconds(), e.end.map(|x| x.num_milliseconds()).unwrap_or(-1),
+                     e.id, e.difficulty, if e.kill { 1 } else { 0 }, e.name)?;
+        }
+        out.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        if lines.next().unwrap_or(Ok(String::new()))? != MAGIC {
+            return Err(invalid("not an index file"));
+        }
+        let header = lines.next().unwrap_or(Ok(String::new()))?;
+        let mut header = header.split('\t');
+        let start = NaiveDateTime::parse_from_str(header.next().unwrap_or(""), DATE_FORMAT).map_err(invalid)?;
+        let len = header.next().unwrap_or("").parse().map_err(invalid)?;
+        let modified = match header.next().unwrap_or("").split_once('.') {
+            Some((secs, nanos)) =>
+                Some(UNIX_EPOCH + time::Duration::new(secs.parse().map_err(invalid)?, nanos.parse().map_err(invalid)?)),
+            None => None,
+        };
+        let mut encounters = vec![];
+        for line in lines {
+            let line = line?;
+            let fields = line.splitn(8, '\t').collect::<Vec<_>>();
+            if fields.len() != 8 {
+                return Err(invalid("truncated index entry"));
+            }
+            let end_offset: i64 = fields[1].parse().map_err(invalid)?;
+            let end: i64 = fields[3].parse().map_err(invalid)?;
+            encounters.push(IndexEntry {
+                offset: fields[0].parse().map_err(invalid)?,
+                end_offset: if end_offset < 0 { None } else { Some(end_offset as u64) },
+                start: Duration::milliseconds(fields[2].parse().map_err(invalid)?),
+                end: if end < 0 { None } else { Some(Duration::milliseconds(end)) },
+                id: fields[4].parse().map_err(invalid)?,
+                difficulty: fields[5].parse().map_err(invalid)?,
+                kill: fields[6] == "1",
+                name: fields[7].to_owned(),
+            });
+        }
+        Ok(EncounterIndex { start, len, modified, encounters })
+    }
+
+    /// Load the sidecar index for `log`, (re)building and saving it if
+    /// it is missing or the log has changed size or been modified since.
+    pub fn for_log(log: &Path) -> io::Result<Self> {
+        let sidecar = EncounterIndex::sidecar_path(log);
+        let meta = fs::metadata(log)?;
+        let modified = meta.modified().ok();
+        if let Ok(index) = EncounterIndex::load(&sidecar) {
+            if index.len == meta.len() && index.modified == modified {
+                return Ok(index);
+            }
+        }
+        let mut index = EncounterIndex::build(BufReader::new(File::open(log)?))?;
+        index.modified = modified;
+        // not being able to write next to the log isn't fatal
+        let _ = index.save(&sidecar);
+        Ok(index)
+    }
+
+    /// Start reading `log` at `entry`'s ENCOUNTER_START, with
+    /// timestamps still relative to the start of the whole log.
+    pub fn iter_at<'a>(&self, intern: &'a Interner, log: &Path, entry: &IndexEntry) -> io::Result<Iter<'a, BufReader<File>>> {
+        // Take the start from the log itself rather than self.start,
+        // as the year is filled in when parsing and may have changed
+        // since the index was built
+        let mut read = BufReader::new(File::open(log)?);
+        let mut first = String::new();
+        read.read_line(&mut first)?;
+        let start = parse_ts(&first).0;
+        read.seek(SeekFrom::Start(entry.offset))?;
+        Ok(iter_from(intern, read, start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use iter;
+
+    static LOG: &str = r#"4/7 21:13:50.000  ZONE_CHANGE,1676,"Tomb of Sargeras",16
+4/7 21:13:58.541  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:59.100  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:15:00.999  ENCOUNTER_END,2032,"Goroth",16,20,0
+4/7 21:17:00.000  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:17:01.000  SPELL_AURA_REMOVED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:19:00.000  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    fn log_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("wow-combat-log-index-{}-{}.txt", name, ::std::process::id()))
+    }
+
+    fn touch(path: &Path, secs: u64) {
+        File::options().write(true).open(path).unwrap().set_modified(UNIX_EPOCH + time::Duration::from_secs(secs)).unwrap();
+    }
+
+    #[test]
+    fn reads_from_an_encounter() {
+        let path = log_path("read");
+        fs::write(&path, LOG).unwrap();
+        touch(&path, 1000);
+        let index = EncounterIndex::for_log(&path).unwrap();
+        let sidecar = EncounterIndex::sidecar_path(&path);
+        let loaded = EncounterIndex::load(&sidecar);
+        let intern = Interner::default();
+        let pull = &index.encounters[1];
+        let first = index.iter_at(&intern, &path, pull).unwrap().next();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&sidecar).unwrap();
+
+        assert_eq!(index.encounters.iter().map(|e| (&e.name[..], e.kill)).collect::<Vec<_>>(),
+                   vec![("Goroth", false), ("Goroth", true)]);
+        assert_eq!((index.len, index.modified), (LOG.len() as u64, Some(UNIX_EPOCH + time::Duration::from_secs(1000))));
+        assert_eq!(loaded.unwrap(), index);
+        assert_eq!(&LOG[pull.offset as usize..pull.end_offset.unwrap() as usize],
+                   LOG.lines().skip(4).map(|x| format!("{}\n", x)).collect::<String>());
+        assert_eq!((pull.start, pull.end), (Duration::seconds(190), Some(Duration::seconds(310))));
+        // the same entry as when parsing the whole log
+        assert_eq!(first, iter(&intern, LOG.as_bytes()).nth(4));
+    }
+
+    #[test]
+    fn rebuilds_for_a_rewritten_log() {
+        let path = log_path("stale");
+        fs::write(&path, LOG).unwrap();
+        touch(&path, 1000);
+        let before = EncounterIndex::for_log(&path).unwrap();
+        // same size, but the kill is now a wipe
+        fs::write(&path, LOG.replace(",20,1\n", ",20,0\n")).unwrap();
+        touch(&path, 2000);
+        let after = EncounterIndex::for_log(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(EncounterIndex::sidecar_path(&path)).unwrap();
+        assert_eq!(before.len, after.len);
+        assert!(before.encounters[1].kill);
+        assert!(!after.encounters[1].kill);
+    }
+}
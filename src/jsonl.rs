@@ -0,0 +1,211 @@
+use std::io::{self, Write};
+use chrono::NaiveDateTime;
+use json::JsonValue;
+
+use unit::{self, Guid};
+use {Entry, Object, AdvancedInfo, HealType, DamageType};
+
+fn guid_json(id: &str) -> JsonValue {
+    match unit::parse_guid(id) {
+        Guid::Nil => JsonValue::Null,
+        Guid::Player { server, id } => object!{ "kind" => "Player", "server" => server, "id" => id },
+        Guid::Creature { kind, server, instance, zone, npc, spawn } => object!{
+            "kind" => kind, "server" => server, "instance" => instance, "zone" => zone, "npc" => npc, "spawn" => spawn
+        },
+        Guid::Other(_) => JsonValue::Null,
+    }
+}
+
+fn adv_json(adv: &AdvancedInfo) -> JsonValue {
+    object!{
+        "id" => adv.id,
+        "owner" => adv.owner,
+        "ap" => adv.ap,
+        "sp" => adv.sp,
+        "power_type" => adv.power_type,
+        "power" => adv.power,
+        "max_power" => adv.max_power,
+        "map_index" => adv.map_index,
+        "x" => adv.x as f64 / 100.,
+        "y" => adv.y as f64 / 100.,
+        "ilvl" => adv.ilvl
+    }
+}
+
+fn unit_json(obj: &Object, flags1: u32, flags2: u32) -> JsonValue {
+    object!{
+        "name" => obj.name,
+        "id" => obj.id,
+        "guid" => guid_json(obj.id),
+        "flags" => unit::unit_flag_names(flags1),
+        "marker" => unit::raid_marker(flags2)
+    }
+}
+
+/// The JSON form of an entry, as written by `write_json_line`. Field
+/// names are the same for every source; `time` is only filled in
+/// when the log's start time is known.
+pub fn entry_json(entry: &Entry, start: Option<NaiveDateTime>) -> JsonValue {
+    use Entry::*;
+    let ts = entry.timestamp();
+    let mut ret = object!{
+        "event" => entry.event_name(),
+        "ts" => ts.num_milliseconds(),
+        "time" => start.map(|s| (s + ts).format("%Y-%m-%dT%H:%M:%S%.3f").to_string())
+    };
+    if let Some(base) = entry.base() {
+        ret["source"] = unit_json(&base.src, base.src_flags1, base.src_flags2);
+        ret["target"] = unit_json(&base.dst, base.dst_flags1, base.dst_flags2);
+    }
+    match *entry {
+        Aura { id, aura, flags, buff, amount, .. } => {
+            ret["spell_id"] = id.into();
+            ret["spell"] = aura.into();
+            ret["school"] = unit::school_names(flags).into();
+            ret["buff"] = buff.into();
+            ret["amount"] = amount.into();
+        },
+        Heal { ty, id, spell, flags, hp, maxhp, heal, overheal, absorbed, crit, ref adv, .. } => {
+            ret["spell_id"] = id.into();
+            ret["spell"] = spell.into();
+            ret["school"] = unit::school_names(flags).into();
+            ret["periodic"] = (ty == HealType::Periodic).into();
+            ret["heal"] = heal.into();
+            ret["overheal"] = overheal.into();
+            ret["effective"] = (heal - overheal).into();
+            ret["absorbed"] = absorbed.into();
+            ret["crit"] = crit.into();
+            ret["hp"] = hp.into();
+            ret["maxhp"] = maxhp.into();
+            ret["advanced"] = adv_json(adv);
+        },
+        Damage { ty, id, spell, flags, hp, maxhp, amount, overkill, school, resisted, blocked, absorbed,
+                 crit, glancing, crushing, offhand, ref adv, .. } => {
+            ret["spell_id"] = id.into();
+            ret["spell"] = spell.into();
+            ret["school"] = unit::school_names(flags).into();
+            ret["periodic"] = (ty == DamageType::Periodic).into();
+            ret["amount"] = amount.into();
+            ret["overkill"] = if overkill >= 0 { overkill.into() } else { JsonValue::Null };
+            ret["damage_school"] = unit::school_names(school).into();
+            ret["resisted"] = resisted.into();
+            ret["blocked"] = blocked.into();
+            ret["absorbed"] = absorbed.into();
+            ret["crit"] = crit.into();
+            ret["glancing"] = glancing.into();
+            ret["crushing"] = crushing.into();
+            ret["offhand"] = offhand.into();
+            ret["hp"] = hp.into();
+            ret["maxhp"] = maxhp.into();
+            ret["advanced"] = adv_json(adv);
+        },
+        Info { id, strength, agi, sta, int, dodge, parry, block, critm, critr, crits, spd, steal,
+               hastem, hastr, hastes, avd, mastery, versm, versr, verss, armor,
+               talents, artifact, gear, ref auras, .. } => {
+            ret["id"] = id.into();
+            ret["guid"] = guid_json(id);
+            ret["stats"] = object!{
+                "strength" => strength, "agility" => agi, "stamina" => sta, "intellect" => int,
+                "dodge" => dodge, "parry" => parry, "block" => block,
+                "crit_melee" => critm, "crit_ranged" => critr, "crit_spell" => crits,
+                "speed" => spd, "leech" => steal,
+                "haste_melee" => hastem, "haste_ranged" => hastr, "haste_spell" => hastes,
+                "avoidance" => avd, "mastery" => mastery,
+                "versatility_damage_done" => versm, "versatility_healing_done" => versr, "versatility_damage_taken" => verss,
+                "armor" => armor
+            };
+            ret["talents"] = talents.into();
+            ret["artifact"] = artifact.into();
+            ret["gear"] = gear.into();
+            ret["auras"] = auras.iter().map(|&(src, aura)| object!{ "source" => src, "spell_id" => aura })
+                .collect::<Vec<_>>().into();
+        },
+        ChallengeStart { id, rest, .. } | ChallengeEnd { id, rest, .. } => {
+            ret["id"] = id.into();
+            ret["rest"] = rest.into();
+        },
+        EncounterStart { name, id, difficulty, size, instance, .. } => {
+            ret["encounter_id"] = id.into();
+            ret["name"] = name.into();
+            ret["difficulty"] = difficulty.into();
+            ret["size"] = size.into();
+            ret["instance"] = instance.into();
+        },
+        EncounterEnd { name, id, difficulty, size, kill, .. } => {
+            ret["encounter_id"] = id.into();
+            ret["name"] = name.into();
+            ret["difficulty"] = difficulty.into();
+            ret["size"] = size.into();
+            ret["kill"] = kill.into();
+        },
+        Died { .. } | Unknown(..) => (),
+    }
+    ret
+}
+
+/// Write `entry` as one line of JSON
+pub fn write_json_line<W: Write>(w: &mut W, entry: &Entry, start: Option<NaiveDateTime>) -> io::Result<()> {
+    writeln!(w, "{}", entry_json(entry, start).dump())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Interner, iter};
+
+    // one of each kind of entry
+    static LOG: &str = r#"4/7 21:13:58.541  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:58.600  COMBATANT_INFO,Player-3674-06D6B01E,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,11234,2468,2468,2468,1918,105,(18569,102351,0,197061,0,197073,155675),(0,0,0,0),[(128306,1,(),(),())],[(147143,915,(),(3562,1497),())],[Player-3674-06D6B01E,774]
+4/7 21:13:59.003  SPELL_HEAL,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,8936,"Regrowth",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,200000,50000,0,1
+4/7 21:13:59.100  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:14:21.000  SWING_DAMAGE,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,Creature-0-3019-1676-22336-115844-00006814F0,0000000000000000,90000000,100000000,0,0,0,0,0,0,6213.56,-842.09,0,300000,-1,1,0,50000,20000,nil,nil,nil,1
+4/7 21:14:25.000  UNIT_DIED,0000000000000000,nil,0x80000000,0x80000000,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0
+4/7 21:14:30.000  SPELL_CAST_SUCCESS,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0
+4/7 21:15:00.999  ENCOUNTER_END,2032,"Goroth",16,20,1
+4/7 21:16:00.000  CHALLENGE_MODE_START,1571,210,10,[9,6,3]
+4/7 21:40:00.000  CHALLENGE_MODE_END,1571,1,10,1440000
+"#;
+
+    fn keys(x: &JsonValue) -> Vec<&str> {
+        x.entries().map(|(k, _)| k).collect()
+    }
+
+    #[test]
+    fn stable_field_names() {
+        let intern = Interner::default();
+        let log = iter(&intern, LOG.as_bytes());
+        let start = log.start_time();
+        let json = log.map(|e| entry_json(&e, Some(start))).collect::<Vec<_>>();
+        let expected: Vec<&[&str]> = vec![
+            &["event", "ts", "time", "encounter_id", "name", "difficulty", "size", "instance"],
+            &["event", "ts", "time", "id", "guid", "stats", "talents", "artifact", "gear", "auras"],
+            &["event", "ts", "time", "source", "target", "spell_id", "spell", "school", "periodic", "heal", "overheal",
+              "effective", "absorbed", "crit", "hp", "maxhp", "advanced"],
+            &["event", "ts", "time", "source", "target", "spell_id", "spell", "school", "buff", "amount"],
+            &["event", "ts", "time", "source", "target", "spell_id", "spell", "school", "periodic", "amount", "overkill",
+              "damage_school", "resisted", "blocked", "absorbed", "crit", "glancing", "crushing", "offhand", "hp", "maxhp",
+              "advanced"],
+            &["event", "ts", "time", "source", "target"],
+            &["event", "ts", "time"],
+            &["event", "ts", "time", "encounter_id", "name", "difficulty", "size", "kill"],
+            &["event", "ts", "time", "id", "rest"],
+            &["event", "ts", "time", "id", "rest"],
+        ];
+        assert_eq!(json.iter().map(keys).collect::<Vec<_>>(), expected);
+
+        let heal = &json[2];
+        assert_eq!(keys(&heal["source"]), vec!["name", "id", "guid", "flags", "marker"]);
+        assert_eq!(keys(&heal["source"]["guid"]), vec!["kind", "server", "id"]);
+        assert_eq!(keys(&json[4]["source"]["guid"]), vec!["kind", "server", "instance", "zone", "npc", "spawn"]);
+        assert_eq!(keys(&heal["advanced"]),
+                   vec!["id", "owner", "ap", "sp", "power_type", "power", "max_power", "map_index", "x", "y", "ilvl"]);
+        assert_eq!(keys(&json[1]["stats"]),
+                   vec!["strength", "agility", "stamina", "intellect", "dodge", "parry", "block",
+                        "crit_melee", "crit_ranged", "crit_spell", "speed", "leech",
+                        "haste_melee", "haste_ranged", "haste_spell", "avoidance", "mastery",
+                        "versatility_damage_done", "versatility_healing_done", "versatility_damage_taken", "armor"]);
+        assert_eq!(keys(&json[1]["auras"][0]), vec!["source", "spell_id"]);
+        assert_eq!(heal["time"], format!("{}-04-07T21:13:59.003", start.format("%Y")));
+        assert_eq!(heal["ts"], 462);
+    }
+}
@@ -1,17 +1,38 @@
 extern crate chrono;
 extern crate itertools;
+extern crate clap;
 #[macro_use]
 extern crate lazy_static;
 
-#[cfg(feature = "wcl")]
+#[macro_use]
 extern crate json;
 #[cfg(feature = "wcl")]
 extern crate reqwest;
 
 mod intern;
 mod collect_tuple;
+mod writer;
 #[cfg(feature = "wcl")]
 pub mod wcl;
+pub mod source;
+pub mod index;
+pub mod store;
+pub mod unit;
+pub mod aura;
+pub mod roster;
+pub mod stats;
+pub mod spells;
+pub mod report;
+pub mod healer;
+pub mod resto_druid;
+pub mod resto_shaman;
+pub mod position;
+pub mod holy_paladin;
+pub mod holy_priest;
+pub mod disc_priest;
+pub mod mistweaver;
+pub mod evoker;
+mod jsonl;
 
 use chrono::Duration;
 use chrono::NaiveDateTime;
@@ -19,6 +40,16 @@ use chrono::NaiveDate;
 use chrono::NaiveTime;
 use chrono::Datelike;
 pub use intern::Interner;
+pub use source::{EventSource, Events};
+pub use index::{EncounterIndex, IndexEntry};
+pub use store::{StoreWriter, StoreIter, read_store};
+pub use jsonl::{entry_json, write_json_line};
+pub use aura::{AuraTracker, ActiveAura, PastAura};
+pub use position::{PositionTracker, Position};
+pub use roster::{Roster, Player, Class, Role, Spec, Item, parse_gear};
+pub use stats::{StatTable, LevelStats, Stat, StatBuff, BuffTable, CritTable};
+pub use spells::{SpellDb, SpellInfo, Interaction};
+pub use writer::{write_entry, write_line, Writer};
 use std::io::BufRead;
 use collect_tuple::OrPanic;
 use itertools::Itertools;
@@ -41,33 +72,79 @@ pub struct BaseInfo<'a> {
     pub dst_flags2: u32,
 }
 
+// The advanced combat logging fields about the unit an event is about
+// (the target for heals). hp/maxhp are kept on the event itself.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct AdvancedInfo<'a> {
+    pub id: &'a str,
+    pub owner: &'a str,
+    pub ap: u64,
+    pub sp: u64,
+    pub power_type: i32,
+    pub power: u64,
+    pub max_power: u64,
+    pub map_index: i32,
+    // positions are in hundredths of a yard, the log has two decimal places
+    pub x: i32,
+    pub y: i32,
+    pub ilvl: u32,
+}
+
+// Stack and Unstack are SPELL_AURA_APPLIED_DOSE and SPELL_AURA_REMOVED_DOSE,
+// with the new stack count as the amount. (Before Unstack, removed doses
+// were also read as Stack.)
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
-pub enum AuraType { Apply, Refresh, Remove, Stack }
+pub enum AuraType { Apply, Refresh, Remove, Stack, Unstack }
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum HealType { Heal, Periodic }
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum DamageType { Spell, Periodic, Swing, Range }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Entry<'a> {
-    Aura { ty: AuraType, base: BaseInfo<'a>, id: u32, aura: &'a str, flags: u8, buff: bool },
-    Heal { ty: HealType, base: BaseInfo<'a>, id: u32, spell: &'a str, flags: u8, hp: u64, maxhp: u64, heal: u64, overheal: u64, crit: bool },
+    // amount is the stack count for (un)stacks, and the absorb amount for some shields
+    Aura { ty: AuraType, base: BaseInfo<'a>, id: u32, aura: &'a str, flags: u8, buff: bool, amount: Option<u64> },
+    Heal { ty: HealType, base: BaseInfo<'a>, id: u32, spell: &'a str, flags: u8, hp: u64, maxhp: u64, heal: u64, overheal: u64, absorbed: u64, crit: bool,
+           adv: AdvancedInfo<'a> },
+    // swings have no spell fields in the log, and get id 1 "Melee" (as
+    // on warcraftlogs). overkill is -1 if the hit didn't kill.
+    Damage { ty: DamageType, base: BaseInfo<'a>, id: u32, spell: &'a str, flags: u8, hp: u64, maxhp: u64, amount: u64, overkill: i64, school: u8,
+             resisted: u64, blocked: u64, absorbed: u64, crit: bool, glancing: bool, crushing: bool, offhand: bool,
+             adv: AdvancedInfo<'a> },
+    Died { base: BaseInfo<'a> },
 
 
     // followed by: ???, (talents), (pvp talents), [artifact info], [gear], [buffs]
     Info { ts: Duration, id: &'a str, strength: u32, agi: u32, sta: u32, int: u32, dodge: u32, parry: u32, block: u32, critm: u32, critr: u32, crits: u32, spd: u32, steal: u32, hastem: u32, hastr: u32, hastes: u32, avd: u32, mastery: u32, versm: u32, versr: u32, verss: u32, armor: u32,
 
+           // unparsed "???, (talents), (pvp talents)", artifact and gear, without the []s
+           talents: &'a str, artifact: &'a str, gear: &'a str,
            // (source id, aura id)
            auras: Vec<(&'a str, u32)>,
     },
-    ChallengeStart { ts: Duration, id: u32 },
-    ChallengeEnd { ts: Duration, id: u32 },
-    EncounterStart { ts: Duration, name: &'a str, id: u32, difficulty: u16, }, // , difficulty/dungeon?, num players
-    EncounterEnd { ts: Duration, name: &'a str, id: u32, difficulty: u16, kill: bool },
+    // rest is the unparsed remainder of the line
+    ChallengeStart { ts: Duration, id: u32, rest: &'a str },
+    ChallengeEnd { ts: Duration, id: u32, rest: &'a str },
+    EncounterStart { ts: Duration, name: &'a str, id: u32, difficulty: u16, size: u32, instance: Option<u32> },
+    EncounterEnd { ts: Duration, name: &'a str, id: u32, difficulty: u16, size: u32, kill: bool },
     Unknown(Duration, &'a str),
 }
 
 lazy_static!{ static ref YEAR: i32 = chrono::UTC::today().year(); }
 
-fn parse_ts(line: &str) -> (NaiveDateTime, &str) {
+/// The line ending `line` was read with: "\r\n", "\n", or nothing at the
+/// end of a file that doesn't end with one
+pub fn line_ending(line: &str) -> &'static str {
+    if line.ends_with("\r\n") {
+        "\r\n"
+    } else if line.ends_with('\n') {
+        "\n"
+    } else {
+        ""
+    }
+}
+
+pub fn parse_ts(line: &str) -> (NaiveDateTime, &str) {
     let OrPanic((ts_str, line)) = line.splitn(2, "  ").collect();
     let OrPanic((date, t)) = ts_str.splitn(2, " ").collect();
     let OrPanic((m, d)) = date.splitn(2, "/").collect();
@@ -79,6 +156,11 @@ fn parse_hex(x: &str) -> u32 {
     u32::from_str_radix(&x[2..], 16).unwrap()
 }
 
+// "-1787.34" -> -178734, rounding anything past two decimal places
+fn parse_pos(x: &str) -> Option<i32> {
+    x.parse::<f64>().ok().map(|x| (x * 100.).round() as i32)
+}
+
 fn parse_quote(x: &str) -> (&str, &str) {
     if x.starts_with('"') {
         let OrPanic((_, a, b)) = x.splitn(3, '"').collect();
@@ -91,6 +173,22 @@ fn parse_quote(x: &str) -> (&str, &str) {
     }
 }
 
+// The advanced fields (and the hp of the unit they're about), and the
+// rest of the line. Nothing needs the fields past maxhp, so ones in a
+// format this doesn't know (eg lists of powers) are left at 0 rather
+// than failing the whole line.
+fn parse_adv<'a, 'b>(intern: &'a Interner, line: &'b str) -> (AdvancedInfo<'a>, u64, u64, &'b str) {
+    let OrPanic((advid, owner, hp, maxhp,
+                 ap, sp, power_type, power, max_power, map_index_maybe,
+                 x, y, ilvl, line)) = line.splitn(14, ",").collect();
+    (AdvancedInfo {
+        id: intern.intern(advid), owner: intern.intern(owner), ap: ap.parse().unwrap_or(0), sp: sp.parse().unwrap_or(0),
+        power_type: power_type.parse().unwrap_or(0), power: power.parse().unwrap_or(0), max_power: max_power.parse().unwrap_or(0),
+        map_index: map_index_maybe.parse().unwrap_or(0), x: parse_pos(x).unwrap_or(0), y: parse_pos(y).unwrap_or(0),
+        ilvl: ilvl.parse().unwrap_or(0),
+    }, hp.parse().unwrap(), maxhp.parse().unwrap(), line)
+}
+
 fn parse_base<'a, 'b>(intern: &'a Interner, line: &'b str, timestamp: Duration) -> (BaseInfo<'a>, &'b str) {
     let OrPanic((srcid, line)) = line.splitn(2, ',').collect();
     let (srcname, line) = parse_quote(line);
@@ -116,25 +214,28 @@ pub fn parse_line<'a>(intern: &'a Interner, line: &str, start_time: NaiveDateTim
             let (base, line) = parse_base(intern, line, dur);
             let OrPanic((id, line)) = line.splitn(2, ',').collect();
             let (name, line) = parse_quote(line);
-            let OrPanic((flag, buff)) = line.splitn(2, ",").collect();
+            let OrPanic((flag, line)) = line.splitn(2, ",").collect();
             let name = intern.intern(name);
             let ty = match ty {
                 "SPELL_AURA_APPLIED" => AuraType::Apply,
-                "SPELL_AURA_APPLIED_DOSE" | "SPELL_AURA_REMOVED_DOSE" => AuraType::Stack,
+                "SPELL_AURA_APPLIED_DOSE" => AuraType::Stack,
+                "SPELL_AURA_REMOVED_DOSE" => AuraType::Unstack,
                 "SPELL_AURA_REMOVED" => AuraType::Remove,
                 "SPELL_AURA_REFRESH" => AuraType::Refresh,
                 _ => unreachable!(),
             };
-            let buff = buff.trim() == "BUFF";
-            Entry::Aura { ty: ty, base: base, id: id.parse().unwrap(), aura: name, flags: parse_hex(flag) as u8, buff: buff }
+            let mut rest = line.trim().splitn(2, ',');
+            let buff = rest.next().unwrap() == "BUFF";
+            let amount = rest.next().map(|x| x.parse().unwrap());
+            Entry::Aura { ty, base, id: id.parse().unwrap(), aura: name, flags: parse_hex(flag) as u8, buff, amount }
         },
         "SPELL_HEAL" | "SPELL_PERIODIC_HEAL" => {
             let (base, line) = parse_base(intern, line, dur);
             let OrPanic((id, line)) = line.splitn(2, ',').collect();
             let (name, line) = parse_quote(line);
-            let OrPanic((flag, _someguid, _zeros, hp, maxhp,
-                         _ap, _sp, _energytype, _energy, _energymax, _map_index_maybe,
-                         _x, _y, _ilvl, heal, overheal, _zero, crit)) = line.splitn(18, ",").collect();
+            let OrPanic((flag, line)) = line.splitn(2, ",").collect();
+            let (adv, hp, maxhp, line) = parse_adv(intern, line);
+            let OrPanic((heal, overheal, absorbed, crit)) = line.splitn(4, ",").collect();
             let name = intern.intern(name);
             let ty = match ty {
                 "SPELL_HEAL" => HealType::Heal,
@@ -142,16 +243,51 @@ pub fn parse_line<'a>(intern: &'a Interner, line: &str, start_time: NaiveDateTim
                 _ => unreachable!(),
             };
             Entry::Heal { ty: ty, base: base, id: id.parse().unwrap(), spell: name, flags: parse_hex(flag) as u8,
-                          hp: hp.parse().unwrap(), maxhp: maxhp.parse().unwrap(), heal: heal.parse().unwrap(), overheal: overheal.parse().unwrap(),
-                          crit: crit.trim() == "1"  }
+                          hp, maxhp, heal: heal.parse().unwrap(), overheal: overheal.parse().unwrap(),
+                          absorbed: absorbed.parse().unwrap(), crit: crit.trim() == "1",
+                          adv }
+        },
+        "SPELL_DAMAGE" | "SPELL_PERIODIC_DAMAGE" | "RANGE_DAMAGE" | "SWING_DAMAGE" => {
+            let (base, line) = parse_base(intern, line, dur);
+            let ty = match ty {
+                "SPELL_DAMAGE" => DamageType::Spell,
+                "SPELL_PERIODIC_DAMAGE" => DamageType::Periodic,
+                "RANGE_DAMAGE" => DamageType::Range,
+                "SWING_DAMAGE" => DamageType::Swing,
+                _ => unreachable!(),
+            };
+            let (id, name, flags, line) = if ty == DamageType::Swing {
+                (1, "Melee", 1, line)
+            } else {
+                let OrPanic((id, line)) = line.splitn(2, ',').collect();
+                let (name, line) = parse_quote(line);
+                let OrPanic((flag, line)) = line.splitn(2, ",").collect();
+                (id.parse().unwrap(), name, parse_hex(flag) as u8, line)
+            };
+            let (adv, hp, maxhp, line) = parse_adv(intern, line);
+            let OrPanic((amount, overkill, school, resisted, blocked, absorbed,
+                         crit, glancing, crushing, offhand)) = line.splitn(10, ",").collect();
+            Entry::Damage { ty, base, id, spell: intern.intern(name), flags, hp, maxhp,
+                            amount: amount.parse().unwrap(), overkill: overkill.parse().unwrap(), school: school.parse().unwrap(),
+                            resisted: resisted.parse().unwrap(), blocked: blocked.parse().unwrap(), absorbed: absorbed.parse().unwrap(),
+                            crit: crit == "1", glancing: glancing == "1", crushing: crushing == "1", offhand: offhand.trim() == "1",
+                            adv }
+        },
+        "UNIT_DIED" => {
+            // 7.x has nothing after the target, parse_base expects a trailing field
+            let (base, _) = parse_base(intern, &format!("{},", line.trim_end()), dur);
+            Entry::Died { base }
         },
         "COMBATANT_INFO" => {
             let OrPanic((id, strength, agi, sta, int, dodge, parry, block,
                          critm, critr, crits, spd, steal,
                          hastem, hastr, hastes, avd, mastery,
                          versm, versr, verss, armor, line)) = line.splitn(23, ',').collect();
-            let OrPanic((_talents, _artifact, _gear, auras)) = line.split('[').collect();
-            let auras = auras.trim().trim_right_matches(']');
+            let OrPanic((talents, artifact, gear, auras)) = line.split('[').collect();
+            let talents = talents.trim_end_matches(',');
+            let artifact = artifact.trim_end_matches(',').trim_end_matches(']');
+            let gear = gear.trim_end_matches(',').trim_end_matches(']');
+            let auras = auras.trim().trim_end_matches(']');
             let auras = auras.split(',').tuples()
                 .map(|(src, aura)| (intern.intern(src), aura.parse().unwrap())).collect();
             Entry::Info { ts: dur, id: intern.intern(id), strength: strength.parse().unwrap(), agi: agi.parse().unwrap(), sta: sta.parse().unwrap(), int: int.parse().unwrap(),
@@ -159,28 +295,34 @@ pub fn parse_line<'a>(intern: &'a Interner, line: &str, start_time: NaiveDateTim
                           critm: critm.parse().unwrap(), critr: critr.parse().unwrap(), crits: crits.parse().unwrap(), spd: spd.parse().unwrap(), steal: steal.parse().unwrap(),
                           hastem: hastem.parse().unwrap(), hastr: hastr.parse().unwrap(), hastes: hastes.parse().unwrap(), avd: avd.parse().unwrap(), mastery: mastery.parse().unwrap(),
                           versm: versm.parse().unwrap(), versr: versr.parse().unwrap(), verss: verss.parse().unwrap(), armor: armor.parse().unwrap(),
+                          talents: intern.intern(talents), artifact: intern.intern(artifact), gear: intern.intern(gear),
                           auras: auras,
             }
         },
         "CHALLENGE_MODE_START" => {
-            let OrPanic((id, _line)) = line.splitn(2, ',').collect();
-            Entry::ChallengeStart { ts: dur, id: id.parse().unwrap() }
+            let OrPanic((id, line)) = line.splitn(2, ',').collect();
+            Entry::ChallengeStart { ts: dur, id: id.parse().unwrap(), rest: intern.intern(line.trim_end()) }
         },
         "CHALLENGE_MODE_END" => {
-            let OrPanic((id, _line)) = line.splitn(2, ',').collect();
-            Entry::ChallengeEnd { ts: dur, id: id.parse().unwrap() }
+            let OrPanic((id, line)) = line.splitn(2, ',').collect();
+            Entry::ChallengeEnd { ts: dur, id: id.parse().unwrap(), rest: intern.intern(line.trim_end()) }
         },
         "ENCOUNTER_START" => {
             let OrPanic((id, line)) = line.splitn(2, ',').collect();
             let (name, line) = parse_quote(line);
-            let OrPanic((difficulty, _players)) = line.splitn(2, ',').collect();
-            Entry::EncounterStart { ts: dur, name: intern.intern(name), id: id.parse().unwrap(), difficulty: difficulty.parse().unwrap() }
+            let OrPanic((difficulty, line)) = line.splitn(2, ',').collect();
+            let mut rest = line.trim().splitn(2, ',');
+            let size = rest.next().unwrap().parse().unwrap();
+            let instance = rest.next().map(|x| x.parse().unwrap());
+            Entry::EncounterStart { ts: dur, name: intern.intern(name), id: id.parse().unwrap(), difficulty: difficulty.parse().unwrap(),
+                                    size, instance }
         },
         "ENCOUNTER_END" => {
             let OrPanic((id, line)) = line.splitn(2, ',').collect();
             let (name, line) = parse_quote(line);
-            let OrPanic((difficulty, _players, kill)) = line.splitn(3, ',').collect();
-            Entry::EncounterEnd { ts: dur, name: intern.intern(name), id: id.parse().unwrap(), difficulty: difficulty.parse().unwrap(), kill: kill.trim() == "1" }
+            let OrPanic((difficulty, size, kill)) = line.splitn(3, ',').collect();
+            Entry::EncounterEnd { ts: dur, name: intern.intern(name), id: id.parse().unwrap(), difficulty: difficulty.parse().unwrap(),
+                                  size: size.parse().unwrap(), kill: kill.trim() == "1" }
         },
         x => Entry::Unknown(dur, intern.intern(x)),
     }
@@ -201,6 +343,30 @@ pub fn iter<R: BufRead>(intern: &Interner, mut read: R) -> Iter<R> {
     Iter { intern: intern, read: read, start: start, nextline: s }
 }
 
+/// Like `iter`, but with timestamps relative to `start` rather than
+/// the first line read, for reading from partway into a log.
+pub fn iter_from<R: BufRead>(intern: &Interner, mut read: R, start: NaiveDateTime) -> Iter<'_, R> {
+    let mut s = String::new();
+    read.read_line(&mut s).unwrap();
+    Iter { intern, read, start, nextline: s }
+}
+
+impl<'a, R: BufRead> Iter<'a, R> {
+    /// The time of the first line of the log, which all timestamps are relative to
+    pub fn start_time(&self) -> NaiveDateTime {
+        self.start
+    }
+
+    /// What the next line ends with, "\r\n" for logs written on
+    /// windows, to write them back the same way
+    pub fn line_ending(&self) -> &'static str {
+        match line_ending(&self.nextline) {
+            "" => "\n",
+            x => x,
+        }
+    }
+}
+
 impl<'a, R: BufRead> Iterator for Iter<'a, R> {
     type Item = Entry<'a>;
     fn next(&mut self) -> Option<Entry<'a>> {
@@ -217,14 +383,56 @@ impl<'a> Entry<'a> {
         match *self {
             Entry::Aura { ref base, .. } => Some(base),
             Entry::Heal { ref base, .. } => Some(base),
+            Entry::Damage { ref base, .. } => Some(base),
+            Entry::Died { ref base } => Some(base),
             _ => None
         }
     }
+    /// The advanced logging fields, for the events that have them
+    pub fn adv(&self) -> Option<&AdvancedInfo<'a>> {
+        match *self {
+            Entry::Heal { ref adv, .. } | Entry::Damage { ref adv, .. } => Some(adv),
+            _ => None
+        }
+    }
+    /// The event type as it appears in the log
+    pub fn event_name(&self) -> &'a str {
+        use Entry::*;
+        match *self {
+            Aura { ty: AuraType::Apply, .. } => "SPELL_AURA_APPLIED",
+            Aura { ty: AuraType::Refresh, .. } => "SPELL_AURA_REFRESH",
+            Aura { ty: AuraType::Remove, .. } => "SPELL_AURA_REMOVED",
+            Aura { ty: AuraType::Stack, .. } => "SPELL_AURA_APPLIED_DOSE",
+            Aura { ty: AuraType::Unstack, .. } => "SPELL_AURA_REMOVED_DOSE",
+            Heal { ty: HealType::Heal, .. } => "SPELL_HEAL",
+            Heal { ty: HealType::Periodic, .. } => "SPELL_PERIODIC_HEAL",
+            Damage { ty: DamageType::Spell, .. } => "SPELL_DAMAGE",
+            Damage { ty: DamageType::Periodic, .. } => "SPELL_PERIODIC_DAMAGE",
+            Damage { ty: DamageType::Swing, .. } => "SWING_DAMAGE",
+            Damage { ty: DamageType::Range, .. } => "RANGE_DAMAGE",
+            Died { .. } => "UNIT_DIED",
+            Info { .. } => "COMBATANT_INFO",
+            ChallengeStart { .. } => "CHALLENGE_MODE_START",
+            ChallengeEnd { .. } => "CHALLENGE_MODE_END",
+            EncounterStart { .. } => "ENCOUNTER_START",
+            EncounterEnd { .. } => "ENCOUNTER_END",
+            Unknown(_, ty) => ty,
+        }
+    }
+    /// The spec id from a COMBATANT_INFO (the first of its `talents`)
+    pub fn spec_id(&self) -> Option<u32> {
+        match *self {
+            Entry::Info { talents, .. } => talents.split(',').next().and_then(|x| x.parse().ok()),
+            _ => None,
+        }
+    }
     pub fn timestamp(&self) -> Duration {
         use Entry::*;
         match *self {
             Aura { ref base, .. } => base.timestamp,
             Heal { ref base, .. } => base.timestamp,
+            Damage { ref base, .. } => base.timestamp,
+            Died { ref base } => base.timestamp,
             Info { ts, .. } => ts,
             ChallengeStart { ts, .. } => ts,
             ChallengeEnd { ts, .. } => ts,
@@ -0,0 +1,48 @@
+use {Entry, AuraTracker};
+use healer::{MasteryModel, HealerComputation};
+
+pub const SPEC_MISTWEAVER: u32 = 270;
+
+/// Mistweaver mastery (Gust of Mists), an extra heal on the target of
+/// some heals. It's all from mastery and scales with it (it's a proc in
+/// the spell database), so the model has nothing to work out.
+#[derive(Default, Debug, Clone)]
+pub struct GustOfMists;
+
+impl<'a> MasteryModel<'a> for GustOfMists {
+    fn spec() -> u32 { SPEC_MISTWEAVER }
+    fn name() -> &'static str { "mistweaver monk" }
+    fn needs() -> &'static str { "nothing" }
+
+    fn effect(&self, _: &Entry<'a>, _: &AuraTracker<'a>, _: &'a str) -> Option<f64> {
+        None
+    }
+}
+
+pub type MistweaverComputation<'a> = HealerComputation<'a, GustOfMists>;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Interner;
+    use healer::tests::analyze;
+
+    static LOG: &str = r#"4/7 21:13:58.000  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:58.100  COMBATANT_INFO,Player-3674-0ABABABA,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,1800,2468,2468,2468,1918,270,(0,0,0,0,0,0,0),(0,0,0,0),[],[(147143,915,(),(),())],[]
+4/7 21:14:00.000  SPELL_HEAL,Player-3674-0ABABABA,"Monky-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,116670,"Vivify",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,100000,0,0,nil
+4/7 21:14:00.000  SPELL_HEAL,Player-3674-0ABABABA,"Monky-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,191894,"Gust of Mists",0x8,Player-3674-0AAAAAAA,0000000000000000,3200000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,25000,5000,0,nil
+4/7 21:14:30.000  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    #[test]
+    fn gust_of_mists() {
+        let intern = Interner::default();
+        let comp = analyze::<GustOfMists>(&intern, LOG, 70);
+        assert_eq!(comp.total_healing(), 120000);
+        // the gust is all mastery, and the vivify none
+        assert_eq!(format!("{}", comp), "\
+scale_mastery_added: 0.166667;
+  mastery healing: 0.166667; mastery procs: 0.166667;");
+    }
+}
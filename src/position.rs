@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use chrono::Duration;
+
+use Entry;
+
+/// Where a unit was last seen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub map_index: i32,
+    /// in hundredths of a yard, like `AdvancedInfo`
+    pub x: i32,
+    pub y: i32,
+    /// when it was seen there
+    pub ts: Duration,
+}
+
+impl Position {
+    /// Distance in yards, if both are on the same map
+    pub fn distance(&self, other: &Position) -> Option<f64> {
+        if self.map_index != other.map_index {
+            return None;
+        }
+        let (dx, dy) = ((self.x - other.x) as f64 / 100., (self.y - other.y) as f64 / 100.);
+        Some((dx * dx + dy * dy).sqrt())
+    }
+}
+
+/// Keeps the last known position of every unit, from the advanced
+/// logging fields of the events passed to `update`
+#[derive(Debug, Clone, Default)]
+pub struct PositionTracker<'a> {
+    positions: HashMap<&'a str, Position>,
+}
+
+impl<'a> PositionTracker<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn update(&mut self, log: &Entry<'a>) {
+        if let Some(adv) = log.adv() {
+            // 0,0 is what's logged when there's no position
+            if adv.id != "0000000000000000" && (adv.x != 0 || adv.y != 0) {
+                self.positions.insert(adv.id, Position { map_index: adv.map_index, x: adv.x, y: adv.y, ts: log.timestamp() });
+            }
+        }
+    }
+
+    pub fn get(&self, unit: &str) -> Option<Position> {
+        self.positions.get(unit).cloned()
+    }
+
+    /// The position of `unit` if it was seen within `max_age` of `now`
+    pub fn recent(&self, unit: &str, now: Duration, max_age: Duration) -> Option<Position> {
+        self.get(unit).and_then(|p| if now - p.ts <= max_age { Some(p) } else { None })
+    }
+
+    /// Distance in yards between two units' last known positions
+    pub fn distance(&self, a: &str, b: &str) -> Option<f64> {
+        match (self.get(a), self.get(b)) {
+            (Some(a), Some(b)) => a.distance(&b),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(map_index: i32, x: i32, y: i32) -> Position {
+        Position { map_index, x, y, ts: Duration::zero() }
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(at(1, 0, 0).distance(&at(1, 300, 400)), Some(5.));
+        assert_eq!(at(1, -150, 250).distance(&at(1, -150, 250)), Some(0.));
+        assert_eq!(at(1, 0, 0).distance(&at(2, 0, 0)), None);
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::fmt;
+use chrono::Duration;
+
+use {Entry, Roster};
+
+/// Numbers about one player that can be added up over any stretch of a
+/// log, so `report` can give them per encounter and in total
+pub trait Analysis<'a>: Clone + fmt::Display {
+    /// Take in one event. Events before `start` update what's known
+    /// (auras, stats etc) but aren't counted.
+    fn parse_entry(&mut self, log: &Entry<'a>, start: Duration);
+    /// Zero everything counted so far, keeping what's known
+    fn reset_stats(&mut self);
+    /// Take away what `other` counted, where `other` saw a subset of
+    /// the same events
+    fn subtract(&mut self, other: &Self);
+}
+
+/// Read from `iter` until the player's first COMBATANT_INFO, returning
+/// their guid and the COMBATANT_INFO. Everything read is kept in
+/// `pending` to be analyzed once their stats are known.
+pub fn find_init_info<'a, I: Iterator<Item=Entry<'a>>>(iter: &mut I, player: &str, pending: &mut Vec<Entry<'a>>) -> Option<(&'a str, Entry<'a>)> {
+    let mut roster = Roster::new();
+    let mut map = HashMap::new();
+    for log in iter.by_ref() {
+        roster.update(&log);
+        if let Entry::Info { id, .. } = log {
+            map.entry(id).or_insert_with(|| log.clone());
+        }
+        pending.push(log);
+        if let Some(p) = roster.by_name(player) {
+            if let Some(info) = map.remove(p.guid) {
+                return Some((p.guid, info));
+            }
+        }
+    }
+    None
+}
+
+/// Read from `iter` until the player shows up, returning their guid.
+/// Everything read is kept in `pending` like in `find_init_info`.
+pub fn find_player<'a, I: Iterator<Item=Entry<'a>>>(iter: &mut I, player: &str, pending: &mut Vec<Entry<'a>>) -> Option<&'a str> {
+    let mut roster = Roster::new();
+    for log in iter.by_ref() {
+        roster.update(&log);
+        pending.push(log);
+        if let Some(p) = roster.by_name(player) {
+            return Some(p.guid);
+        }
+    }
+    None
+}
+
+/// Print `analysis` for each encounter in `iter`, then for the whole
+/// log, all boss encounters and only kills
+pub fn report<'a, A: Analysis<'a>, I: IntoIterator<Item=Entry<'a>>>(iter: I, start: Duration, analysis: A) {
+    let mut encounter_start = None;
+    let mut total = analysis;
+    let mut encounter = total.clone();
+    let mut kills = total.clone();
+    let mut bosses = total.clone();
+
+    for log in iter {
+        match log {
+            Entry::EncounterStart {..} => {
+                encounter_start = Some(log.timestamp());
+                bosses.subtract(&encounter);
+                kills.subtract(&encounter);
+                encounter.reset_stats();
+            },
+            Entry::EncounterEnd {name, kill, difficulty, ..} => {
+                if let Some(s) = encounter_start {
+                    println!("duration: {}, start: {}, {} ({}), kill: {}", (log.timestamp() - s).num_seconds(), s.num_seconds(), name, difficulty, kill);
+                    println!("{}", encounter);
+                    println!();
+                    encounter_start = None;
+                }
+                if !kill {
+                    kills.subtract(&encounter);
+                }
+                encounter.reset_stats();
+            },
+            _ => ()
+        }
+        encounter.parse_entry(&log, start);
+        total.parse_entry(&log, start);
+        kills.parse_entry(&log, start);
+        bosses.parse_entry(&log, start);
+    }
+    bosses.subtract(&encounter);
+    kills.subtract(&encounter);
+
+    println!("-------");
+    println!();
+    println!("Log total:");
+    println!("{}", total);
+    println!();
+    println!("Boss total:");
+    println!("{}", bosses);
+    println!();
+    println!("Kill total:");
+    println!("{}", kills);
+}
@@ -0,0 +1,67 @@
+use {Entry, AuraTracker};
+use healer::MasteryModel;
+use spells::{SpellDb, Interaction};
+
+pub const SPEC_RESTO_DRUID: u32 = 105;
+
+/// The resto druid spells analyses need, from the spell database
+#[derive(Default, Debug, Clone)]
+pub struct DruidSpells {
+    /// HoTs that count as a mastery stack
+    pub mastery_auras: Vec<u32>,
+    /// short names of `mastery_auras`, in the same order
+    pub mastery_names: Vec<(u32, String)>,
+    /// everything mastery applies to, stacks included
+    pub mastery_heals: Vec<u32>,
+    pub rejuv_auras: Vec<u32>,
+    /// the Germination ones of `rejuv_auras`, which can be on a target
+    /// alongside a plain Rejuvenation
+    pub germination: Vec<u32>,
+    /// heals that plant a living seed when they crit
+    pub living_seed_heals: Vec<u32>,
+}
+
+impl DruidSpells {
+    pub fn new(db: &SpellDb) -> Self {
+        DruidSpells {
+            mastery_auras: db.with(SPEC_RESTO_DRUID, Interaction::Stack),
+            mastery_names: db.iter().filter(|s| s.for_spec(SPEC_RESTO_DRUID) && s.has(Interaction::Stack))
+                .map(|s| (s.id, s.short.clone())).collect(),
+            mastery_heals: db.with(SPEC_RESTO_DRUID, Interaction::Mastery),
+            rejuv_auras: db.tagged(SPEC_RESTO_DRUID, "rejuv"),
+            germination: db.tagged(SPEC_RESTO_DRUID, "germination"),
+            living_seed_heals: db.with(SPEC_RESTO_DRUID, Interaction::Seed),
+        }
+    }
+}
+
+/// Resto druid mastery (Harmony), which heals more for each of the
+/// druid's HoTs on the target
+#[derive(Default, Debug, Clone)]
+pub struct Harmony {
+    mastery_auras: Vec<u32>,
+}
+
+impl Harmony {
+    /// The number of mastery stacks `source` has on `target`
+    pub fn stacks(&self, auras: &AuraTracker, target: &str, source: &str) -> usize {
+        auras.count(target, source, &self.mastery_auras)
+    }
+}
+
+impl<'a> MasteryModel<'a> for Harmony {
+    fn spec() -> u32 { SPEC_RESTO_DRUID }
+    fn name() -> &'static str { "restoration druid" }
+    fn needs() -> &'static str { "target" }
+
+    fn new(db: &SpellDb) -> Self {
+        Harmony { mastery_auras: db.with(SPEC_RESTO_DRUID, Interaction::Stack) }
+    }
+
+    fn effect(&self, log: &Entry<'a>, auras: &AuraTracker<'a>, player_id: &'a str) -> Option<f64> {
+        match *log {
+            Entry::Heal { ref base, .. } => Some(self.stacks(auras, base.dst.id, player_id) as f64),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use {Entry, AuraTracker};
+use healer::{MasteryModel, HealerComputation, health_before};
+
+pub const SPEC_RESTO_SHAMAN: u32 = 264;
+
+/// Resto shaman mastery (Deep Healing), which heals more the lower the
+/// target's health was. The health before each heal is worked out from
+/// the health after it in the advanced logging fields.
+#[derive(Default, Debug, Clone)]
+pub struct DeepHealing;
+
+impl<'a> MasteryModel<'a> for DeepHealing {
+    fn spec() -> u32 { SPEC_RESTO_SHAMAN }
+    fn name() -> &'static str { "restoration shaman" }
+    fn needs() -> &'static str { "health" }
+
+    fn effect(&self, log: &Entry<'a>, _: &AuraTracker<'a>, _: &'a str) -> Option<f64> {
+        health_before(log).map(|before| 1. - before)
+    }
+}
+
+pub type RestoShamanComputation<'a> = HealerComputation<'a, DeepHealing>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Interner;
+    use healer::tests::analyze;
+
+    // a healing wave on the tank at 25% health, a riptide tick at 90.6%
+    // and a chain heal without advanced logging
+    static LOG: &str = r#"4/7 21:13:58.000  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:58.100  COMBATANT_INFO,Player-3674-0CCCCCCC,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,11234,2468,2468,2468,1918,264,(0,0,0,0,0,0,0),(0,0,0,0),[],[(147143,915,(),(),())],[]
+4/7 21:14:00.000  SPELL_HEAL,Player-3674-0CCCCCCC,"Shammy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,77472,"Healing Wave",0x8,Player-3674-0AAAAAAA,0000000000000000,1600000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,800000,0,0,nil
+4/7 21:14:01.000  SPELL_PERIODIC_HEAL,Player-3674-0CCCCCCC,"Shammy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,61295,"Riptide",0x8,Player-3674-0AAAAAAA,0000000000000000,3200000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,400000,100000,0,nil
+4/7 21:14:02.000  SPELL_HEAL,Player-3674-0CCCCCCC,"Shammy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,1064,"Chain Heal",0x8,0000000000000000,0000000000000000,0,0,0,0,0,0,0,0,0,0,0,200000,0,0,nil
+4/7 21:14:30.000  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    #[test]
+    fn deep_healing() {
+        let intern = Interner::default();
+        let comp = analyze::<DeepHealing>(&intern, LOG, 110);
+        assert_eq!(comp.total_healing(), 1300000);
+        // 3% per point of mastery, at 11234 / 400 + 8 points
+        let m = 0.03 * (11234. / 400. + 8.);
+        assert!((comp.mastery() - m).abs() < 1e-9);
+        let (wave, riptide) = (800000. / (1. + 0.75 * m), 300000. / (1. + 0.09375 * m));
+        let frac = (0.75 * wave + 0.09375 * riptide) / (wave + riptide);
+        assert!((comp.mastery_frac() - frac).abs() < 1e-5, "{} {}", comp.mastery_frac(), frac);
+        let report = format!("{}", comp);
+        assert!(report.contains(&format!("scale_mastery_added: {:.6};", (1100000. - wave - riptide) / 1300000.)), "{}", report);
+        assert!(report.contains("mastery healing: 1.000000; unknown health: 0.153846;"), "{}", report);
+    }
+}
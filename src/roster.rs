@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use chrono::Duration;
+
+use unit::{self, Guid};
+use Entry;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Class { Warrior, Paladin, Hunter, Rogue, Priest, DeathKnight, Shaman, Mage, Warlock, Monk, Druid, DemonHunter, Evoker }
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Role { Tank, Healer, Melee, Ranged }
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Spec {
+    /// The id COMBATANT_INFO uses
+    pub id: u32,
+    pub class: Class,
+    pub name: &'static str,
+    pub role: Role,
+}
+
+macro_rules! spec {
+    ($id:expr, $class:ident, $name:expr, $role:ident) => {
+        Spec { id: $id, class: Class::$class, name: $name, role: Role::$role }
+    }
+}
+
+pub static SPECS: &[Spec] = &[
+    spec!(71, Warrior, "Arms", Melee),
+    spec!(72, Warrior, "Fury", Melee),
+    spec!(73, Warrior, "Protection", Tank),
+    spec!(65, Paladin, "Holy", Healer),
+    spec!(66, Paladin, "Protection", Tank),
+    spec!(70, Paladin, "Retribution", Melee),
+    spec!(253, Hunter, "Beast Mastery", Ranged),
+    spec!(254, Hunter, "Marksmanship", Ranged),
+    spec!(255, Hunter, "Survival", Melee),
+    spec!(259, Rogue, "Assassination", Melee),
+    spec!(260, Rogue, "Outlaw", Melee),
+    spec!(261, Rogue, "Subtlety", Melee),
+    spec!(256, Priest, "Discipline", Healer),
+    spec!(257, Priest, "Holy", Healer),
+    spec!(258, Priest, "Shadow", Ranged),
+    spec!(250, DeathKnight, "Blood", Tank),
+    spec!(251, DeathKnight, "Frost", Melee),
+    spec!(252, DeathKnight, "Unholy", Melee),
+    spec!(262, Shaman, "Elemental", Ranged),
+    spec!(263, Shaman, "Enhancement", Melee),
+    spec!(264, Shaman, "Restoration", Healer),
+    spec!(62, Mage, "Arcane", Ranged),
+    spec!(63, Mage, "Fire", Ranged),
+    spec!(64, Mage, "Frost", Ranged),
+    spec!(265, Warlock, "Affliction", Ranged),
+    spec!(266, Warlock, "Demonology", Ranged),
+    spec!(267, Warlock, "Destruction", Ranged),
+    spec!(268, Monk, "Brewmaster", Tank),
+    spec!(269, Monk, "Windwalker", Melee),
+    spec!(270, Monk, "Mistweaver", Healer),
+    spec!(102, Druid, "Balance", Ranged),
+    spec!(103, Druid, "Feral", Melee),
+    spec!(104, Druid, "Guardian", Tank),
+    spec!(105, Druid, "Restoration", Healer),
+    spec!(577, DemonHunter, "Havoc", Melee),
+    spec!(581, DemonHunter, "Vengeance", Tank),
+    spec!(1467, Evoker, "Devastation", Ranged),
+    spec!(1468, Evoker, "Preservation", Healer),
+    spec!(1473, Evoker, "Augmentation", Ranged),
+    ];
+
+pub fn spec(id: u32) -> Option<&'static Spec> {
+    SPECS.iter().find(|s| s.id == id)
+}
+
+/// What's known about a player
+#[derive(Debug, Clone, PartialEq)]
+pub struct Player<'a> {
+    pub guid: &'a str,
+    /// "Name-Realm", or just the guid until they show up in an event
+    pub name: &'a str,
+    pub spec: Option<&'static Spec>,
+    /// Average equipped item level from COMBATANT_INFO, or the last
+    /// one seen in advanced logging
+    pub ilvl: Option<u32>,
+    pub pets: Vec<&'a str>,
+    // when the last COMBATANT_INFO was
+    info_ts: Option<Duration>,
+}
+
+impl<'a> Player<'a> {
+    pub fn short_name(&self) -> &'a str {
+        self.name.split('-').next().unwrap()
+    }
+    pub fn realm(&self) -> Option<&'a str> {
+        self.name.split_once('-').map(|x| x.1)
+    }
+    pub fn class(&self) -> Option<Class> {
+        self.spec.map(|s| s.class)
+    }
+    pub fn role(&self) -> Option<Role> {
+        self.spec.map(|s| s.role)
+    }
+}
+
+/// One equipped item from COMBATANT_INFO
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    pub id: u32,
+    pub ilvl: u32,
+    pub enchants: Vec<u32>,
+    pub bonuses: Vec<u32>,
+    pub gems: Vec<u32>,
+}
+
+/// The items in COMBATANT_INFO's gear list, which is
+/// "(item,ilvl,(enchants),(bonus ids),(gems)),..." in slot order,
+/// empty slots included
+pub fn parse_gear(gear: &str) -> Vec<Item> {
+    let mut items = vec![];
+    let mut depth = 0;
+    // each field as a list, so scalars and (..) groups are the same
+    let mut fields: Vec<Vec<u32>> = vec![];
+    let mut cur = String::new();
+    for c in gear.chars() {
+        if c == ',' || c == ')' {
+            if let (Ok(x), Some(field)) = (cur.parse(), fields.last_mut()) {
+                field.push(x);
+            }
+            cur.clear();
+        }
+        match c {
+            '(' => {
+                depth += 1;
+                if depth == 1 {
+                    fields = vec![vec![]];
+                }
+            },
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    let mut fields = fields.drain(..).map(|f| f.into_iter());
+                    let mut next = || fields.next().map(|f| f.collect()).unwrap_or(vec![]);
+                    let (id, ilvl) = (next(), next());
+                    items.push(Item {
+                        id: id.first().cloned().unwrap_or(0), ilvl: ilvl.first().cloned().unwrap_or(0),
+                        enchants: next(), bonuses: next(), gems: next(),
+                    });
+                }
+            },
+            ',' if depth == 1 => fields.push(vec![]),
+            ',' => (),
+            c if depth >= 1 => cur.push(c),
+            _ => (),
+        }
+    }
+    items
+}
+
+/// The average item level of the gear in COMBATANT_INFO, leaving out
+/// the shirt, tabard and empty slots
+pub fn gear_ilvl(gear: &str) -> Option<u32> {
+    // slots 3 and 18 are the shirt and tabard
+    let ilvls = parse_gear(gear).into_iter().enumerate()
+        .filter(|&(slot, ref item)| slot != 3 && slot != 18 && item.ilvl != 0)
+        .map(|(_, item)| item.ilvl)
+        .collect::<Vec<_>>();
+    if ilvls.is_empty() {
+        None
+    } else {
+        Some(ilvls.iter().sum::<u32>() / ilvls.len() as u32)
+    }
+}
+
+/// Everyone in the log, by guid. Built up from the events passed to
+/// `update`: COMBATANT_INFO for specs and item levels, and other
+/// events for names and pets.
+#[derive(Debug, Clone, Default)]
+pub struct Roster<'a> {
+    players: HashMap<&'a str, Player<'a>>,
+    // name -> guid
+    names: HashMap<&'a str, &'a str>,
+    encounter_start: Option<Duration>,
+}
+
+impl<'a> Roster<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Build a roster from a whole pass over a log
+    pub fn build<I: Iterator<Item=Entry<'a>>>(iter: I) -> Self {
+        let mut roster = Roster::new();
+        for log in iter {
+            roster.update(&log);
+        }
+        roster
+    }
+
+    fn player(&mut self, guid: &'a str) -> &mut Player<'a> {
+        self.players.entry(guid).or_insert(Player { guid, name: guid, spec: None, ilvl: None, pets: vec![], info_ts: None })
+    }
+
+    pub fn update(&mut self, log: &Entry<'a>) {
+        match *log {
+            Entry::Info { id, gear, ts, .. } => {
+                let spec = log.spec_id().and_then(spec);
+                let player = self.player(id);
+                player.spec = spec;
+                player.info_ts = Some(ts);
+                if let Some(ilvl) = gear_ilvl(gear) {
+                    player.ilvl = Some(ilvl);
+                }
+            },
+            Entry::EncounterStart { ts, .. } => self.encounter_start = Some(ts),
+            Entry::EncounterEnd { .. } => self.encounter_start = None,
+            _ => (),
+        }
+        if let Some(base) = log.base() {
+            for obj in &[base.src, base.dst] {
+                // warcraftlogs ids aren't guids, but they'll have had a COMBATANT_INFO
+                let is_player = match unit::parse_guid(obj.id) {
+                    Guid::Player { .. } => true,
+                    _ => self.players.contains_key(obj.id),
+                };
+                if is_player && obj.name != "nil" {
+                    self.player(obj.id).name = obj.name;
+                    self.names.insert(obj.name, obj.id);
+                }
+            }
+        }
+        let adv = match *log {
+            Entry::Heal { ref adv, .. } | Entry::Damage { ref adv, .. } => adv,
+            _ => return,
+        };
+        match unit::parse_guid(adv.id) {
+            Guid::Player { .. } => {
+                let player = self.player(adv.id);
+                if player.info_ts.is_none() && adv.ilvl != 0 {
+                    player.ilvl = Some(adv.ilvl);
+                }
+            },
+            Guid::Nil => (),
+            _ => if let Guid::Player { .. } = unit::parse_guid(adv.owner) {
+                let owner = self.player(adv.owner);
+                if !owner.pets.contains(&adv.id) {
+                    owner.pets.push(adv.id);
+                }
+            },
+        }
+    }
+
+    pub fn get(&self, guid: &str) -> Option<&Player<'a>> {
+        self.players.get(guid)
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&Player<'a>> {
+        self.names.get(name).and_then(|guid| self.players.get(guid))
+    }
+
+    /// The player a pet belongs to
+    pub fn owner(&self, pet: &str) -> Option<&Player<'a>> {
+        self.players.values().find(|p| p.pets.contains(&pet))
+    }
+
+    pub fn players(&self) -> Vec<&Player<'a>> {
+        self.players.values().collect()
+    }
+
+    pub fn with_spec(&self, class: Class, spec: &str) -> Vec<&Player<'a>> {
+        self.players.values().filter(|p| p.spec.map(|s| s.class == class && s.name == spec).unwrap_or(false)).collect()
+    }
+
+    pub fn with_role(&self, role: Role) -> Vec<&Player<'a>> {
+        self.players.values().filter(|p| p.role() == Some(role)).collect()
+    }
+
+    /// Players that got a COMBATANT_INFO at the start of the current
+    /// encounter
+    pub fn in_encounter(&self) -> Vec<&Player<'a>> {
+        match self.encounter_start {
+            Some(start) => self.players.values().filter(|p| p.info_ts.map(|t| t >= start).unwrap_or(false)).collect(),
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // from a 7.2 COMBATANT_INFO: head, neck (gem), shoulders, shirt,
+    // chest, then an empty slot
+    static GEAR: &str = "(147143,915,(),(3562,1497,3528),()),(134530,880,(5890),(1727,1492,1813),(130246)),\
+                                 (147149,915,(),(3562,1497,3528),()),(6096,1,(),(),()),(147148,930,(),(3563,1512,3337),()),\
+                                 (0,0,(),(),())";
+
+    #[test]
+    fn gear() {
+        let items = parse_gear(GEAR);
+        assert_eq!(items.len(), 6);
+        assert_eq!(items[0], Item { id: 147143, ilvl: 915, enchants: vec![], bonuses: vec![3562, 1497, 3528], gems: vec![] });
+        assert_eq!(items[1], Item { id: 134530, ilvl: 880, enchants: vec![5890], bonuses: vec![1727, 1492, 1813], gems: vec![130246] });
+        assert_eq!(items[5], Item { id: 0, ilvl: 0, enchants: vec![], bonuses: vec![], gems: vec![] });
+        assert!(parse_gear("").is_empty());
+    }
+
+    #[test]
+    fn average_ilvl() {
+        // the shirt and the empty slot don't count
+        assert_eq!(gear_ilvl(GEAR), Some((915 + 880 + 915 + 930) / 4));
+        assert_eq!(gear_ilvl("(0,0,(),(),())"), None);
+    }
+
+    static LOG: &str = r#"4/7 21:13:50.000  COMBATANT_INFO,Player-3674-0AAAAAAA,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,11234,2468,2468,2468,1918,250,(0,0,0,0,0,0,0),(0,0,0,0),[],[],[]
+4/7 21:13:58.000  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:58.100  COMBATANT_INFO,Player-3674-06D6B01E,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,11234,2468,2468,2468,1918,105,(18569,102351,0,197061,0,197073,155675),(0,0,0,0),[],[(147143,915,(),(),()),(134530,905,(),(),())],[]
+4/7 21:13:59.003  SPELL_HEAL,Player-3674-0BBBBBBB,"Healy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,8936,"Regrowth",0x8,Player-3674-0BBBBBBB,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,200000,50000,0,1
+4/7 21:14:00.000  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:14:20.500  SPELL_DAMAGE,Pet-0-3019-1676-22336-165189-0000123456,"Treant",0x1111,0x0,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,5176,"Wrath",0x8,Pet-0-3019-1676-22336-165189-0000123456,Player-3674-06D6B01E,89960000,100000000,0,0,0,0,0,0,6213.56,-842.09,0,12000,0,8,0,0,0,nil,nil,nil,nil
+"#;
+
+    const DRUID: &str = "Player-3674-06D6B01E";
+    const PET: &str = "Pet-0-3019-1676-22336-165189-0000123456";
+
+    fn names(players: Vec<&Player>) -> Vec<String> {
+        let mut ret = players.into_iter().map(|p| p.name.to_owned()).collect::<Vec<_>>();
+        ret.sort();
+        ret
+    }
+
+    #[test]
+    fn players_from_a_log() {
+        let intern = ::Interner::default();
+        let mut roster = Roster::build(::iter(&intern, LOG.as_bytes()));
+        assert_eq!(names(roster.players()), vec!["Druidy-Realm", "Healy-Realm", "Tanky-Realm"]);
+
+        let druid = roster.get(DRUID).unwrap();
+        assert_eq!(druid.spec, spec(105));
+        assert_eq!((druid.class(), druid.role()), (Some(Class::Druid), Some(Role::Healer)));
+        assert_eq!((druid.short_name(), druid.realm()), ("Druidy", Some("Realm")));
+        assert_eq!(druid.ilvl, Some(910));
+        // the pet comes from the owner in the advanced fields
+        assert_eq!(druid.pets, vec![PET]);
+        assert_eq!(roster.owner(PET).map(|p| p.guid), Some(DRUID));
+
+        let tank = roster.by_name("Tanky-Realm").unwrap();
+        assert_eq!(tank.guid, "Player-3674-0AAAAAAA");
+        assert_eq!(tank.spec.map(|s| s.name), Some("Blood"));
+        assert_eq!(tank.ilvl, None);
+        // no COMBATANT_INFO, so only what advanced logging says
+        let healer = roster.by_name("Healy-Realm").unwrap();
+        assert_eq!((healer.spec, healer.ilvl), (None, Some(920)));
+        assert!(roster.by_name("Treant").is_none());
+
+        assert_eq!(names(roster.with_role(Role::Tank)), vec!["Tanky-Realm"]);
+        assert_eq!(names(roster.with_role(Role::Healer)), vec!["Druidy-Realm"]);
+        assert_eq!(names(roster.with_spec(Class::Druid, "Restoration")), vec!["Druidy-Realm"]);
+        // the tank's info was from before the pull
+        assert_eq!(names(roster.in_encounter()), vec!["Druidy-Realm"]);
+        let end = ::iter(&intern, "4/7 21:15:00.999  ENCOUNTER_END,2032,\"Goroth\",16,20,1\n".as_bytes()).next().unwrap();
+        roster.update(&end);
+        assert!(roster.in_encounter().is_empty());
+    }
+}
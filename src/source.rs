@@ -0,0 +1,223 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Stdin};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use chrono::NaiveDateTime;
+
+use intern::Interner;
+use store::{self, StoreIter, read_store};
+use {Entry, Iter, iter};
+#[cfg(feature = "wcl")]
+use wcl;
+
+/// Somewhere a combat log can be read from: a local file, a binary
+/// log written by `StoreWriter`, stdin (either kind, read as it
+/// comes, so opening it a second time is an error), or a warcraftlogs
+/// report.
+#[derive(Debug, Clone)]
+pub enum EventSource {
+    File(PathBuf),
+    Store(PathBuf),
+    Stdin,
+    /// `report` or `report:fight`, with the api key to fetch it with
+    Wcl { log: String, api_key: String },
+}
+
+impl EventSource {
+    /// Pick a source from a command line argument: "-" is stdin,
+    /// anything else is a file path, or a warcraftlogs id if an api
+    /// key was given.
+    pub fn new(input: &str, api_key: Option<&str>) -> EventSource {
+        if let Some(key) = api_key {
+            EventSource::Wcl { log: input.to_owned(), api_key: key.to_owned() }
+        } else if input == "-" {
+            EventSource::Stdin
+        } else {
+            let mut magic = [0; 8];
+            let is_store = File::open(input).and_then(|mut f| f.read_exact(&mut magic)).is_ok() && &magic == store::MAGIC;
+            if is_store {
+                EventSource::Store(PathBuf::from(input))
+            } else {
+                EventSource::File(PathBuf::from(input))
+            }
+        }
+    }
+
+    /// Start a fresh pass over the source. If `actor` is given only
+    /// events cast by that name (plus everything that has no
+    /// source/dest, like encounter markers and COMBATANT_INFO, deaths,
+    /// and auras anyone puts on them) are returned. `skip_to_first_boss` drops everything
+    /// before the first ENCOUNTER_START.
+    pub fn open<'a>(&self, intern: &'a Interner, actor: Option<&str>, skip_to_first_boss: bool) -> io::Result<Events<'a>> {
+        let inner = match *self {
+            EventSource::File(ref path) =>
+                Inner::File(iter(intern, BufReader::new(File::open(path)?))),
+            EventSource::Store(ref path) =>
+                Inner::Store(read_store(intern, BufReader::new(File::open(path)?))?),
+            EventSource::Stdin => {
+                if STDIN_OPENED.swap(true, Ordering::SeqCst) {
+                    return Err(io::Error::other("stdin can only be read once, save the log to a file to read it again"));
+                }
+                let mut read = BufReader::new(io::stdin());
+                let is_store = read.fill_buf()?.starts_with(store::MAGIC);
+                if is_store {
+                    Inner::StdinStore(read_store(intern, read)?)
+                } else {
+                    Inner::Stdin(iter(intern, read))
+                }
+            },
+            #[cfg(feature = "wcl")]
+            EventSource::Wcl { ref log, ref api_key } => {
+                // wcl does the filtering server side
+                let iter = wcl::iter(intern, log, api_key, skip_to_first_boss, actor);
+                return Ok(Events { inner: Inner::Wcl(iter), actor: None, skipping: false });
+            },
+            #[cfg(not(feature = "wcl"))]
+            EventSource::Wcl { .. } =>
+                return Err(io::Error::other("built without warcraftlogs support")),
+        };
+        Ok(Events { inner, actor: actor.map(|x| x.to_owned()), skipping: skip_to_first_boss })
+    }
+}
+
+static STDIN_OPENED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+enum Inner<'a> {
+    File(Iter<'a, BufReader<File>>),
+    Store(StoreIter<'a, BufReader<File>>),
+    Stdin(Iter<'a, BufReader<Stdin>>),
+    StdinStore(StoreIter<'a, BufReader<Stdin>>),
+    #[cfg(feature = "wcl")]
+    Wcl(wcl::Iter<'a>),
+}
+
+/// One pass over an `EventSource`
+#[derive(Debug)]
+pub struct Events<'a> {
+    inner: Inner<'a>,
+    actor: Option<String>,
+    skipping: bool,
+}
+
+impl<'a> Events<'a> {
+    /// The time timestamps are relative to, if the source has one
+    /// (warcraftlogs reports don't)
+    pub fn start_time(&self) -> Option<NaiveDateTime> {
+        match self.inner {
+            Inner::File(ref i) => Some(i.start_time()),
+            Inner::Store(ref i) => Some(i.start_time()),
+            Inner::Stdin(ref i) => Some(i.start_time()),
+            Inner::StdinStore(ref i) => Some(i.start_time()),
+            #[cfg(feature = "wcl")]
+            Inner::Wcl(_) => None,
+        }
+    }
+
+    /// The line ending of a text log (see `Iter::line_ending`), "\n"
+    /// for the others
+    pub fn line_ending(&self) -> &'static str {
+        match self.inner {
+            Inner::File(ref i) => i.line_ending(),
+            Inner::Stdin(ref i) => i.line_ending(),
+            _ => "\n",
+        }
+    }
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Entry<'a>;
+    fn next(&mut self) -> Option<Entry<'a>> {
+        loop {
+            let log = match self.inner {
+                Inner::File(ref mut i) => i.next(),
+                Inner::Store(ref mut i) => i.next(),
+                Inner::Stdin(ref mut i) => i.next(),
+                Inner::StdinStore(ref mut i) => i.next(),
+                #[cfg(feature = "wcl")]
+                Inner::Wcl(ref mut i) => i.next(),
+            };
+            let log = log?;
+            if self.skipping {
+                if let Entry::EncounterStart { .. } = log {
+                    self.skipping = false;
+                } else {
+                    continue;
+                }
+            }
+            if let Some(ref actor) = self.actor {
+                let keep = match log {
+                    Entry::Died { .. } => true,
+                    // buffs like bloodlust change the actor's stats
+                    Entry::Aura { ref base, .. } => base.src.name == actor || base.dst.name == actor,
+                    _ => log.base().map(|b| b.src.name == actor).unwrap_or(true),
+                };
+                if !keep {
+                    continue;
+                }
+            }
+            return Some(log);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    static LOG: &str = r#"4/7 21:13:58.541  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:59.100  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:13:59.150  SPELL_AURA_APPLIED,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,17,"Power Word: Shield",0x2,BUFF,120000
+4/7 21:14:25.000  UNIT_DIED,0000000000000000,nil,0x80000000,0x80000000,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0
+4/7 21:15:00.999  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    #[test]
+    fn reopens_files() {
+        let path = env::temp_dir().join(format!("wow-combat-log-source-{}.txt", ::std::process::id()));
+        fs::write(&path, LOG).unwrap();
+        let intern = Interner::default();
+        let source = EventSource::new(path.to_str().unwrap(), None);
+        let all = source.open(&intern, None, false).unwrap().count();
+        let druid = source.open(&intern, Some("Druidy-Realm"), false).unwrap().count();
+        fs::remove_file(&path).unwrap();
+        assert_eq!((all, druid), (5, 4));
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        let intern = Interner::default();
+        let source = EventSource::File(env::temp_dir().join("wow-combat-log-missing.txt"));
+        assert_eq!(source.open(&intern, None, false).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn keeps_auras_on_the_actor() {
+        use {AuraTracker, Stat, StatTable, BuffTable};
+        let log = format!("{}{}", LOG, r#"4/7 21:15:10.000  SPELL_AURA_APPLIED,Player-3674-0CCCCCCC,"Shammy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,2825,"Bloodlust",0x8,BUFF
+4/7 21:15:10.000  SPELL_AURA_APPLIED,Player-3674-0CCCCCCC,"Shammy-Realm",0x511,0x0,Player-3674-0CCCCCCC,"Shammy-Realm",0x511,0x0,2825,"Bloodlust",0x8,BUFF
+4/7 21:15:11.000  SPELL_HEAL,Player-3674-0CCCCCCC,"Shammy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,77472,"Healing Wave",0x8,Player-3674-06D6B01E,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,800000,0,0,nil
+"#);
+        let path = env::temp_dir().join(format!("wow-combat-log-source-auras-{}.txt", ::std::process::id()));
+        fs::write(&path, log).unwrap();
+        let intern = Interner::default();
+        let source = EventSource::new(path.to_str().unwrap(), None);
+        let mut auras = AuraTracker::new();
+        let mut n = 0;
+        for log in source.open(&intern, Some("Druidy-Realm"), false).unwrap() {
+            auras.update(&log);
+            n += 1;
+        }
+        fs::remove_file(&path).unwrap();
+        // the shaman's own bloodlust and heal aren't the druid's
+        assert_eq!(n, 5);
+
+        // 10% haste from rating, and bloodlust's 30% on top
+        let buffs = auras.auras_on("Player-3674-06D6B01E").into_iter().map(|(_, spell, aura)| (spell, aura.stacks));
+        let (_, percent) = BuffTable::builtin().totals(Stat::Haste, buffs);
+        let haste = StatTable::builtin().level(110).unwrap().buffed_percent(Stat::Haste, 3750, &percent);
+        assert!((haste - 43.).abs() < 1e-9, "{}", haste);
+    }
+}
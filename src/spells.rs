@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use roster::{self, Class};
+
+static BUILTIN_SPELLS: &str = include_str!("../data/spells.txt");
+
+/// How a spell and its spec's mastery go together
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Interaction {
+    /// mastery makes it bigger
+    Mastery,
+    /// it counts as a mastery stack (resto druid)
+    Stack,
+    /// it only happens because of mastery, so it's all from it
+    Proc,
+    /// it plants a living seed when it crits, which keeps its mastery
+    Seed,
+}
+
+impl Interaction {
+    pub fn from_name(name: &str) -> Option<Interaction> {
+        match name {
+            "mastery" => Some(Interaction::Mastery),
+            "stack" => Some(Interaction::Stack),
+            "proc" => Some(Interaction::Proc),
+            "seed" => Some(Interaction::Seed),
+            _ => None,
+        }
+    }
+}
+
+/// What's known about one spell
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpellInfo {
+    pub id: u32,
+    pub name: String,
+    /// a short name for reports, or the name
+    pub short: String,
+    pub class: Class,
+    /// None if it's any spec of the class
+    pub spec: Option<u32>,
+    pub hot: bool,
+    pub cooldown: bool,
+    pub mastery: Vec<Interaction>,
+    /// anything else, for analyzers to look for
+    pub tags: Vec<String>,
+}
+
+impl SpellInfo {
+    /// Whether a player of `spec` can have it
+    pub fn for_spec(&self, spec: u32) -> bool {
+        match self.spec {
+            Some(s) => s == spec,
+            None => roster::spec(spec).map(|s| s.class == self.class).unwrap_or(false),
+        }
+    }
+
+    pub fn has(&self, interaction: Interaction) -> bool {
+        self.mastery.contains(&interaction)
+    }
+
+    pub fn tagged(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+fn invalid<E: Into<Box<dyn Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn class_from_name(name: &str) -> Option<Class> {
+    roster::SPECS.iter().map(|s| s.class)
+        .find(|c| format!("{:?}", c).to_lowercase() == name.replace('_', ""))
+}
+
+/// Healing spells and how they work, from a file like `data/spells.txt`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpellDb {
+    // in file order, which reports keep
+    spells: Vec<SpellInfo>,
+    index: HashMap<u32, usize>,
+}
+
+impl SpellDb {
+    pub fn builtin() -> Self {
+        let mut db = SpellDb::default();
+        db.parse(BUILTIN_SPELLS.as_bytes()).unwrap();
+        db
+    }
+
+    /// The built-in database with the spells in `overrides` (if any)
+    /// added or replaced
+    pub fn with_overrides(overrides: Option<&Path>) -> io::Result<Self> {
+        let mut db = SpellDb::builtin();
+        if let Some(path) = overrides {
+            db.load(path)?;
+        }
+        Ok(db)
+    }
+
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        self.parse(BufReader::new(File::open(path)?))
+    }
+
+    /// Read spells into the database, replacing any with the same id;
+    /// "<id> remove" takes one out
+    pub fn parse<R: BufRead>(&mut self, read: R) -> io::Result<()> {
+        for (n, line) in read.lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap();
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if fields.is_empty() {
+                continue;
+            }
+            let err = |what: &str| invalid(format!("line {}: {}", n + 1, what));
+            let id = fields[0].parse().map_err(|_| err("bad spell id"))?;
+            if fields.len() == 2 && fields[1] == "remove" {
+                self.remove(id);
+                continue;
+            }
+            if fields.len() < 5 {
+                return Err(err("expected <id> <class> <spec> <flags> <name>"));
+            }
+            let class = class_from_name(fields[1]).ok_or_else(|| err("unknown class"))?;
+            let spec = match fields[2] {
+                "-" => None,
+                x => Some(x.parse().map_err(|_| err("bad spec id"))?),
+            };
+            let name = fields[4..].join(" ");
+            let mut spell = SpellInfo {
+                id, short: name.clone(), name, class, spec,
+                hot: false, cooldown: false, mastery: vec![], tags: vec![],
+            };
+            for flag in fields[3].split(',').filter(|&f| f != "-") {
+                match flag {
+                    "hot" => spell.hot = true,
+                    "cooldown" => spell.cooldown = true,
+                    _ if flag.starts_with("short=") => spell.short = flag[6..].to_string(),
+                    _ => match Interaction::from_name(flag) {
+                        Some(i) => spell.mastery.push(i),
+                        None => spell.tags.push(flag.to_string()),
+                    },
+                }
+            }
+            self.insert(spell);
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, spell: SpellInfo) {
+        match self.index.get(&spell.id) {
+            Some(&i) => self.spells[i] = spell,
+            None => {
+                self.index.insert(spell.id, self.spells.len());
+                self.spells.push(spell);
+            },
+        }
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        if let Some(i) = self.index.remove(&id) {
+            self.spells.remove(i);
+            for j in self.index.values_mut() {
+                if *j > i {
+                    *j -= 1;
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&SpellInfo> {
+        self.index.get(&id).map(|&i| &self.spells[i])
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<'_, SpellInfo> {
+        self.spells.iter()
+    }
+
+    /// The ids of `spec`'s spells with `interaction`
+    pub fn with(&self, spec: u32, interaction: Interaction) -> Vec<u32> {
+        self.iter().filter(|s| s.for_spec(spec) && s.has(interaction)).map(|s| s.id).collect()
+    }
+
+    /// The ids of `spec`'s spells tagged `tag`
+    pub fn tagged(&self, spec: u32, tag: &str) -> Vec<u32> {
+        self.iter().filter(|s| s.for_spec(spec) && s.tagged(tag)).map(|s| s.id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> SpellDb {
+        let mut db = SpellDb::default();
+        db.parse(text.as_bytes()).unwrap();
+        db
+    }
+
+    #[test]
+    fn parses_flags() {
+        let db = parse("# comment\n\n774 druid 105 hot,mastery,stack,rejuv,short=Rejuv Rejuvenation # more\n22842 druid - mastery Frenzied Regeneration\n");
+        let rejuv = db.get(774).unwrap();
+        assert_eq!((&rejuv.name[..], &rejuv.short[..]), ("Rejuvenation", "Rejuv"));
+        assert_eq!((rejuv.class, rejuv.spec, rejuv.hot, rejuv.cooldown), (Class::Druid, Some(105), true, false));
+        assert_eq!(rejuv.mastery, vec![Interaction::Mastery, Interaction::Stack]);
+        assert!(rejuv.tagged("rejuv"));
+        let frenzied = db.get(22842).unwrap();
+        assert_eq!((frenzied.spec, &frenzied.short[..]), (None, "Frenzied Regeneration"));
+        assert!(frenzied.tags.is_empty());
+        // - is any spec of the class
+        assert_eq!(db.with(105, Interaction::Mastery), vec![774, 22842]);
+        assert_eq!(db.with(264, Interaction::Mastery), Vec::<u32>::new());
+        assert_eq!(db.tagged(105, "rejuv"), vec![774]);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        for line in &["x druid 105 - Name", "774 druid 105 -", "774 dwarf 105 - Name", "774 druid resto - Name"] {
+            assert!(SpellDb::default().parse(line.as_bytes()).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn overrides_and_removes() {
+        let mut db = parse("1 druid 105 mastery One\n2 druid 105 mastery Two\n3 druid 105 mastery Three\n4 druid 105 mastery Four\n");
+        db.parse("2 druid 105 proc New Two\n1 remove\n5 druid 105 mastery Five\n3 remove\n9 remove\n".as_bytes()).unwrap();
+        // replacements keep their place, and the rest stay in order
+        assert_eq!(db.iter().map(|s| s.id).collect::<Vec<_>>(), vec![2, 4, 5]);
+        assert_eq!(db.get(2).unwrap().name, "New Two");
+        assert_eq!(db.get(2).unwrap().mastery, vec![Interaction::Proc]);
+        assert!(db.get(1).is_none() && db.get(3).is_none());
+        // the index still points at the right spells
+        for id in &[2, 4, 5] {
+            assert_eq!(db.get(*id).unwrap().id, *id);
+        }
+        db.parse("4 druid 105 mastery Four again\n".as_bytes()).unwrap();
+        assert_eq!(db.get(4).unwrap().name, "Four again");
+        assert_eq!(db.iter().count(), 3);
+    }
+
+    #[test]
+    fn builtin_germination() {
+        let db = SpellDb::builtin();
+        assert_eq!(db.tagged(105, "rejuv"), vec![774, 155777]);
+        assert_eq!(db.tagged(105, "germination"), vec![155777]);
+    }
+}
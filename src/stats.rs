@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use roster::{Item, gear_ilvl};
+use Entry;
+
+static BUILTIN: &str = include_str!("../data/stats.txt");
+static BUILTIN_BUFFS: &str = include_str!("../data/buffs.txt");
+static BUILTIN_CRIT: &str = include_str!("../data/crit.txt");
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Stat { Crit, Haste, Mastery, Versatility, Intellect, Leech }
+
+impl Stat {
+    pub fn from_name(name: &str) -> Option<Stat> {
+        match name {
+            "crit" => Some(Stat::Crit),
+            "haste" => Some(Stat::Haste),
+            "mastery" => Some(Stat::Mastery),
+            "versatility" | "vers" => Some(Stat::Versatility),
+            "intellect" | "int" => Some(Stat::Intellect),
+            "leech" => Some(Stat::Leech),
+            _ => None,
+        }
+    }
+}
+
+/// Rating conversions at one character level
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LevelStats {
+    /// rating for 1%, or for 1 point of mastery
+    pub rating: HashMap<Stat, f64>,
+    /// % (or mastery points) a character has with no rating
+    pub base: HashMap<Stat, f64>,
+    /// Diminishing returns, as (%, multiplier): the part of the % from
+    /// rating above each threshold is scaled by its multiplier
+    pub dr: Vec<(f64, f64)>,
+    /// spec id -> % per mastery point
+    pub mastery: HashMap<u32, f64>,
+    /// the (lowest, highest) average item level of characters at this
+    /// level, to tell a player's level from their gear
+    pub ilvl: Option<(u32, u32)>,
+}
+
+impl LevelStats {
+    /// % (mastery points for mastery) from `rating`, including the
+    /// base value and diminishing returns
+    pub fn percent(&self, stat: Stat, rating: u32) -> f64 {
+        let raw = rating as f64 / self.rating.get(&stat).cloned().unwrap_or(f64::INFINITY);
+        // each bracket runs up to the next threshold
+        let mut value = raw.min(self.dr.first().map(|x| x.0).unwrap_or(raw));
+        for (i, &(from, mult)) in self.dr.iter().enumerate() {
+            let to = self.dr.get(i + 1).map(|x| x.0).unwrap_or(raw).min(raw);
+            if to > from {
+                value += (to - from) * mult;
+            }
+        }
+        value + self.base.get(&stat).cloned().unwrap_or(0.)
+    }
+
+    /// A spec's mastery %, or None if its coefficient isn't known
+    pub fn mastery_percent(&self, spec: u32, rating: u32) -> Option<f64> {
+        self.mastery.get(&spec).map(|coef| coef * self.percent(Stat::Mastery, rating))
+    }
+
+    /// `percent` with % buffs on top. Haste buffs multiply, the rest
+    /// add.
+    pub fn buffed_percent(&self, stat: Stat, rating: u32, buffs: &[f64]) -> f64 {
+        let percent = self.percent(stat, rating);
+        if stat == Stat::Haste {
+            buffs.iter().fold(1. + percent / 100., |h, b| h * (1. + b / 100.)) * 100. - 100.
+        } else {
+            percent + buffs.iter().sum::<f64>()
+        }
+    }
+
+    /// How much `buffed_percent` goes up per point of rating, at
+    /// `rating`
+    pub fn marginal(&self, stat: Stat, rating: u32, buffs: &[f64]) -> f64 {
+        // over 100 rating, so DR brackets don't make it jump about
+        (self.buffed_percent(stat, rating + 100, buffs) - self.buffed_percent(stat, rating, buffs)) / 100.
+    }
+}
+
+fn invalid<E: Into<Box<dyn Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Stat conversions for each level, from a file like `data/stats.txt`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatTable {
+    levels: HashMap<u32, LevelStats>,
+}
+
+impl StatTable {
+    /// The table shipped in `data/stats.txt`
+    pub fn builtin() -> Self {
+        StatTable::parse(BUILTIN.as_bytes()).unwrap()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        StatTable::parse(BufReader::new(File::open(path)?))
+    }
+
+    pub fn parse<R: BufRead>(read: R) -> io::Result<Self> {
+        let mut table = StatTable::default();
+        let mut level = None;
+        for (n, line) in read.lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap();
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if fields.is_empty() {
+                continue;
+            }
+            let err = |what: &str| invalid(format!("line {}: {}", n + 1, what));
+            let num = |s: &str| s.parse::<f64>().map_err(|_| err("bad number"));
+            let stat = |s: &str| Stat::from_name(s).ok_or_else(|| err("unknown stat"));
+            if fields[0] == "level" && fields.len() == 2 {
+                let l = fields[1].parse().map_err(|_| err("bad level"))?;
+                table.levels.entry(l).or_insert_with(LevelStats::default);
+                level = Some(l);
+                continue;
+            }
+            let stats = match level {
+                Some(l) => table.levels.get_mut(&l).unwrap(),
+                None => return Err(err("expected a level line first")),
+            };
+            match (fields[0], fields.len()) {
+                ("rating", 3) => { stats.rating.insert(stat(fields[1])?, num(fields[2])?); },
+                ("base", 3) => { stats.base.insert(stat(fields[1])?, num(fields[2])?); },
+                ("dr", 3) => {
+                    stats.dr.push((num(fields[1])?, num(fields[2])?));
+                    stats.dr.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                },
+                ("ilvl", 3) => {
+                    let ilvl = |s: &str| s.parse::<u32>().map_err(|_| err("bad item level"));
+                    stats.ilvl = Some((ilvl(fields[1])?, ilvl(fields[2])?));
+                },
+                ("spec", 4) if fields[2] == "mastery" => {
+                    let spec = fields[1].parse().map_err(|_| err("bad spec id"))?;
+                    stats.mastery.insert(spec, num(fields[3])?);
+                },
+                _ => return Err(err("unrecognized line")),
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn level(&self, level: u32) -> Option<&LevelStats> {
+        self.levels.get(&level)
+    }
+
+    /// The level of the player in COMBATANT_INFO `info`, from the item
+    /// level of their gear
+    pub fn level_of(&self, info: &Entry) -> Option<u32> {
+        let ilvl = match *info {
+            Entry::Info { gear, .. } => gear_ilvl(gear)?,
+            _ => return None,
+        };
+        self.levels.iter().filter(|&(_, l)| l.ilvl.map(|(lo, hi)| lo <= ilvl && ilvl <= hi).unwrap_or(false))
+            .map(|(&level, _)| level).next()
+    }
+
+    /// `LevelStats::percent` at `level`
+    pub fn percent(&self, level: u32, stat: Stat, rating: u32) -> Option<f64> {
+        self.level(level).map(|l| l.percent(stat, rating))
+    }
+
+    /// `LevelStats::mastery_percent` at `level`
+    pub fn mastery_percent(&self, level: u32, spec: u32, rating: u32) -> Option<f64> {
+        self.level(level).and_then(|l| l.mastery_percent(spec, rating))
+    }
+}
+
+/// One stat change from an aura, per stack
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatBuff {
+    pub stat: Stat,
+    pub amount: f64,
+    /// `amount` is a % (see `LevelStats::buffed_percent`) rather than
+    /// rating, or a % of intellect
+    pub percent: bool,
+}
+
+/// The stat changes from procs, set bonuses, flasks, food etc by aura
+/// id, from a file like `data/buffs.txt`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BuffTable {
+    auras: HashMap<u32, Vec<StatBuff>>,
+}
+
+impl BuffTable {
+    /// The table shipped in `data/buffs.txt`
+    pub fn builtin() -> Self {
+        BuffTable::parse(BUILTIN_BUFFS.as_bytes()).unwrap()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        BuffTable::parse(BufReader::new(File::open(path)?))
+    }
+
+    pub fn parse<R: BufRead>(read: R) -> io::Result<Self> {
+        let mut table = BuffTable::default();
+        for (n, line) in read.lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap();
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if fields.is_empty() {
+                continue;
+            }
+            let err = |what: &str| invalid(format!("line {}: {}", n + 1, what));
+            if fields.len() != 3 {
+                return Err(err("expected <aura id> <stat> <amount>"));
+            }
+            let id = fields[0].parse().map_err(|_| err("bad aura id"))?;
+            let stat = Stat::from_name(fields[1]).ok_or_else(|| err("unknown stat"))?;
+            let percent = fields[2].ends_with('%');
+            let amount = fields[2].trim_end_matches('%').parse().map_err(|_| err("bad amount"))?;
+            table.auras.entry(id).or_insert_with(Vec::new).push(StatBuff { stat, amount, percent });
+        }
+        Ok(table)
+    }
+
+    pub fn get(&self, aura: u32) -> &[StatBuff] {
+        self.auras.get(&aura).map(|x| &x[..]).unwrap_or(&[])
+    }
+
+    pub fn contains(&self, aura: u32) -> bool {
+        self.auras.contains_key(&aura)
+    }
+
+    /// The flat rating and the % buffs to `stat` from `auras`, as
+    /// (aura id, stacks)
+    pub fn totals<I: IntoIterator<Item=(u32, u64)>>(&self, stat: Stat, auras: I) -> (f64, Vec<f64>) {
+        let mut flat = 0.;
+        let mut percent = vec![];
+        for (aura, stacks) in auras {
+            for buff in self.get(aura).iter().filter(|b| b.stat == stat) {
+                if buff.percent {
+                    percent.push(buff.amount * stacks as f64);
+                } else {
+                    flat += buff.amount * stacks as f64;
+                }
+            }
+        }
+        (flat, percent)
+    }
+
+    /// `rating` with the flat buffs from `auras` added, and intellect %
+    /// buffs applied
+    pub fn rating<I: IntoIterator<Item=(u32, u64)>>(&self, stat: Stat, rating: u32, auras: I) -> u32 {
+        let (flat, percent) = self.totals(stat, auras);
+        let mut rating = rating as f64 + flat;
+        if stat == Stat::Intellect {
+            rating = percent.iter().fold(rating, |r, p| r * (1. + p / 100.));
+        }
+        rating.max(0.) as u32
+    }
+
+    /// The reverse of `rating`, for taking buffs back out of the stats
+    /// in COMBATANT_INFO
+    pub fn unbuffed<I: IntoIterator<Item=(u32, u64)>>(&self, stat: Stat, rating: u32, auras: I) -> u32 {
+        let (flat, percent) = self.totals(stat, auras);
+        let mut rating = rating as f64;
+        if stat == Stat::Intellect {
+            rating = percent.iter().fold(rating, |r, p| r / (1. + p / 100.));
+        }
+        (rating - flat).max(0.) as u32
+    }
+}
+
+/// How much bigger crit heals are, from race, gear and meta gems, from
+/// a file like `data/crit.txt`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CritTable {
+    pub base: f64,
+    pub races: HashMap<String, f64>,
+    /// racial aura id -> race
+    pub racials: HashMap<u32, String>,
+    pub items: HashMap<u32, f64>,
+    pub gems: HashMap<u32, f64>,
+}
+
+impl Default for CritTable {
+    fn default() -> Self {
+        CritTable { base: 2., races: HashMap::new(), racials: HashMap::new(), items: HashMap::new(), gems: HashMap::new() }
+    }
+}
+
+impl CritTable {
+    /// The table shipped in `data/crit.txt`
+    pub fn builtin() -> Self {
+        CritTable::parse(BUILTIN_CRIT.as_bytes()).unwrap()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        CritTable::parse(BufReader::new(File::open(path)?))
+    }
+
+    pub fn parse<R: BufRead>(read: R) -> io::Result<Self> {
+        let mut table = CritTable::default();
+        for (n, line) in read.lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap();
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if fields.is_empty() {
+                continue;
+            }
+            let err = |what: &str| invalid(format!("line {}: {}", n + 1, what));
+            let num = |s: &str| s.parse::<f64>().map_err(|_| err("bad number"));
+            let id = |s: &str| s.parse::<u32>().map_err(|_| err("bad id"));
+            match (fields[0], fields.len()) {
+                ("base", 2) => table.base = num(fields[1])?,
+                ("race", 3) => { table.races.insert(fields[1].to_owned(), num(fields[2])?); },
+                ("racial", 3) => { table.racials.insert(id(fields[1])?, fields[2].to_owned()); },
+                ("item", 3) => { table.items.insert(id(fields[1])?, num(fields[2])?); },
+                ("gem", 3) => { table.gems.insert(id(fields[1])?, num(fields[2])?); },
+                _ => return Err(err("unrecognized line")),
+            }
+        }
+        Ok(table)
+    }
+
+    /// The race shown by a racial passive in `auras`
+    pub fn race_from_auras(&self, auras: &[u32]) -> Option<&str> {
+        auras.iter().filter_map(|a| self.racials.get(a)).map(|r| &r[..]).next()
+    }
+
+    /// A crit heal's size relative to a normal one
+    pub fn multiplier(&self, race: Option<&str>, gear: &[Item]) -> f64 {
+        let mut mult = self.base;
+        if let Some(m) = race.and_then(|r| self.races.get(r)) {
+            mult *= m;
+        }
+        for item in gear {
+            mult *= self.items.get(&item.id).cloned().unwrap_or(1.);
+            for gem in &item.gems {
+                mult *= self.gems.get(gem).cloned().unwrap_or(1.);
+            }
+        }
+        mult
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use roster::parse_gear;
+
+    #[test]
+    fn buff_table() {
+        let table = BuffTable::parse("1 mastery 400\n1 haste 5%\n2 intellect 10%\n3 intellect 100\n4 leech -50\n".as_bytes()).unwrap();
+        assert_eq!(table.totals(Stat::Mastery, vec![(1, 1)]), (400., vec![]));
+        assert_eq!(table.totals(Stat::Haste, vec![(1, 1), (2, 1)]), (0., vec![5.]));
+        // flat and % buffs both scale with stacks
+        assert_eq!(table.totals(Stat::Mastery, vec![(1, 3), (9, 1)]), (1200., vec![]));
+        assert_eq!(table.totals(Stat::Haste, vec![(1, 2)]), (0., vec![10.]));
+        assert_eq!(table.totals(Stat::Crit, vec![(1, 1)]), (0., vec![]));
+
+        assert_eq!(table.rating(Stat::Mastery, 1000, vec![(1, 2)]), 1800);
+        assert_eq!(table.unbuffed(Stat::Mastery, 1800, vec![(1, 2)]), 1000);
+        // % buffs only change the rating for intellect, after the flat ones
+        assert_eq!(table.rating(Stat::Haste, 1000, vec![(1, 1)]), 1000);
+        assert_eq!(table.rating(Stat::Intellect, 1000, vec![(2, 1), (3, 2)]), 1320);
+        assert_eq!(table.rating(Stat::Intellect, 1000, vec![(2, 2)]), 1200);
+        for &auras in &[&[(2, 1), (3, 2)][..], &[(2, 2)], &[(3, 1)]] {
+            let buffed = table.rating(Stat::Intellect, 1000, auras.iter().cloned());
+            assert_eq!(table.unbuffed(Stat::Intellect, buffed, auras.iter().cloned()), 1000);
+        }
+        // never below zero
+        assert_eq!(table.rating(Stat::Leech, 20, vec![(4, 1)]), 0);
+        assert_eq!(table.unbuffed(Stat::Mastery, 100, vec![(1, 1)]), 0);
+
+        assert!(BuffTable::parse("1 mastery\n".as_bytes()).is_err());
+        assert!(BuffTable::parse("1 speed 10\n".as_bytes()).is_err());
+        assert!(BuffTable::parse("1 haste x%\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn level_from_gear() {
+        use {Interner, iter};
+        let table = StatTable::builtin();
+        let info = |ilvl: u32| format!("4/7 21:13:58.600  COMBATANT_INFO,Player-3674-06D6B01E,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,11234,2468,2468,2468,1918,105,(18569,102351,0,197061,0,197073,155675),(0,0,0,0),[],[(147143,{},(),(),()),(0,0,(),(),())],[]\n", ilvl);
+        let intern = Interner::default();
+        let level = |ilvl| table.level_of(&iter(&intern, info(ilvl).as_bytes()).next().unwrap());
+        assert_eq!(level(915), Some(110));
+        assert_eq!(level(400), Some(70));
+        assert_eq!(level(200), Some(60));
+        assert_eq!(level(5000), None);
+    }
+
+    #[test]
+    fn diminishing_returns() {
+        let table = StatTable::parse("level 1\nrating haste 10\nbase crit 5\ndr 30 0.5\ndr 40 0\n\nlevel 2\nrating haste 10\ndr 30 0.5\n".as_bytes()).unwrap();
+        let stats = table.level(1).unwrap();
+        assert_eq!(stats.percent(Stat::Haste, 200), 20.);
+        assert_eq!(stats.percent(Stat::Haste, 300), 30.);
+        assert_eq!(stats.percent(Stat::Haste, 350), 32.5);
+        assert_eq!(stats.percent(Stat::Haste, 400), 35.);
+        assert_eq!(stats.percent(Stat::Haste, 1000), 35.);
+        // the last bracket carries on forever
+        assert_eq!(table.percent(2, Stat::Haste, 500), Some(40.));
+        // no rating conversion means rating does nothing
+        assert_eq!(stats.percent(Stat::Crit, 1000), 5.);
+        assert_eq!(stats.percent(Stat::Mastery, 1000), 0.);
+        assert_eq!(stats.mastery_percent(105, 1000), None);
+
+        let legion = StatTable::builtin();
+        assert_eq!(legion.percent(110, Stat::Haste, 3750), Some(10.));
+        assert_eq!(legion.mastery_percent(110, 105, 4000), Some(0.6 * 18.));
+        assert!((legion.percent(60, Stat::Haste, 33 * 35).unwrap() - 34.5).abs() < 1e-9);
+        let level = legion.level(110).unwrap();
+        assert!((level.buffed_percent(Stat::Haste, 7500, &[30.]) - 56.).abs() < 1e-9);
+        assert!((level.buffed_percent(Stat::Crit, 4000, &[10.]) - 25.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn stat_table_errors() {
+        let err = |s: &str| StatTable::parse(s.as_bytes()).unwrap_err().to_string();
+        assert_eq!(err("rating haste 10\n"), "line 1: expected a level line first");
+        assert_eq!(err("level x\n"), "line 1: bad level");
+        assert_eq!(err("# comment\nlevel 1\nrating haste ten\n"), "line 3: bad number");
+        assert_eq!(err("level 1\ndr 30 x\n"), "line 2: bad number");
+        assert_eq!(err("level 1\nrating speed 10\n"), "line 2: unknown stat");
+        assert_eq!(err("level 1\nrating haste\n"), "line 2: unrecognized line");
+    }
+
+    #[test]
+    fn crit_table() {
+        let table = CritTable::parse("base 2\nrace Tauren 1.02 # comment\nracial 154743 Tauren\n\nitem 142170 1.05\ngem 41376 1.03\n".as_bytes()).unwrap();
+        assert_eq!(table.race_from_auras(&[1, 154743]), Some("Tauren"));
+        assert_eq!(table.race_from_auras(&[1]), None);
+
+        let plain = parse_gear("(147143,915,(),(3562,1497),()),(0,0,(),(),())");
+        assert_eq!(table.multiplier(None, &plain), 2.);
+        assert_eq!(table.multiplier(Some("Human"), &plain), 2.);
+        assert!((table.multiplier(Some("Tauren"), &plain) - 2.04).abs() < 1e-9);
+
+        // the drape, and a meta gem in the helm
+        let gear = parse_gear("(137000,850,(),(),(41376)),(0,0,(),(),()),(142170,880,(),(),())");
+        assert!((table.multiplier(None, &gear) - 2. * 1.03 * 1.05).abs() < 1e-9);
+
+        assert!(CritTable::parse("item 142170".as_bytes()).is_err());
+        assert!(CritTable::parse("gem x 1.03".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,486 @@
+//! A compact binary form of a parsed log, for analyzing the same log
+//! many times without parsing the text each time.
+//!
+//! The file is `MAGIC`, a u32 version and the log start time (i64
+//! milliseconds since the epoch), followed by records, each a tag
+//! byte and then fixed width little endian fields for that kind of
+//! entry. Strings are stored once in a `TAG_STRING` record when first
+//! used, and referred to by index after that. COMBATANT_INFO is the
+//! one variable width record, as it ends with its list of auras.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, Read, Write};
+use chrono::{Duration, NaiveDateTime};
+
+use intern::Interner;
+use {Entry, Object, BaseInfo, AdvancedInfo, AuraType, HealType, DamageType};
+
+pub const MAGIC: &[u8; 8] = b"WOWCLOG\0";
+pub const VERSION: u32 = 1;
+
+const TAG_STRING: u8 = 0;
+const TAG_AURA: u8 = 1;
+const TAG_HEAL: u8 = 2;
+const TAG_INFO: u8 = 3;
+const TAG_CHALLENGE_START: u8 = 4;
+const TAG_CHALLENGE_END: u8 = 5;
+const TAG_ENCOUNTER_START: u8 = 6;
+const TAG_ENCOUNTER_END: u8 = 7;
+const TAG_UNKNOWN: u8 = 8;
+const TAG_DAMAGE: u8 = 9;
+const TAG_DIED: u8 = 10;
+
+/// Writes entries in the binary format
+#[derive(Debug)]
+pub struct StoreWriter<W: Write> {
+    write: W,
+    strings: HashMap<String, u32>,
+}
+
+impl<W: Write> StoreWriter<W> {
+    pub fn new(mut write: W, start: NaiveDateTime) -> io::Result<Self> {
+        write.write_all(MAGIC)?;
+        write.write_all(&VERSION.to_le_bytes())?;
+        let start_ms = start.timestamp() * 1000 + start.timestamp_subsec_millis() as i64;
+        write.write_all(&start_ms.to_le_bytes())?;
+        Ok(StoreWriter { write, strings: HashMap::new() })
+    }
+
+    fn string(&mut self, s: &str) -> io::Result<u32> {
+        if let Some(&i) = self.strings.get(s) {
+            return Ok(i);
+        }
+        let i = self.strings.len() as u32;
+        self.write.write_all(&[TAG_STRING])?;
+        self.write.write_all(&(s.len() as u32).to_le_bytes())?;
+        self.write.write_all(s.as_bytes())?;
+        self.strings.insert(s.to_owned(), i);
+        Ok(i)
+    }
+
+    // Strings have to be defined before the record that uses them
+    // starts, so look them all up first and then write the record.
+    fn strings(&mut self, strs: &[&str]) -> io::Result<Vec<u32>> {
+        strs.iter().map(|s| self.string(s)).collect()
+    }
+
+    pub fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        use Entry::*;
+        let mut rec = Vec::with_capacity(160);
+        match *entry {
+            Aura { ty, ref base, id, aura, flags, buff, amount } => {
+                let s = self.strings(&[base.src.name, base.src.id, base.dst.name, base.dst.id, aura])?;
+                rec.push(TAG_AURA);
+                rec.push(match ty {
+                    AuraType::Apply => 0, AuraType::Refresh => 1, AuraType::Remove => 2,
+                    AuraType::Stack => 3, AuraType::Unstack => 4,
+                });
+                put_base(&mut rec, base, &s);
+                put_u32(&mut rec, id);
+                put_u32(&mut rec, s[4]);
+                rec.push(flags);
+                rec.push(buff as u8);
+                rec.push(amount.is_some() as u8);
+                put_u64(&mut rec, amount.unwrap_or(0));
+            },
+            Heal { ty, ref base, id, spell, flags, hp, maxhp, heal, overheal, absorbed, crit, ref adv } => {
+                let s = self.strings(&[base.src.name, base.src.id, base.dst.name, base.dst.id, spell, adv.id, adv.owner])?;
+                rec.push(TAG_HEAL);
+                rec.push(if ty == HealType::Heal { 0 } else { 1 });
+                put_base(&mut rec, base, &s);
+                put_u32(&mut rec, id);
+                put_u32(&mut rec, s[4]);
+                rec.push(flags);
+                for &x in &[hp, maxhp, heal, overheal, absorbed] {
+                    put_u64(&mut rec, x);
+                }
+                rec.push(crit as u8);
+                put_adv(&mut rec, adv, &s[5..]);
+            },
+            Damage { ty, ref base, id, spell, flags, hp, maxhp, amount, overkill, school, resisted, blocked, absorbed,
+                     crit, glancing, crushing, offhand, ref adv } => {
+                let s = self.strings(&[base.src.name, base.src.id, base.dst.name, base.dst.id, spell, adv.id, adv.owner])?;
+                rec.push(TAG_DAMAGE);
+                rec.push(match ty {
+                    DamageType::Spell => 0, DamageType::Periodic => 1, DamageType::Swing => 2, DamageType::Range => 3,
+                });
+                put_base(&mut rec, base, &s);
+                put_u32(&mut rec, id);
+                put_u32(&mut rec, s[4]);
+                rec.push(flags);
+                for &x in &[hp, maxhp, amount, overkill as u64] {
+                    put_u64(&mut rec, x);
+                }
+                rec.push(school);
+                for &x in &[resisted, blocked, absorbed] {
+                    put_u64(&mut rec, x);
+                }
+                for &x in &[crit, glancing, crushing, offhand] {
+                    rec.push(x as u8);
+                }
+                put_adv(&mut rec, adv, &s[5..]);
+            },
+            Died { ref base } => {
+                let s = self.strings(&[base.src.name, base.src.id, base.dst.name, base.dst.id])?;
+                rec.push(TAG_DIED);
+                put_base(&mut rec, base, &s);
+            },
+            Info { ts, id, strength, agi, sta, int, dodge, parry, block, critm, critr, crits, spd, steal,
+                   hastem, hastr, hastes, avd, mastery, versm, versr, verss, armor,
+                   talents, artifact, gear, ref auras } => {
+                let s = self.strings(&[id, talents, artifact, gear])?;
+                let aura_srcs = self.strings(&auras.iter().map(|a| a.0).collect::<Vec<_>>())?;
+                rec.push(TAG_INFO);
+                put_ts(&mut rec, ts);
+                put_u32(&mut rec, s[0]);
+                for &x in &[strength, agi, sta, int, dodge, parry, block, critm, critr, crits, spd, steal,
+                            hastem, hastr, hastes, avd, mastery, versm, versr, verss, armor] {
+                    put_u32(&mut rec, x);
+                }
+                put_u32(&mut rec, s[1]);
+                put_u32(&mut rec, s[2]);
+                put_u32(&mut rec, s[3]);
+                put_u32(&mut rec, auras.len() as u32);
+                for (&(_, aura), &src) in auras.iter().zip(aura_srcs.iter()) {
+                    put_u32(&mut rec, src);
+                    put_u32(&mut rec, aura);
+                }
+            },
+            ChallengeStart { ts, id, rest } | ChallengeEnd { ts, id, rest } => {
+                let s = self.string(rest)?;
+                rec.push(if let ChallengeStart { .. } = *entry { TAG_CHALLENGE_START } else { TAG_CHALLENGE_END });
+                put_ts(&mut rec, ts);
+                put_u32(&mut rec, id);
+                put_u32(&mut rec, s);
+            },
+            EncounterStart { ts, name, id, difficulty, size, instance } => {
+                let s = self.string(name)?;
+                rec.push(TAG_ENCOUNTER_START);
+                put_ts(&mut rec, ts);
+                put_u32(&mut rec, s);
+                put_u32(&mut rec, id);
+                put_u32(&mut rec, difficulty as u32);
+                put_u32(&mut rec, size);
+                rec.push(instance.is_some() as u8);
+                put_u32(&mut rec, instance.unwrap_or(0));
+            },
+            EncounterEnd { ts, name, id, difficulty, size, kill } => {
+                let s = self.string(name)?;
+                rec.push(TAG_ENCOUNTER_END);
+                put_ts(&mut rec, ts);
+                put_u32(&mut rec, s);
+                put_u32(&mut rec, id);
+                put_u32(&mut rec, difficulty as u32);
+                put_u32(&mut rec, size);
+                rec.push(kill as u8);
+            },
+            Unknown(ts, ty) => {
+                let s = self.string(ty)?;
+                rec.push(TAG_UNKNOWN);
+                put_ts(&mut rec, ts);
+                put_u32(&mut rec, s);
+            },
+        }
+        self.write.write_all(&rec)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.write
+    }
+}
+
+fn put_u32(rec: &mut Vec<u8>, x: u32) {
+    rec.extend_from_slice(&x.to_le_bytes());
+}
+
+fn put_u64(rec: &mut Vec<u8>, x: u64) {
+    rec.extend_from_slice(&x.to_le_bytes());
+}
+
+fn put_ts(rec: &mut Vec<u8>, ts: Duration) {
+    rec.extend_from_slice(&ts.num_milliseconds().to_le_bytes());
+}
+
+// s is [src name, src id, dst name, dst id, ..]
+fn put_base(rec: &mut Vec<u8>, base: &BaseInfo, s: &[u32]) {
+    put_ts(rec, base.timestamp);
+    put_u32(rec, s[0]);
+    put_u32(rec, s[1]);
+    put_u32(rec, base.src_flags1);
+    put_u32(rec, base.src_flags2);
+    put_u32(rec, s[2]);
+    put_u32(rec, s[3]);
+    put_u32(rec, base.dst_flags1);
+    put_u32(rec, base.dst_flags2);
+}
+
+// s is [id, owner]
+fn put_adv(rec: &mut Vec<u8>, adv: &AdvancedInfo, s: &[u32]) {
+    put_u32(rec, s[0]);
+    put_u32(rec, s[1]);
+    put_u64(rec, adv.ap);
+    put_u64(rec, adv.sp);
+    put_u32(rec, adv.power_type as u32);
+    put_u64(rec, adv.power);
+    put_u64(rec, adv.max_power);
+    put_u32(rec, adv.map_index as u32);
+    put_u32(rec, adv.x as u32);
+    put_u32(rec, adv.y as u32);
+    put_u32(rec, adv.ilvl);
+}
+
+fn invalid<E: Into<Box<dyn Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Reads a binary log back as entries
+#[derive(Debug)]
+pub struct StoreIter<'a, R: Read> {
+    intern: &'a Interner,
+    read: R,
+    start: NaiveDateTime,
+    strings: Vec<&'a str>,
+}
+
+/// Check the header of a binary log and start reading it
+pub fn read_store<R: Read>(intern: &Interner, mut read: R) -> io::Result<StoreIter<'_, R>> {
+    let mut magic = [0; 8];
+    read.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a binary combat log"));
+    }
+    let mut version = [0; 4];
+    read.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != VERSION {
+        return Err(invalid(format!("unsupported binary log version {}", version)));
+    }
+    let mut start = [0; 8];
+    read.read_exact(&mut start)?;
+    let start = i64::from_le_bytes(start);
+    let start = NaiveDateTime::from_timestamp(start.div_euclid(1000), (start.rem_euclid(1000) * 1_000_000) as u32);
+    Ok(StoreIter { intern, read, start, strings: vec![] })
+}
+
+impl<'a, R: Read> StoreIter<'a, R> {
+    /// The start time of the log the entries were read from
+    pub fn start_time(&self) -> NaiveDateTime {
+        self.start
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        let mut b = [0; 1];
+        self.read.read_exact(&mut b)?;
+        Ok(b[0])
+    }
+    fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut b = [0; 4];
+        self.read.read_exact(&mut b)?;
+        Ok(u32::from_le_bytes(b))
+    }
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut b = [0; 8];
+        self.read.read_exact(&mut b)?;
+        Ok(u64::from_le_bytes(b))
+    }
+    fn ts(&mut self) -> io::Result<Duration> {
+        Ok(Duration::milliseconds(self.u64()? as i64))
+    }
+    fn str(&mut self) -> io::Result<&'a str> {
+        let i = self.u32()? as usize;
+        self.strings.get(i).cloned().ok_or_else(|| invalid(format!("undefined string {}", i)))
+    }
+    fn base(&mut self) -> io::Result<BaseInfo<'a>> {
+        Ok(BaseInfo {
+            timestamp: self.ts()?,
+            src: Object { name: self.str()?, id: self.str()? },
+            src_flags1: self.u32()?, src_flags2: self.u32()?,
+            dst: Object { name: self.str()?, id: self.str()? },
+            dst_flags1: self.u32()?, dst_flags2: self.u32()?,
+        })
+    }
+    fn adv(&mut self) -> io::Result<AdvancedInfo<'a>> {
+        Ok(AdvancedInfo {
+            id: self.str()?, owner: self.str()?, ap: self.u64()?, sp: self.u64()?,
+            power_type: self.u32()? as i32, power: self.u64()?, max_power: self.u64()?,
+            map_index: self.u32()? as i32, x: self.u32()? as i32, y: self.u32()? as i32, ilvl: self.u32()?,
+        })
+    }
+
+    /// The next entry, or None at the end of the file. A file that ends
+    /// partway through a record gives an `UnexpectedEof` error, and one
+    /// that isn't a valid binary log an `InvalidData` error.
+    pub fn try_next(&mut self) -> io::Result<Option<Entry<'a>>> {
+        loop {
+            let mut tag = [0; 1];
+            if self.read.read(&mut tag)? == 0 {
+                return Ok(None);
+            }
+            let ret = match tag[0] {
+                TAG_STRING => {
+                    let len = self.u32()? as usize;
+                    let mut buf = vec![0; len];
+                    self.read.read_exact(&mut buf)?;
+                    let s = self.intern.intern(::std::str::from_utf8(&buf).map_err(invalid)?);
+                    self.strings.push(s);
+                    continue;
+                },
+                TAG_AURA => {
+                    let ty = match self.u8()? {
+                        0 => AuraType::Apply, 1 => AuraType::Refresh, 2 => AuraType::Remove,
+                        3 => AuraType::Stack, 4 => AuraType::Unstack,
+                        x => return Err(invalid(format!("bad aura type {}", x))),
+                    };
+                    let base = self.base()?;
+                    let id = self.u32()?;
+                    let aura = self.str()?;
+                    let flags = self.u8()?;
+                    let buff = self.bool()?;
+                    let has_amount = self.bool()?;
+                    let amount = self.u64()?;
+                    Entry::Aura { ty, base, id, aura, flags, buff,
+                                  amount: if has_amount { Some(amount) } else { None } }
+                },
+                TAG_HEAL => {
+                    let ty = if self.u8()? == 0 { HealType::Heal } else { HealType::Periodic };
+                    let base = self.base()?;
+                    Entry::Heal {
+                        ty, base, id: self.u32()?, spell: self.str()?, flags: self.u8()?,
+                        hp: self.u64()?, maxhp: self.u64()?, heal: self.u64()?, overheal: self.u64()?, absorbed: self.u64()?,
+                        crit: self.bool()?,
+                        adv: self.adv()?,
+                    }
+                },
+                TAG_DAMAGE => {
+                    let ty = match self.u8()? {
+                        0 => DamageType::Spell, 1 => DamageType::Periodic, 2 => DamageType::Swing, 3 => DamageType::Range,
+                        x => return Err(invalid(format!("bad damage type {}", x))),
+                    };
+                    let base = self.base()?;
+                    Entry::Damage {
+                        ty, base, id: self.u32()?, spell: self.str()?, flags: self.u8()?,
+                        hp: self.u64()?, maxhp: self.u64()?, amount: self.u64()?, overkill: self.u64()? as i64, school: self.u8()?,
+                        resisted: self.u64()?, blocked: self.u64()?, absorbed: self.u64()?,
+                        crit: self.bool()?, glancing: self.bool()?, crushing: self.bool()?, offhand: self.bool()?,
+                        adv: self.adv()?,
+                    }
+                },
+                TAG_DIED => Entry::Died { base: self.base()? },
+                TAG_INFO => {
+                    let ts = self.ts()?;
+                    let id = self.str()?;
+                    let mut stats = [0; 21];
+                    for x in stats.iter_mut() {
+                        *x = self.u32()?;
+                    }
+                    let (talents, artifact, gear) = (self.str()?, self.str()?, self.str()?);
+                    let n = self.u32()?;
+                    let auras = (0..n).map(|_| Ok((self.str()?, self.u32()?))).collect::<io::Result<_>>()?;
+                    Entry::Info {
+                        ts, id,
+                        strength: stats[0], agi: stats[1], sta: stats[2], int: stats[3], dodge: stats[4], parry: stats[5], block: stats[6],
+                        critm: stats[7], critr: stats[8], crits: stats[9], spd: stats[10], steal: stats[11],
+                        hastem: stats[12], hastr: stats[13], hastes: stats[14], avd: stats[15], mastery: stats[16],
+                        versm: stats[17], versr: stats[18], verss: stats[19], armor: stats[20],
+                        talents, artifact, gear,
+                        auras,
+                    }
+                },
+                TAG_CHALLENGE_START => Entry::ChallengeStart { ts: self.ts()?, id: self.u32()?, rest: self.str()? },
+                TAG_CHALLENGE_END => Entry::ChallengeEnd { ts: self.ts()?, id: self.u32()?, rest: self.str()? },
+                TAG_ENCOUNTER_START => {
+                    let (ts, name, id, difficulty, size) = (self.ts()?, self.str()?, self.u32()?, self.u32()? as u16, self.u32()?);
+                    let has_instance = self.bool()?;
+                    let instance = self.u32()?;
+                    Entry::EncounterStart { ts, name, id, difficulty, size,
+                                            instance: if has_instance { Some(instance) } else { None } }
+                },
+                TAG_ENCOUNTER_END => Entry::EncounterEnd {
+                    ts: self.ts()?, name: self.str()?, id: self.u32()?, difficulty: self.u32()? as u16, size: self.u32()?, kill: self.bool()?,
+                },
+                TAG_UNKNOWN => Entry::Unknown(self.ts()?, self.str()?),
+                x => return Err(invalid(format!("bad record tag {}", x))),
+            };
+            return Ok(Some(ret));
+        }
+    }
+}
+
+/// Panics on a truncated or corrupt file, as `Iter` does on a read
+/// error; use `try_next` to handle those.
+impl<'a, R: Read> Iterator for StoreIter<'a, R> {
+    type Item = Entry<'a>;
+    fn next(&mut self) -> Option<Entry<'a>> {
+        self.try_next().unwrap_or_else(|e| panic!("reading binary log: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iter;
+
+    static LOG: &str = r#"4/7 21:13:58.541  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:58.600  COMBATANT_INFO,Player-3674-06D6B01E,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,11234,2468,2468,2468,1918,105,(18569,102351,0,197061,0,197073,155675),(0,0,0,0),[(128306,1,(),(),())],[(147143,915,(),(3562,1497),())],[Player-3674-06D6B01E,774,Player-3674-06D6B01E,33763]
+4/7 21:13:59.003  SPELL_HEAL,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,8936,"Regrowth",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,200000,50000,0,1
+4/7 21:13:59.004  SPELL_PERIODIC_HEAL,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.05,-0.50,920,20000,0,0,nil
+4/7 21:13:59.100  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:13:59.200  SPELL_AURA_APPLIED_DOSE,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,242315,"Something",0x1,BUFF,3
+4/7 21:13:59.300  SPELL_AURA_REMOVED,0000000000000000,nil,0x80000000,0x80000000,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,1234,"Debuff",0x20,DEBUFF
+4/7 21:14:20.000  SPELL_DAMAGE,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,5176,"Solar Wrath",0x8,Creature-0-3019-1676-22336-115844-00006814F0,0000000000000000,90000000,100000000,0,0,0,0,0,0,6213.56,-842.09,0,40000,-1,8,0,0,0,1,nil,nil,nil
+4/7 21:14:21.000  SWING_DAMAGE,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,Creature-0-3019-1676-22336-115844-00006814F0,0000000000000000,90000000,100000000,0,0,0,0,0,0,6213.56,-842.09,0,300000,-1,1,0,50000,20000,nil,nil,nil,nil
+4/7 21:14:25.000  UNIT_DIED,0000000000000000,nil,0x80000000,0x80000000,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0
+4/7 21:14:30.000  SPELL_CAST_SUCCESS,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0
+4/7 21:15:00.999  ENCOUNTER_END,2032,"Goroth",16,20,1
+"#;
+
+    #[test]
+    fn store_matches_text() {
+        let intern = Interner::default();
+        let text = iter(&intern, LOG.as_bytes());
+        let start = text.start_time();
+        let entries = text.collect::<Vec<_>>();
+
+        let mut writer = StoreWriter::new(vec![], start).unwrap();
+        for e in &entries {
+            writer.write(e).unwrap();
+        }
+        let buf = writer.into_inner();
+
+        let intern2 = Interner::default();
+        let store = read_store(&intern2, &buf[..]).unwrap();
+        assert_eq!(store.start_time(), start);
+        assert_eq!(store.collect::<Vec<_>>(), entries);
+    }
+
+    #[test]
+    fn truncated_store_is_an_error() {
+        let intern = Interner::default();
+        let text = iter(&intern, LOG.as_bytes());
+        let mut writer = StoreWriter::new(vec![], text.start_time()).unwrap();
+        for e in text {
+            writer.write(&e).unwrap();
+        }
+        let buf = writer.into_inner();
+
+        let mut store = read_store(&intern, &buf[..buf.len() - 3]).unwrap();
+        let mut n = 0;
+        let err = loop {
+            match store.try_next() {
+                Ok(Some(_)) => n += 1,
+                Ok(None) => panic!("read to the end"),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(n, LOG.lines().count() - 1);
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(read_store(&intern, &buf[..10]).err().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+        let mut other = buf.clone();
+        other[8] = 2;
+        assert_eq!(read_store(&intern, &other[..]).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
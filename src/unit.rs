@@ -0,0 +1,98 @@
+// Decoding unit GUIDs and the flag fields that go with them
+
+/// The parts of a unit GUID
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Guid<'a> {
+    /// 0000000000000000, for events with no source or target
+    Nil,
+    Player { server: u32, id: &'a str },
+    /// Creature, Pet, Vehicle, GameObject, ...
+    Creature { kind: &'a str, server: u32, instance: u32, zone: u32, npc: u32, spawn: &'a str },
+    /// anything else (including the numeric ids wcl uses)
+    Other(&'a str),
+}
+
+pub fn parse_guid(guid: &str) -> Guid<'_> {
+    if guid == "0000000000000000" {
+        return Guid::Nil;
+    }
+    let parts = guid.split('-').collect::<Vec<_>>();
+    match parts.len() {
+        3 if parts[0] == "Player" => {
+            match parts[1].parse() {
+                Ok(server) => Guid::Player { server, id: parts[2] },
+                Err(_) => Guid::Other(guid),
+            }
+        },
+        7 => {
+            match (parts[2].parse(), parts[3].parse(), parts[4].parse(), parts[5].parse()) {
+                (Ok(server), Ok(instance), Ok(zone), Ok(npc)) =>
+                    Guid::Creature { kind: parts[0], server, instance, zone, npc, spawn: parts[6] },
+                _ => Guid::Other(guid),
+            }
+        },
+        _ => Guid::Other(guid),
+    }
+}
+
+// src_flags1/dst_flags1
+pub const AFFILIATION_MINE: u32 = 0x1;
+pub const AFFILIATION_PARTY: u32 = 0x2;
+pub const AFFILIATION_RAID: u32 = 0x4;
+pub const AFFILIATION_OUTSIDER: u32 = 0x8;
+pub const REACTION_FRIENDLY: u32 = 0x10;
+pub const REACTION_NEUTRAL: u32 = 0x20;
+pub const REACTION_HOSTILE: u32 = 0x40;
+pub const CONTROL_PLAYER: u32 = 0x100;
+pub const CONTROL_NPC: u32 = 0x200;
+pub const TYPE_PLAYER: u32 = 0x400;
+pub const TYPE_NPC: u32 = 0x800;
+pub const TYPE_PET: u32 = 0x1000;
+pub const TYPE_GUARDIAN: u32 = 0x2000;
+pub const TYPE_OBJECT: u32 = 0x4000;
+
+static UNIT_FLAGS: &[(u32, &str)] = &[
+    (AFFILIATION_MINE, "mine"),
+    (AFFILIATION_PARTY, "party"),
+    (AFFILIATION_RAID, "raid"),
+    (AFFILIATION_OUTSIDER, "outsider"),
+    (REACTION_FRIENDLY, "friendly"),
+    (REACTION_NEUTRAL, "neutral"),
+    (REACTION_HOSTILE, "hostile"),
+    (CONTROL_PLAYER, "player_controlled"),
+    (CONTROL_NPC, "npc_controlled"),
+    (TYPE_PLAYER, "player"),
+    (TYPE_NPC, "npc"),
+    (TYPE_PET, "pet"),
+    (TYPE_GUARDIAN, "guardian"),
+    (TYPE_OBJECT, "object"),
+    (0x10000, "target"),
+    (0x20000, "focus"),
+    (0x40000, "maintank"),
+    (0x80000, "mainassist"),
+    (0x80000000, "none"),
+    ];
+
+// src_flags2/dst_flags2
+static RAID_MARKERS: &[&str] = &[
+    "star", "circle", "diamond", "triangle", "moon", "square", "cross", "skull",
+    ];
+
+static SCHOOLS: &[&str] = &[
+    "physical", "holy", "fire", "nature", "frost", "shadow", "arcane",
+    ];
+
+/// Names of the bits set in a unit's flags
+pub fn unit_flag_names(flags: u32) -> Vec<&'static str> {
+    UNIT_FLAGS.iter().filter(|&&(bit, _)| flags & bit != 0).map(|&(_, name)| name).collect()
+}
+
+/// The raid target marker on a unit, from its second flags field
+pub fn raid_marker(flags2: u32) -> Option<&'static str> {
+    RAID_MARKERS.iter().enumerate().find(|&(i, _)| flags2 & (1 << i) != 0).map(|(_, &name)| name)
+}
+
+/// Names of the schools in a spell school mask
+pub fn school_names(school: u8) -> Vec<&'static str> {
+    SCHOOLS.iter().enumerate().filter(|&(i, _)| school & (1 << i) != 0).map(|(_, &name)| name).collect()
+}
@@ -8,7 +8,7 @@ use std::collections::HashMap;
 use std::str;
 use collect_tuple::OrPanic;
 
-use {Entry, Object, BaseInfo, AuraType, HealType};
+use {Entry, Object, BaseInfo, AdvancedInfo, AuraType, HealType, DamageType};
 
 #[derive(Debug)]
 pub struct Iter<'a> {
@@ -69,7 +69,9 @@ pub fn iter<'a>(intern: &'a Interner, log: &str, api_key: &str,
         // actorid does work, but then you can't get encounter info,
         // and I'd have to store that separately or something rather
         // than leaving them in the event stream
-        args.push(("filter", format!(r#"type="encounterstart" or type="encounterend" or type="combatantinfo" or source.name = "{}""#, name)));
+        //
+        // Auras others put on them are kept too, like EventSource does
+        args.push(("filter", format!(r#"type="encounterstart" or type="encounterend" or type="combatantinfo" or type="death" or source.name = "{0}" or (target.name = "{0}" and type in ("applybuff", "applybuffstack", "removebuff", "removebuffstack", "refreshbuff", "applydebuff", "applydebuffstack", "removedebuff", "removedebuffstack", "refreshdebuff"))"#, name)));
     }
     base_url = Url::parse_with_params(&base_url, &args)
         .unwrap().into_string();
@@ -141,6 +143,8 @@ impl<'a> Iter<'a> {
                     name: intern.intern(json["name"].as_str().unwrap()),
                     id: json["encounterID"].as_u32().unwrap(),
                     difficulty: json["difficulty"].as_u16().unwrap(),
+                    size: json["size"].as_u32().unwrap_or(0),
+                    instance: None,
                 },
             "encounterend" =>
                 Entry::EncounterEnd {
@@ -148,6 +152,7 @@ impl<'a> Iter<'a> {
                     name: intern.intern(json["name"].as_str().unwrap()),
                     id: json["encounterID"].as_u32().unwrap(),
                     difficulty: json["difficulty"].as_u16().unwrap(),
+                    size: json["size"].as_u32().unwrap_or(0),
                     kill: json["kill"] == true,
                 },
             "combatantinfo" =>
@@ -175,6 +180,8 @@ impl<'a> Iter<'a> {
                     versr: json["versatilityHealingDone"].as_u32().unwrap(),
                     verss: json["versatilityDamageReduction"].as_u32().unwrap(),
                     armor: json["armor"].as_u32().unwrap(),
+                    // only the spec is available
+                    talents: intern.intern(&json["specID"].to_string()), artifact: "", gear: "",
                     auras: json["auras"].members()
                         .map(|j| (intern.intern(&j["source"].to_string()),
                                   j["ability"].as_u32().unwrap())).collect(),
@@ -192,14 +199,62 @@ impl<'a> Iter<'a> {
                     maxhp: json["maxHitPoints"].as_u64().unwrap(),
                     heal: effective + overheal,
                     overheal: overheal,
-                    crit: json["hitType"] == 2
+                    absorbed: json["absorbed"].as_u64().unwrap_or(0),
+                    crit: json["hitType"] == 2,
+                    // wcl positions are already in hundredths
+                    adv: AdvancedInfo {
+                        ap: json["attackPower"].as_u64().unwrap_or(0),
+                        sp: json["spellPower"].as_u64().unwrap_or(0),
+                        x: json["x"].as_i32().unwrap_or(0),
+                        y: json["y"].as_i32().unwrap_or(0),
+                        ilvl: json["itemLevel"].as_u32().unwrap_or(0),
+                        ..Default::default()
+                    },
+                }
+            },
+            "death" => Entry::Died { base: self.parse_base(json) },
+            "damage" => {
+                let ability = &json["ability"];
+                let ty = if json["tick"] == true {
+                    DamageType::Periodic
+                } else if ability["guid"] == 1 {
+                    DamageType::Swing
+                } else {
+                    DamageType::Spell
+                };
+                Entry::Damage {
+                    ty,
+                    base: self.parse_base(json),
+                    id: ability["guid"].as_u32().unwrap(),
+                    spell: intern.intern(ability["name"].as_str().unwrap()),
+                    flags: ability["type"].as_u8().unwrap_or(0),
+                    hp: json["hitPoints"].as_u64().unwrap_or(0),
+                    maxhp: json["maxHitPoints"].as_u64().unwrap_or(0),
+                    amount: json["amount"].as_u64().unwrap(),
+                    overkill: json["overkill"].as_i64().unwrap_or(-1),
+                    school: ability["type"].as_u8().unwrap_or(0),
+                    resisted: json["resisted"].as_u64().unwrap_or(0),
+                    blocked: json["blocked"].as_u64().unwrap_or(0),
+                    absorbed: json["absorbed"].as_u64().unwrap_or(0),
+                    crit: json["hitType"] == 2,
+                    glancing: json["hitType"] == 6,
+                    crushing: json["hitType"] == 8,
+                    offhand: false,
+                    adv: AdvancedInfo {
+                        ap: json["attackPower"].as_u64().unwrap_or(0),
+                        sp: json["spellPower"].as_u64().unwrap_or(0),
+                        x: json["x"].as_i32().unwrap_or(0),
+                        y: json["y"].as_i32().unwrap_or(0),
+                        ilvl: json["itemLevel"].as_u32().unwrap_or(0),
+                        ..Default::default()
+                    },
                 }
             },
             "applybuff" | "removebuff" | "refreshbuff" | "applybuffstack" | "removebuffstack" |
             "applydebuff" | "removedebuff" | "refreshdebuff" | "applydebuffstack" | "removedebuffstack" => {
                 let buff = !ty.contains("debuff");
                 let ty = if ty.ends_with("stack") {
-                    AuraType::Stack
+                    if ty.starts_with("apply") { AuraType::Stack } else { AuraType::Unstack }
                 } else if ty.starts_with("apply") {
                     AuraType::Apply
                 } else if ty.starts_with("remove") {
@@ -214,6 +269,7 @@ impl<'a> Iter<'a> {
                     aura: intern.intern(json["ability"]["name"].as_str().unwrap()),
                     flags: 0,
                     buff: buff,
+                    amount: json["stack"].as_u64(),
                 }
             }
             _ => Entry::Unknown(ts, intern.intern(&json.to_string()))
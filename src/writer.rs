@@ -0,0 +1,225 @@
+use std::io::{self, Write};
+use chrono::{Datelike, NaiveDateTime};
+
+use {Entry, Object, BaseInfo, AdvancedInfo, DamageType};
+
+fn write_object<W: Write>(w: &mut W, obj: &Object, flags1: u32, flags2: u32) -> io::Result<()> {
+    // units with no name are logged as a bare nil
+    if obj.name == "nil" {
+        write!(w, "{},nil,0x{:x},0x{:x}", obj.id, flags1, flags2)
+    } else {
+        write!(w, "{},\"{}\",0x{:x},0x{:x}", obj.id, obj.name, flags1, flags2)
+    }
+}
+
+fn write_base<W: Write>(w: &mut W, base: &BaseInfo) -> io::Result<()> {
+    write_object(w, &base.src, base.src_flags1, base.src_flags2)?;
+    write!(w, ",")?;
+    write_object(w, &base.dst, base.dst_flags1, base.dst_flags2)
+}
+
+fn write_pos<W: Write>(w: &mut W, x: i32) -> io::Result<()> {
+    let sign = if x < 0 { "-" } else { "" };
+    write!(w, "{}{}.{:02}", sign, x.abs() / 100, x.abs() % 100)
+}
+
+fn write_adv<W: Write>(w: &mut W, adv: &AdvancedInfo, hp: u64, maxhp: u64) -> io::Result<()> {
+    write!(w, "{},{},{},{},{},{},{},{},{},{},",
+           adv.id, adv.owner, hp, maxhp, adv.ap, adv.sp,
+           adv.power_type, adv.power, adv.max_power, adv.map_index)?;
+    write_pos(w, adv.x)?;
+    write!(w, ",")?;
+    write_pos(w, adv.y)?;
+    write!(w, ",{}", adv.ilvl)
+}
+
+fn flag(x: bool) -> &'static str {
+    if x { "1" } else { "nil" }
+}
+
+/// Write `entry` as a single combat log line, with timestamps relative
+/// to `start` (see `Iter::start_time`). `Unknown` entries don't keep
+/// enough of the line to be written back, and give an `InvalidInput`
+/// error.
+///
+/// Advanced fields the parser only tolerates come back as they were
+/// parsed rather than as they were in the log: a list of powers
+/// (`0|3`) is written as 0, and positions are written with two decimal
+/// places.
+pub fn write_entry<W: Write>(w: &mut W, entry: &Entry, start: NaiveDateTime) -> io::Result<()> {
+    write_line(w, entry, start, "\n")
+}
+
+/// Like `write_entry`, ending the line with `eol` (see `Iter::line_ending`)
+pub fn write_line<W: Write>(w: &mut W, entry: &Entry, start: NaiveDateTime, eol: &str) -> io::Result<()> {
+    use Entry::*;
+    if let Unknown(_, ty) = *entry {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("can't write unknown event {}", ty)));
+    }
+
+    let ts = start + entry.timestamp();
+    write!(w, "{}/{} {}  {},", ts.month(), ts.day(), ts.format("%H:%M:%S%.3f"), entry.event_name())?;
+    match *entry {
+        Aura { ref base, id, aura, flags, buff, amount, .. } => {
+            write_base(w, base)?;
+            write!(w, ",{},\"{}\",0x{:x},{}", id, aura, flags, if buff { "BUFF" } else { "DEBUFF" })?;
+            if let Some(amount) = amount {
+                write!(w, ",{}", amount)?;
+            }
+        },
+        Heal { ref base, id, spell, flags, hp, maxhp, heal, overheal, absorbed, crit, ref adv, .. } => {
+            write_base(w, base)?;
+            write!(w, ",{},\"{}\",0x{:x},", id, spell, flags)?;
+            write_adv(w, adv, hp, maxhp)?;
+            write!(w, ",{},{},{},{}", heal, overheal, absorbed, flag(crit))?;
+        },
+        Damage { ty, ref base, id, spell, flags, hp, maxhp, amount, overkill, school, resisted, blocked, absorbed,
+                 crit, glancing, crushing, offhand, ref adv } => {
+            write_base(w, base)?;
+            if ty != DamageType::Swing {
+                write!(w, ",{},\"{}\",0x{:x}", id, spell, flags)?;
+            }
+            write!(w, ",")?;
+            write_adv(w, adv, hp, maxhp)?;
+            write!(w, ",{},{},{},{},{},{},{},{},{},{}", amount, overkill, school, resisted, blocked, absorbed,
+                   flag(crit), flag(glancing), flag(crushing), flag(offhand))?;
+        },
+        Died { ref base } => write_base(w, base)?,
+        Info { id, strength, agi, sta, int, dodge, parry, block, critm, critr, crits, spd, steal,
+               hastem, hastr, hastes, avd, mastery, versm, versr, verss, armor,
+               talents, artifact, gear, ref auras, .. } => {
+            write!(w, "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},",
+                   id, strength, agi, sta, int, dodge, parry, block, critm, critr, crits, spd, steal,
+                   hastem, hastr, hastes, avd, mastery, versm, versr, verss, armor)?;
+            write!(w, "{},[{}],[{}],[", talents, artifact, gear)?;
+            for (i, &(src, aura)) in auras.iter().enumerate() {
+                if i != 0 {
+                    write!(w, ",")?;
+                }
+                write!(w, "{},{}", src, aura)?;
+            }
+            write!(w, "]")?;
+        },
+        ChallengeStart { id, rest, .. } | ChallengeEnd { id, rest, .. } => write!(w, "{},{}", id, rest)?,
+        EncounterStart { name, id, difficulty, size, instance, .. } => {
+            write!(w, "{},\"{}\",{},{}", id, name, difficulty, size)?;
+            if let Some(instance) = instance {
+                write!(w, ",{}", instance)?;
+            }
+        },
+        EncounterEnd { name, id, difficulty, size, kill, .. } =>
+            write!(w, "{},\"{}\",{},{},{}", id, name, difficulty, size, if kill { 1 } else { 0 })?,
+        Unknown(..) => unreachable!(),
+    }
+    w.write_all(eol.as_bytes())
+}
+
+/// Writes entries back out as a combat log
+#[derive(Debug)]
+pub struct Writer<W: Write> {
+    write: W,
+    start: NaiveDateTime,
+    eol: &'static str,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(write: W, start: NaiveDateTime) -> Self {
+        Writer::with_line_ending(write, start, "\n")
+    }
+
+    pub fn with_line_ending(write: W, start: NaiveDateTime, eol: &'static str) -> Self {
+        Writer { write, start, eol }
+    }
+
+    pub fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        write_line(&mut self.write, entry, self.start, self.eol)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.write
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Interner, AuraType, iter};
+
+    // one of each event type the parser knows
+    static LOG: &str = r#"4/7 21:13:58.541  ENCOUNTER_START,2032,"Goroth",16,20,1676
+4/7 21:13:58.600  COMBATANT_INFO,Player-3674-06D6B01E,1085,6425,38235,33574,0,0,0,5762,5762,5762,0,0,4927,4927,4927,0,11234,2468,2468,2468,1918,105,(18569,102351,0,197061,0,197073,155675),(0,0,0,0),[(128306,1,(),(),())],[(147143,915,(),(3562,1497),()),(),(144258,940,(),(3459,1502),(130246))],[Player-3674-06D6B01E,774,Player-3674-06D6B01E,33763]
+4/7 21:13:59.003  SPELL_HEAL,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,8936,"Regrowth",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.90,1463.52,920,200000,50000,0,1
+4/7 21:13:59.004  SPELL_PERIODIC_HEAL,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,1,100,100,0,-3437.05,-0.50,920,20000,0,0,nil
+4/7 21:13:59.100  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:13:59.150  SPELL_AURA_APPLIED,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,17,"Power Word: Shield",0x2,BUFF,120000
+4/7 21:13:59.200  SPELL_AURA_APPLIED_DOSE,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,242315,"Something",0x1,BUFF,3
+4/7 21:13:59.250  SPELL_AURA_REMOVED_DOSE,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,242315,"Something",0x1,BUFF,2
+4/7 21:13:59.280  SPELL_AURA_REFRESH,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,774,"Rejuvenation",0x8,BUFF
+4/7 21:13:59.300  SPELL_AURA_REMOVED,0000000000000000,nil,0x80000000,0x80000000,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,1234,"Debuff",0x20,DEBUFF
+4/7 21:14:20.000  SPELL_DAMAGE,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,5176,"Solar Wrath",0x8,Creature-0-3019-1676-22336-115844-00006814F0,0000000000000000,90000000,100000000,0,0,0,0,0,0,6213.56,-842.09,0,40000,-1,8,0,0,0,1,nil,nil,nil
+4/7 21:14:20.500  SPELL_PERIODIC_DAMAGE,Player-3674-06D6B01E,"Druidy-Realm",0x511,0x0,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,8921,"Moonfire",0x40,Creature-0-3019-1676-22336-115844-00006814F0,0000000000000000,89960000,100000000,0,0,0,0,0,0,6213.56,-842.09,0,12000,0,64,0,0,0,nil,nil,nil,nil
+4/7 21:14:20.700  RANGE_DAMAGE,Player-3674-0BBBBBBB,"Hunty-Realm",0x512,0x0,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,75,"Auto Shot",0x1,Creature-0-3019-1676-22336-115844-00006814F0,0000000000000000,89950000,100000000,0,0,0,0,0,0,6213.56,-842.09,0,30000,-1,1,0,0,0,nil,nil,nil,nil
+4/7 21:14:21.000  SWING_DAMAGE,Creature-0-3019-1676-22336-115844-00006814F0,"Goroth",0x10a48,0x0,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0,Creature-0-3019-1676-22336-115844-00006814F0,0000000000000000,90000000,100000000,0,0,0,0,0,0,6213.56,-842.09,0,300000,-1,1,0,50000,20000,nil,nil,nil,1
+4/7 21:14:25.000  UNIT_DIED,0000000000000000,nil,0x80000000,0x80000000,Player-3674-0AAAAAAA,"Tanky-Realm",0x512,0x0
+4/7 21:15:00.999  ENCOUNTER_END,2032,"Goroth",16,20,1
+4/7 21:16:00.000  CHALLENGE_MODE_START,1571,210,10,[9,6,3]
+4/7 21:17:00.000  ENCOUNTER_START,1868,"Patrol Captain Gerdo",8,5
+4/7 21:17:30.000  ENCOUNTER_END,1868,"Patrol Captain Gerdo",8,5,0
+4/7 21:40:00.000  CHALLENGE_MODE_END,1571,1,10,1440000
+"#;
+
+    fn round_trip(log: &str) -> String {
+        let intern = Interner::default();
+        let entries = iter(&intern, log.as_bytes());
+        let mut writer = Writer::with_line_ending(vec![], entries.start_time(), entries.line_ending());
+        for e in entries {
+            writer.write(&e).unwrap();
+        }
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn writes_back_the_same_lines() {
+        assert_eq!(round_trip(LOG), LOG);
+    }
+
+    #[test]
+    fn tolerates_odd_advanced_fields() {
+        // positions without two decimal places, and a list of powers
+        let line = "4/7 21:13:59.003  SPELL_HEAL,Player-3674-06D6B01E,\"Druidy-Realm\",0x511,0x0,Player-3674-0AAAAAAA,\"Tanky-Realm\",0x512,0x0,8936,\"Regrowth\",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,0|3,100|5,100|5,0,-3437.905,1463,920,200000,50000,0,1\n";
+        let intern = Interner::default();
+        let e = iter(&intern, line.as_bytes()).next().unwrap();
+        let adv = *e.adv().unwrap();
+        assert_eq!((adv.x, adv.y, adv.power_type, adv.power), (-343791, 146300, 0, 0));
+        // they're written back as parsed, which then round trips
+        let written = "4/7 21:13:59.003  SPELL_HEAL,Player-3674-06D6B01E,\"Druidy-Realm\",0x511,0x0,Player-3674-0AAAAAAA,\"Tanky-Realm\",0x512,0x0,8936,\"Regrowth\",0x8,Player-3674-0AAAAAAA,0000000000000000,3000000,3200000,10000,45000,0,0,0,0,-3437.91,1463.00,920,200000,50000,0,1\n";
+        assert_eq!(round_trip(line), written);
+        assert_eq!(round_trip(written), written);
+    }
+
+    #[test]
+    fn doses_keep_their_direction() {
+        let intern = Interner::default();
+        let types = iter(&intern, LOG.as_bytes()).filter_map(|e| match e {
+            Entry::Aura { ty, amount: Some(amount), buff: true, id: 242315, .. } => Some((ty, amount)),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(types, vec![(AuraType::Stack, 3), (AuraType::Unstack, 2)]);
+    }
+
+    #[test]
+    fn unknown_events_are_an_error() {
+        let line = "4/7 21:13:59.003  SPELL_CAST_SUCCESS,Player-3674-06D6B01E,\"Druidy-Realm\",0x511,0x0,0000000000000000,nil,0x80000000,0x80000000,774,\"Rejuvenation\",0x8\n";
+        let intern = Interner::default();
+        let mut entries = iter(&intern, line.as_bytes());
+        let start = entries.start_time();
+        let e = entries.next().unwrap();
+        assert_eq!(write_entry(&mut vec![], &e, start).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn keeps_crlf() {
+        let log = LOG.replace("\n", "\r\n");
+        assert_eq!(round_trip(&log), log);
+    }
+}